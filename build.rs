@@ -0,0 +1,14 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/patlite.proto");
+        // protoc comes from protoc-bin-vendored so it doesn't have to be installed
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("No vendored protoc for this platform");
+        std::env::set_var("PROTOC", protoc);
+        tonic_prost_build::configure()
+            .build_client(false)
+            .compile_protos(&["proto/patlite.proto"], &["proto"])
+            .expect("Failed to compile proto/patlite.proto");
+    }
+}
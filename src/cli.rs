@@ -0,0 +1,20 @@
+#[cfg(feature = "remote")]
+pub mod access;
+pub mod completions;
+pub mod config;
+pub mod exec;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod output;
+#[cfg(feature = "remote")]
+pub mod remote;
+pub mod replay;
+#[cfg(feature = "remote")]
+pub mod serve;
+pub mod shell;
+pub mod state_file;
+pub mod watch_log;
+pub mod stream;
+pub mod tui;
+#[cfg(feature = "remote")]
+pub mod ui;
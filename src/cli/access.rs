@@ -0,0 +1,354 @@
+use super::config::{ApiToken, Scope};
+use patlite_rs::constants::{BUZZER_COUNT_KEEP, BUZZER_KEEP, BUZZER_OFF, BUZZER_VOLUME_KEEP, LED_COLOR_KEEP, LED_PATTERN_KEEP};
+use patlite_rs::DeviceCommand;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// The window buzzer commands are counted over
+const BUZZER_WINDOW: Duration = Duration::from_secs(60);
+
+// Whoever holds one of the daemon's tokens
+#[derive(Debug)]
+pub struct Client {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub buzzer_per_minute: Option<u32>,
+}
+
+impl Client {
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&Scope::Admin) || self.scopes.contains(&scope)
+    }
+
+    // The first of `scopes` the client lacks
+    pub fn missing(&self, scopes: &[Scope]) -> Option<Scope> {
+        scopes.iter().copied().find(|scope| !self.allows(*scope))
+    }
+}
+
+// Why the daemon turned a request down before it reached the device
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Refusal {
+    // The client's token doesn't have this scope
+    Scope(Scope),
+    // The client sounded the buzzer too often, it may again after this long
+    BuzzerLimit(Duration),
+}
+
+impl std::fmt::Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Refusal::Scope(scope) => write!(f, "The token doesn't have the {} scope", scope),
+            Refusal::BuzzerLimit(wait) => write!(f, "Too many buzzer commands, try again in {}s", wait.as_secs() + 1),
+        }
+    }
+}
+
+// Compare in constant time so a token can't be guessed a byte at a time
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+// The tokens of the daemon and the clients holding them
+pub struct Tokens {
+    clients: Vec<(String, Arc<Client>)>,
+}
+
+impl Tokens {
+    pub fn new(tokens: Vec<ApiToken>) -> Self {
+        let clients = tokens
+            .into_iter()
+            .map(|token| {
+                let client = Client {
+                    name: token.name,
+                    scopes: token.scopes,
+                    buzzer_per_minute: token.buzzer_per_minute,
+                };
+                (token.token, Arc::new(client))
+            })
+            .collect();
+        Tokens { clients }
+    }
+
+    // The client holding `given`. Every token is compared so the time taken doesn't tell which matched
+    pub fn client(&self, given: &str) -> Option<Arc<Client>> {
+        self.clients
+            .iter()
+            .fold(None, |found, (token, client)| if same_token(given, token) { Some(client.clone()) } else { found })
+    }
+}
+
+// The scopes a command needs, by the parts of the device it changes. Values the protocol keeps
+// as they are don't count, so a master command only setting the light only needs `light`
+pub fn required_scopes(command: &DeviceCommand) -> Vec<Scope> {
+    match command {
+        DeviceCommand::Master { color, color_pattern, buzzer_pattern, repetition, volume } => {
+            let mut scopes: Vec<Scope> = vec![];
+            if *color != LED_COLOR_KEEP || *color_pattern != LED_PATTERN_KEEP {
+                scopes.push(Scope::Light);
+            }
+            if *buzzer_pattern != BUZZER_KEEP || *repetition != BUZZER_COUNT_KEEP || *volume != BUZZER_VOLUME_KEEP {
+                scopes.push(Scope::Buzzer);
+            }
+            scopes
+        }
+        DeviceCommand::Light { .. } | DeviceCommand::Tiers { .. } => vec![Scope::Light],
+        DeviceCommand::Buzz { .. } | DeviceCommand::Volume { .. } => vec![Scope::Buzzer],
+        DeviceCommand::Off => vec![Scope::Light, Scope::Buzzer],
+        DeviceCommand::ConnectionDisplay { .. } => vec![Scope::Admin],
+    }
+}
+
+// Whether a command starts the buzzer. Only these are rate limited, silencing it never is
+pub fn sounds_buzzer(command: &DeviceCommand) -> bool {
+    let pattern: u8 = match command {
+        DeviceCommand::Master { buzzer_pattern, .. } => *buzzer_pattern,
+        DeviceCommand::Buzz { pattern, .. } => *pattern,
+        _ => return false,
+    };
+    pattern != BUZZER_OFF && pattern != BUZZER_KEEP
+}
+
+// Counts the buzzer commands of each client over the last minute
+pub struct BuzzerLimit {
+    per_minute: u32,
+    sent: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl BuzzerLimit {
+    // `per_minute` for clients without a limit of their own, 0 for no limit
+    pub fn new(per_minute: u32) -> Self {
+        BuzzerLimit {
+            per_minute,
+            sent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Count a buzzer command of `client`, or say how long it has to wait for the next one
+    pub fn take(&self, client: &Client) -> Result<(), Refusal> {
+        self.take_at(client, Instant::now())
+    }
+
+    fn take_at(&self, client: &Client, now: Instant) -> Result<(), Refusal> {
+        let limit: u32 = client.buzzer_per_minute.unwrap_or(self.per_minute);
+        if limit == 0 {
+            return Ok(());
+        }
+        let mut sent = self.sent.lock().unwrap_or_else(|e| e.into_inner());
+        let times: &mut VecDeque<Instant> = sent.entry(client.name.clone()).or_default();
+        while times.front().is_some_and(|at| now.duration_since(*at) >= BUZZER_WINDOW) {
+            times.pop_front();
+        }
+        if times.len() >= limit as usize {
+            let oldest: Instant = *times.front().expect("The limit is at least 1");
+            return Err(Refusal::BuzzerLimit(BUZZER_WINDOW - now.duration_since(oldest)));
+        }
+        times.push_back(now);
+        Ok(())
+    }
+}
+
+// One line of the audit log
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+    unix_ms: u128,
+    client: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command: Option<&'a DeviceCommand>,
+    // Raw frames from --remote, as hex
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frame: Option<&'a str>,
+    // "ok", the device's error, or why the daemon refused it
+    result: &'a str,
+}
+
+fn unix_ms() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis())
+}
+
+// Who changed what on the device, a line of JSON for every command and frame sent or refused
+pub struct AuditLog {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AuditLog {
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        AuditLog { out: Mutex::new(out) }
+    }
+
+    // Append to `path`, or write to stderr without one
+    pub fn open(path: Option<&Path>) -> io::Result<Self> {
+        let out: Box<dyn Write + Send> = match path {
+            Some(path) => {
+                let file: File = OpenOptions::new().create(true).append(true).open(path)?;
+                Box::new(file)
+            }
+            None => Box::new(io::stderr()),
+        };
+        Ok(AuditLog::new(out))
+    }
+
+    pub fn command(&self, client: &Client, command: &DeviceCommand, result: &str) {
+        self.write(AuditEntry {
+            unix_ms: unix_ms(),
+            client: &client.name,
+            command: Some(command),
+            frame: None,
+            result,
+        });
+    }
+
+    pub fn frame(&self, client: &Client, frame: &str, result: &str) {
+        self.write(AuditEntry {
+            unix_ms: unix_ms(),
+            client: &client.name,
+            command: None,
+            frame: Some(frame),
+            result,
+        });
+    }
+
+    fn write(&self, entry: AuditEntry) {
+        let Ok(line) = serde_json::to_string(&entry) else { return };
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        // A full disk shouldn't stop the device from being driven, the failure is only reported
+        if let Err(e) = writeln!(out, "{}", line).and_then(|_| out.flush()) {
+            eprintln!("Failed to write the audit log: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(scopes: Vec<Scope>, buzzer_per_minute: Option<u32>) -> Client {
+        Client {
+            name: "ci".to_string(),
+            scopes,
+            buzzer_per_minute,
+        }
+    }
+
+    fn master(color: u8, color_pattern: u8, buzzer_pattern: u8, repetition: u8, volume: u8) -> DeviceCommand {
+        DeviceCommand::Master { color, color_pattern, buzzer_pattern, repetition, volume }
+    }
+
+    fn buzz(pattern: u8) -> DeviceCommand {
+        DeviceCommand::Buzz { pattern, repetition: 1, volume: BUZZER_VOLUME_KEEP }
+    }
+
+    // The audit log written to a buffer the test keeps hold of
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap_or_else(|e| e.into_inner()).write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn only_known_tokens_have_a_client() {
+        let tokens: Tokens = Tokens::new(vec![ApiToken {
+            name: "ci".to_string(),
+            token: "secret".to_string(),
+            scopes: vec![Scope::Light],
+            buzzer_per_minute: None,
+        }]);
+        assert_eq!(tokens.client("secret").map(|client| client.name.clone()), Some("ci".to_string()));
+        assert!(tokens.client("secreT").is_none());
+        assert!(tokens.client("secret2").is_none());
+        assert!(tokens.client("").is_none());
+    }
+
+    #[test]
+    fn clients_miss_the_scopes_they_dont_have() {
+        let light: Client = client(vec![Scope::Light], None);
+        assert_eq!(light.missing(&[Scope::Light]), None);
+        assert_eq!(light.missing(&[Scope::Light, Scope::Buzzer]), Some(Scope::Buzzer));
+        assert_eq!(light.missing(&[Scope::ReadState]), Some(Scope::ReadState));
+        assert_eq!(client(vec![Scope::Admin], None).missing(&[Scope::ReadState, Scope::Buzzer]), None);
+    }
+
+    #[test]
+    fn commands_need_the_scopes_of_what_they_change() {
+        assert_eq!(required_scopes(&DeviceCommand::Tiers { tiers: vec![] }), vec![Scope::Light]);
+        assert_eq!(required_scopes(&DeviceCommand::Off), vec![Scope::Light, Scope::Buzzer]);
+        assert_eq!(required_scopes(&DeviceCommand::ConnectionDisplay { on: true }), vec![Scope::Admin]);
+
+        let keep_buzzer = |color: u8| master(color, 1, BUZZER_KEEP, BUZZER_COUNT_KEEP, BUZZER_VOLUME_KEEP);
+        assert_eq!(required_scopes(&keep_buzzer(1)), vec![Scope::Light]);
+        assert_eq!(required_scopes(&master(LED_COLOR_KEEP, LED_PATTERN_KEEP, 1, 1, 1)), vec![Scope::Buzzer]);
+        assert_eq!(required_scopes(&master(1, 1, 1, 1, 1)), vec![Scope::Light, Scope::Buzzer]);
+        // Changing the volume alone still touches the buzzer
+        let volume: DeviceCommand = master(LED_COLOR_KEEP, LED_PATTERN_KEEP, BUZZER_KEEP, BUZZER_COUNT_KEEP, 3);
+        assert_eq!(required_scopes(&volume), vec![Scope::Buzzer]);
+    }
+
+    #[test]
+    fn silencing_the_buzzer_doesnt_sound_it() {
+        assert!(sounds_buzzer(&buzz(1)));
+        assert!(sounds_buzzer(&master(0, 0, 2, 1, 1)));
+        assert!(!sounds_buzzer(&buzz(BUZZER_OFF)));
+        assert!(!sounds_buzzer(&buzz(BUZZER_KEEP)));
+        assert!(!sounds_buzzer(&master(1, 1, BUZZER_OFF, 0, 0)));
+        assert!(!sounds_buzzer(&DeviceCommand::Off));
+    }
+
+    #[test]
+    fn buzzer_limit_refills_after_the_window() {
+        let limit: BuzzerLimit = BuzzerLimit::new(2);
+        let ci: Client = client(vec![Scope::Buzzer], None);
+        let start: Instant = Instant::now();
+        assert_eq!(limit.take_at(&ci, start), Ok(()));
+        assert_eq!(limit.take_at(&ci, start + Duration::from_secs(10)), Ok(()));
+        assert_eq!(
+            limit.take_at(&ci, start + Duration::from_secs(20)),
+            Err(Refusal::BuzzerLimit(Duration::from_secs(40)))
+        );
+        // The first command has left the window, the second hasn't
+        assert_eq!(limit.take_at(&ci, start + BUZZER_WINDOW), Ok(()));
+        assert!(limit.take_at(&ci, start + BUZZER_WINDOW).is_err());
+
+        // Each client is counted on its own, and its own limit wins over the daemon's
+        let unlimited: Client = Client { name: "web".to_string(), ..client(vec![Scope::Buzzer], Some(0)) };
+        for _ in 0..5 {
+            assert_eq!(limit.take_at(&unlimited, start), Ok(()));
+        }
+    }
+
+    #[test]
+    fn audit_lines_name_the_client_and_result() {
+        let out: Shared = Shared::default();
+        let audit: AuditLog = AuditLog::new(Box::new(out.clone()));
+        let ci: Client = client(vec![Scope::Light], None);
+        audit.command(&ci, &DeviceCommand::Light { color: 1, pattern: 2 }, "ok");
+        audit.frame(&ci, "00 00", "The token doesn't have the admin scope");
+
+        let written: Vec<u8> = out.0.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let lines: Vec<serde_json::Value> = String::from_utf8(written)
+            .expect("The audit log is UTF-8")
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("Audit lines are JSON"))
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0]["unix_ms"].as_u64().is_some_and(|ms| ms > 0));
+        assert_eq!(lines[0]["client"], "ci");
+        assert_eq!(lines[0]["command"], serde_json::json!({"command": "light", "color": 1, "pattern": 2}));
+        assert_eq!(lines[0]["result"], "ok");
+        assert!(lines[0].get("frame").is_none());
+        assert_eq!(lines[1]["frame"], "00 00");
+        assert_eq!(lines[1]["result"], "The token doesn't have the admin scope");
+        assert!(lines[1].get("command").is_none());
+    }
+}
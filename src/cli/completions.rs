@@ -0,0 +1,73 @@
+use super::config::Config;
+use clap::Command;
+use clap_complete::engine::{ArgValueCandidates, CompletionCandidate};
+use clap_complete::env::Shells;
+use patlite_rs::list_devices;
+use std::io::{self, Write};
+use std::path::Path;
+
+// Environment variable the generated scripts set when calling back into the binary to complete
+pub const COMPLETE_VAR: &str = "COMPLETE";
+
+pub const SHELLS: [&str; 5] = ["bash", "elvish", "fish", "powershell", "zsh"];
+
+// Complete the names in `table`, showing the value each one stands for
+pub fn name_candidates(table: &'static [(&'static str, u8)]) -> ArgValueCandidates {
+    ArgValueCandidates::new(move || {
+        table
+            .iter()
+            .map(|(name, value)| CompletionCandidate::new(name).help(Some(value.to_string().into())))
+            .collect()
+    })
+}
+
+// Complete the serial numbers of connected devices and the configured aliases
+pub fn device_candidates() -> ArgValueCandidates {
+    ArgValueCandidates::new(|| {
+        let mut candidates: Vec<CompletionCandidate> = list_devices()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|d| d.serial_number)
+            .map(|serial| CompletionCandidate::new(serial).help(Some("connected".into())))
+            .collect();
+        if let Ok(config) = Config::load(None, None) {
+            candidates.extend(config.devices.into_iter().map(|(alias, serial)| {
+                CompletionCandidate::new(alias).help(Some(serial.into()))
+            }));
+        }
+        candidates
+    })
+}
+
+pub fn preset_candidates() -> ArgValueCandidates {
+    ArgValueCandidates::new(|| {
+        Config::load(None, None)
+            .map(|config| {
+                config
+                    .presets
+                    .into_iter()
+                    .map(|(name, preset)| CompletionCandidate::new(name).help(Some(preset.action.into())))
+                    .collect()
+            })
+            .unwrap_or_default()
+    })
+}
+
+// Write the script that registers completions for `shell`. The script calls the binary back with
+// `COMPLETE` set, so connected devices and presets are completed as they are at the time
+pub fn write_registration(cmd: &Command, shell: &str, out: &mut dyn Write) -> io::Result<()> {
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(shell)
+        .ok_or_else(|| io::Error::other(format!("Unknown shell: {}", shell)))?;
+    let bin = cmd.get_name();
+    completer.write_registration(COMPLETE_VAR, bin, bin, bin, out)
+}
+
+// Write the man page for the whole command to `out`, or a page per subcommand into `dir`
+pub fn write_man(cmd: Command, dir: Option<&Path>, out: &mut dyn Write) -> io::Result<()> {
+    match dir {
+        Some(dir) => clap_mangen::generate_to(cmd, dir),
+        None => clap_mangen::Man::new(cmd).render(out),
+    }
+}
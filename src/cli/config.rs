@@ -0,0 +1,423 @@
+use patlite_rs::{parse_color, parse_volume, DeviceCommand, DeviceProfile, FrameFormat, ProfileRegistry};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Volume used by presets that buzz without `vol` when the config doesn't set `volume`
+const DEFAULT_PRESET_VOLUME: u8 = 10;
+
+// The token for --remote and serve when --token isn't given
+pub const TOKEN_VAR: &str = "PATLITE_TOKEN";
+
+// Example config:
+//
+//   device = "team-tower"
+//   volume = 6
+//
+//   [presets]
+//   build-failed = "red pattern3 + strong-attention x3 vol 6"
+//   build-passed = "green"
+//
+//   [devices]
+//   team-tower = "SERIAL123"
+//
+//   [profiles.quiet]
+//   volume = "silent"
+//
+//   [models.NE-SN-USB-quiet]
+//   product_id = 0x6001
+//   buzzer = false
+//
+//   [tokens.dashboard]
+//   token = "..."
+//   scopes = ["read-state", "light"]
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum NameOrNumber {
+    Number(u8),
+    Name(String),
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ProfileFile {
+    device: Option<String>,
+    volume: Option<NameOrNumber>,
+    presets: BTreeMap<String, String>,
+    devices: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    device: Option<String>,
+    volume: Option<NameOrNumber>,
+    presets: BTreeMap<String, String>,
+    devices: BTreeMap<String, String>,
+    profiles: BTreeMap<String, ProfileFile>,
+    models: BTreeMap<String, ModelFile>,
+    tokens: BTreeMap<String, TokenFile>,
+}
+
+// What a token of `serve` lets its client do. Reading covers the state, events and device list,
+// light and buzzer the commands changing them, and admin everything including raw frames
+// from --remote and the device settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scope {
+    ReadState,
+    Light,
+    Buzzer,
+    Admin,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name: &str = match self {
+            Scope::ReadState => "read-state",
+            Scope::Light => "light",
+            Scope::Buzzer => "buzzer",
+            Scope::Admin => "admin",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokenFile {
+    token: String,
+    scopes: Vec<Scope>,
+    buzzer_per_minute: Option<u32>,
+}
+
+// A token `serve` accepts besides --token, named for the audit log
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "remote"), allow(dead_code))]
+pub struct ApiToken {
+    pub name: String,
+    pub token: String,
+    pub scopes: Vec<Scope>,
+    // Commands sounding the buzzer the client may send a minute, instead of serve's limit
+    pub buzzer_per_minute: Option<u32>,
+}
+
+// A device model missing from the built in profiles, e.g. a variant without a buzzer
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModelFile {
+    product_id: u16,
+    colors: Option<Vec<NameOrNumber>>,
+    tiers: Option<u8>,
+    buzzer: Option<bool>,
+    #[serde(default)]
+    frame_format: FrameFormat,
+}
+
+impl ModelFile {
+    fn to_profile(&self, name: &str) -> Result<DeviceProfile, String> {
+        let defaults: DeviceProfile = DeviceProfile::ne_usb();
+        // There's no tower to take the tiers from, so they're always given
+        let colors: Vec<u8> = match (&self.colors, self.frame_format) {
+            (Some(colors), _) => colors.iter().map(|c| c.parse(parse_color)).collect::<Result<_, _>>()?,
+            (None, FrameFormat::NeUsb) => defaults.colors,
+            (None, FrameFormat::LrUsb) => return Err(format!("Model {} needs the colors of its tiers, top first", name)),
+        };
+        let tiers: u8 = match self.frame_format {
+            FrameFormat::NeUsb => self.tiers.unwrap_or(defaults.tiers),
+            FrameFormat::LrUsb => self.tiers.unwrap_or(colors.len() as u8),
+        };
+        Ok(DeviceProfile {
+            name: name.to_string(),
+            product_id: self.product_id,
+            colors,
+            tiers,
+            buzzer: self.buzzer.unwrap_or(defaults.buzzer),
+            frame_format: self.frame_format,
+        })
+    }
+}
+
+impl NameOrNumber {
+    fn parse(&self, parse: fn(&str) -> Result<u8, String>) -> Result<u8, String> {
+        match self {
+            NameOrNumber::Number(n) => parse(&n.to_string()),
+            NameOrNumber::Name(name) => parse(name),
+        }
+    }
+}
+
+impl ConfigFile {
+    // Values set in `other` replace ours, its presets, aliases and profiles are added to ours
+    fn merge(&mut self, other: ConfigFile) {
+        self.merge_profile(ProfileFile {
+            device: other.device,
+            volume: other.volume,
+            presets: other.presets,
+            devices: other.devices,
+        });
+        self.profiles.extend(other.profiles);
+        self.models.extend(other.models);
+        self.tokens.extend(other.tokens);
+    }
+
+    fn merge_profile(&mut self, profile: ProfileFile) {
+        if profile.device.is_some() {
+            self.device = profile.device;
+        }
+        if profile.volume.is_some() {
+            self.volume = profile.volume;
+        }
+        self.presets.extend(profile.presets);
+        self.devices.extend(profile.devices);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Preset {
+    pub action: String,
+    pub command: DeviceCommand,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    // Device to use when `--device` isn't given, a serial number or an alias
+    pub device: Option<String>,
+    // Volume for commands that don't give one
+    pub volume: Option<u8>,
+    pub presets: BTreeMap<String, Preset>,
+    // Aliases for device serial numbers
+    pub devices: BTreeMap<String, String>,
+    // Built in device models and those added by the config
+    pub models: ProfileRegistry,
+    #[cfg_attr(not(feature = "remote"), allow(dead_code))]
+    pub tokens: Vec<ApiToken>,
+}
+
+pub fn user_config_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME") {
+        return Some(PathBuf::from(dir));
+    }
+    if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+    }
+}
+
+// Config files in the order they are read, later files override earlier ones
+pub fn config_paths() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = vec![];
+    if cfg!(unix) {
+        paths.push(PathBuf::from("/etc/patlite-rs/config.toml"));
+    }
+    if let Some(dir) = user_config_dir() {
+        paths.push(dir.join("patlite-rs").join("config.toml"));
+    }
+    paths
+}
+
+fn read_file(path: &Path) -> Result<ConfigFile, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    toml::from_str(&contents).map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+}
+
+impl Config {
+    // Load `path`, or the system and user config files when it is `None`, then apply `profile`.
+    // Every preset is parsed and validated so mistakes show up before anything is sent
+    pub fn load(path: Option<&Path>, profile: Option<&str>) -> Result<Config, String> {
+        let mut file = ConfigFile::default();
+        match path {
+            Some(path) => file = read_file(path)?,
+            None => {
+                for path in config_paths().iter().filter(|p| p.exists()) {
+                    file.merge(read_file(path)?);
+                }
+            }
+        }
+        Config::from_file(file, profile)
+    }
+
+    fn from_file(mut file: ConfigFile, profile: Option<&str>) -> Result<Config, String> {
+        if let Some(name) = profile {
+            let profile = file
+                .profiles
+                .remove(name)
+                .ok_or_else(|| format!("Unknown profile: {}", name))?;
+            file.merge_profile(profile);
+        }
+
+        let volume = match &file.volume {
+            Some(volume) => Some(volume.parse(parse_volume)?),
+            None => None,
+        };
+
+        let mut models = ProfileRegistry::default();
+        for (name, model) in &file.models {
+            if model.frame_format == FrameFormat::LrUsb {
+                eprintln!("Model {} uses the lr-usb frame layout, which hasn't been checked against a USB tower, try it with --dry-run first", name);
+            }
+            models.register(model.to_profile(name).map_err(|e| format!("Invalid model {}: {}", name, e))?);
+        }
+
+        let mut presets: BTreeMap<String, Preset> = BTreeMap::new();
+        for (name, action) in file.presets {
+            let command =
+                DeviceCommand::parse_action(&action, volume.unwrap_or(DEFAULT_PRESET_VOLUME))
+                    .and_then(|c| c.validate().map(|_| c))
+                    .map_err(|e| format!("Invalid preset {}: {}", name, e))?;
+            presets.insert(name, Preset { action, command });
+        }
+
+        let mut tokens: Vec<ApiToken> = vec![];
+        for (name, token) in file.tokens {
+            if token.token.is_empty() {
+                return Err(format!("Invalid token {}: the token is empty", name));
+            }
+            if tokens.iter().any(|other| other.token == token.token) {
+                return Err(format!("Invalid token {}: the same token is configured twice", name));
+            }
+            tokens.push(ApiToken {
+                name,
+                token: token.token,
+                scopes: token.scopes,
+                buzzer_per_minute: token.buzzer_per_minute,
+            });
+        }
+
+        Ok(Config {
+            device: file.device,
+            volume,
+            presets,
+            devices: file.devices,
+            models,
+            tokens,
+        })
+    }
+
+    // Serial number of the device to use, resolving aliases. `selector` from the command line
+    // wins over the configured default
+    pub fn serial(&self, selector: Option<&str>) -> Option<String> {
+        let selector = selector.or(self.device.as_deref())?;
+        Some(self.devices.get(selector).cloned().unwrap_or(selector.to_string()))
+    }
+
+    // The model called `name`, from the registry or LA6-PNS for units that aren't on USB
+    pub fn model(&self, name: &str) -> Result<DeviceProfile, String> {
+        let pns: DeviceProfile = DeviceProfile::pns();
+        let model: Option<DeviceProfile> = self
+            .models
+            .profiles()
+            .iter()
+            .chain([&pns])
+            .find(|p| p.name.eq_ignore_ascii_case(name))
+            .cloned();
+        model.ok_or_else(|| format!("Unknown model: {}", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use patlite_rs::constants::{BUZZER_STRONG_ATTENTION, LED_COLOR_RED, LED_PATTERN3};
+
+    fn config(toml: &str, profile: Option<&str>) -> Result<Config, String> {
+        Config::from_file(toml::from_str(toml).map_err(|e| e.to_string())?, profile)
+    }
+
+    fn config_error(toml: &str, profile: Option<&str>) -> String {
+        config(toml, profile).unwrap_err()
+    }
+
+    const EXAMPLE: &str = r#"
+        device = "team-tower"
+        volume = "max"
+
+        [presets]
+        build-failed = "red pattern3 + strong-attention x3 vol 6"
+        build-passed = "green"
+
+        [devices]
+        team-tower = "SERIAL123"
+
+        [profiles.quiet]
+        volume = "silent"
+        device = "SERIAL456"
+
+        [tokens.dashboard]
+        token = "secret"
+        scopes = ["read-state", "light"]
+    "#;
+
+    #[test]
+    fn example_config_loads() {
+        let config: Config = config(EXAMPLE, None).unwrap();
+        assert_eq!(config.volume, Some(10));
+        assert_eq!(config.presets["build-failed"].action, "red pattern3 + strong-attention x3 vol 6");
+        assert_eq!(
+            config.presets["build-failed"].command,
+            DeviceCommand::Master { color: LED_COLOR_RED, color_pattern: LED_PATTERN3, buzzer_pattern: BUZZER_STRONG_ATTENTION, repetition: 3, volume: 6 }
+        );
+        assert_eq!(config.tokens[0].name, "dashboard");
+        assert_eq!(config.tokens[0].scopes, [Scope::ReadState, Scope::Light]);
+    }
+
+    #[test]
+    fn profile_overrides_the_top_level() {
+        let config: Config = config(EXAMPLE, Some("quiet")).unwrap();
+        assert_eq!(config.volume, Some(0));
+        assert_eq!(config.serial(None).as_deref(), Some("SERIAL456"));
+        assert_eq!(config_error(EXAMPLE, Some("loud")), "Unknown profile: loud");
+    }
+
+    #[test]
+    fn presets_are_validated_at_load() {
+        assert_eq!(
+            config_error("[presets]\nbroken = \"chartreuse\"", None),
+            "Invalid preset broken: Unknown color: chartreuse (expected 0-7 or one of off, red, green, yellow, blue, purple, light-blue, white, keep)"
+        );
+        assert!(config_error("[presets]\nbroken = \"red + buzz x99\"", None).starts_with("Invalid preset broken: "));
+    }
+
+    #[test]
+    fn presets_without_a_volume_use_the_configured_one() {
+        let config: Config = config("volume = 3\n[presets]\nalarm = \"red + buzz\"", None).unwrap();
+        assert!(matches!(config.presets["alarm"].command, DeviceCommand::Master { volume: 3, .. }));
+    }
+
+    #[test]
+    fn aliases_resolve_to_serials() {
+        let config: Config = config(EXAMPLE, None).unwrap();
+        assert_eq!(config.serial(None).as_deref(), Some("SERIAL123"));
+        assert_eq!(config.serial(Some("team-tower")).as_deref(), Some("SERIAL123"));
+        // Anything that isn't an alias is taken to be a serial number
+        assert_eq!(config.serial(Some("OTHER789")).as_deref(), Some("OTHER789"));
+        assert_eq!(Config::default().serial(None), None);
+    }
+
+    #[test]
+    fn tower_models_need_their_colors() {
+        let config: Config = config("[models.line-tower]\nproduct_id = 0x1234\nframe_format = \"lr-usb\"\ncolors = [\"red\", \"amber\", \"green\"]", None).unwrap();
+        let model: DeviceProfile = config.model("Line-Tower").unwrap();
+        assert_eq!((model.product_id, model.tiers, model.frame_format), (0x1234, 3, FrameFormat::LrUsb));
+        assert_eq!(
+            config_error("[models.line-tower]\nproduct_id = 0x1234\nframe_format = \"lr-usb\"", None),
+            "Invalid model line-tower: Model line-tower needs the colors of its tiers, top first"
+        );
+        assert_eq!(Config::default().model("line-tower").unwrap_err(), "Unknown model: line-tower");
+    }
+
+    #[test]
+    fn unknown_fields_and_duplicate_tokens_are_refused() {
+        assert!(config_error("colour = \"red\"", None).contains("unknown field `colour`"));
+        assert_eq!(
+            config_error("[tokens.a]\ntoken = \"t\"\nscopes = []\n[tokens.b]\ntoken = \"t\"\nscopes = []", None),
+            "Invalid token b: the same token is configured twice"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use patlite_rs::{set_master_controls_command, Transport};
+use rusb::Result;
+use std::process::{Child, Command, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Exit code used when the child is killed after its timeout, same as coreutils `timeout`
+pub const EXIT_CODE_TIMEOUT: i32 = 124;
+// Exit code used when the child could not be started, same as POSIX shells
+pub const EXIT_CODE_NOT_STARTED: i32 = 127;
+// How often the child is polled while a timeout is active
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// Added to the signal number for a child killed by a signal, same as POSIX shells
+#[cfg(unix)]
+const EXIT_CODE_SIGNAL_BASE: i32 = 128;
+
+// Light and buzzer settings shown for one stage of the child's lifetime
+pub struct ExecState {
+    pub color: u8,
+    pub pattern: u8,
+    pub buzzer_pattern: u8,
+    pub repetition: u8,
+    pub volume: u8,
+}
+
+pub struct ExecOptions {
+    pub running: ExecState,
+    pub success: ExecState,
+    pub failure: ExecState,
+    pub timeout: Option<Duration>,
+}
+
+// The code a shell would report for the child, 128 plus the signal when it was killed by one
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return EXIT_CODE_SIGNAL_BASE + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+enum Outcome {
+    Exited(ExitStatus),
+    TimedOut,
+}
+
+fn show_state(handle: &mut dyn Transport, state: &ExecState) -> Result<bool> {
+    set_master_controls_command(
+        handle,
+        &state.color,
+        &state.pattern,
+        &state.buzzer_pattern,
+        &state.repetition,
+        &state.volume,
+    )
+}
+
+fn wait_for_child(child: &mut Child, timeout: Option<Duration>) -> std::io::Result<Outcome> {
+    let timeout = match timeout {
+        Some(t) => t,
+        None => return child.wait().map(Outcome::Exited),
+    };
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Outcome::Exited(status));
+        }
+        if started.elapsed() >= timeout {
+            child.kill()?;
+            child.wait()?;
+            return Ok(Outcome::TimedOut);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// Run `command` while the light shows the running state, then show the success or failure
+// state depending on how it exited. Returns the exit code the CLI should exit with.
+pub fn run(
+    handle: &mut dyn Transport,
+    command: &[String],
+    options: &ExecOptions,
+) -> Result<i32> {
+    let (program, args) = command.split_first().expect("Command is required");
+
+    show_state(handle, &options.running)?;
+
+    let mut child = match Command::new(program).args(args).spawn() {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Failed to start {}: {}", program, e);
+            show_state(handle, &options.failure)?;
+            return Ok(EXIT_CODE_NOT_STARTED);
+        }
+    };
+
+    let code = match wait_for_child(&mut child, options.timeout) {
+        Ok(Outcome::Exited(status)) => exit_code(status),
+        Ok(Outcome::TimedOut) => {
+            eprintln!("{} timed out, killed it", program);
+            EXIT_CODE_TIMEOUT
+        }
+        Err(e) => {
+            eprintln!("Failed to wait on {}: {}", program, e);
+            1
+        }
+    };
+
+    if code == 0 {
+        show_state(handle, &options.success)?;
+    } else {
+        show_state(handle, &options.failure)?;
+    }
+
+    Ok(code)
+}
@@ -0,0 +1,313 @@
+use super::access::{Client, Refusal};
+use super::config::Scope;
+use super::serve::{Daemon, Failure};
+use patlite_rs::{
+    parse_buzzer_pattern, parse_color, parse_led_pattern, parse_repetition, parse_volume, DeviceCommand, DeviceState,
+    DeviceSummary, Step,
+};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_stream::wrappers::WatchStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Code, Request, Response, Status};
+
+mod proto {
+    tonic::include_proto!("patlite.v1");
+}
+
+use proto::patlite_server::{Patlite, PatliteServer};
+use proto::step::Command;
+use proto::{
+    BuzzRequest, CommandReply, Device, GetStateRequest, ListDevicesReply, ListDevicesRequest, OffRequest,
+    PlaySequenceRequest, SetLightRequest, SetVolumeRequest, WatchStateRequest,
+};
+
+// The gRPC status for a failure of the device, the name of the error as the message
+fn status(error: rusb::Error) -> Status {
+    let code: Code = match error {
+        rusb::Error::InvalidParam => Code::InvalidArgument,
+        rusb::Error::Timeout => Code::DeadlineExceeded,
+        rusb::Error::NoDevice | rusb::Error::NotFound | rusb::Error::Busy => Code::Unavailable,
+        rusb::Error::Access => Code::PermissionDenied,
+        rusb::Error::NotSupported => Code::FailedPrecondition,
+        _ => Code::Internal,
+    };
+    Status::new(code, format!("{:?}", error))
+}
+
+fn failed(failure: Failure) -> Status {
+    match failure {
+        Failure::Refused(refusal @ Refusal::Scope(_)) => Status::permission_denied(refusal.to_string()),
+        Failure::Refused(refusal @ Refusal::BuzzerLimit(_)) => Status::resource_exhausted(refusal.to_string()),
+        Failure::Device(error) => status(error),
+    }
+}
+
+// The client the interceptor found for the call, which needs `scope` when given
+fn client<T>(request: &Request<T>, scope: Option<Scope>) -> Result<Arc<Client>, Status> {
+    let client: Arc<Client> = request
+        .extensions()
+        .get::<Arc<Client>>()
+        .cloned()
+        .ok_or_else(|| Status::unauthenticated("Access"))?;
+    match scope.and_then(|scope| client.missing(&[scope])) {
+        Some(scope) => Err(Status::permission_denied(Refusal::Scope(scope).to_string())),
+        None => Ok(client),
+    }
+}
+
+// A name or number from a request, `default` when it was left empty
+fn value(given: &str, parse: fn(&str) -> Result<u8, String>, default: u8) -> Result<u8, Status> {
+    match given {
+        "" => Ok(default),
+        given => parse(given).map_err(Status::invalid_argument),
+    }
+}
+
+impl From<DeviceState> for proto::DeviceState {
+    fn from(state: DeviceState) -> Self {
+        proto::DeviceState {
+            color: state.color.into(),
+            pattern: state.pattern.into(),
+            buzzer_pattern: state.buzzer_pattern.into(),
+            repetition: state.repetition.into(),
+            volume: state.volume.into(),
+            connection_display: state.connection_display,
+        }
+    }
+}
+
+impl From<DeviceSummary> for Device {
+    fn from(device: DeviceSummary) -> Self {
+        Device {
+            bus: device.bus.into(),
+            address: device.address.into(),
+            vendor_id: device.vendor_id.into(),
+            product_id: device.product_id.into(),
+            model: device.model,
+            serial_number: device.serial_number,
+        }
+    }
+}
+
+struct Service {
+    daemon: Daemon,
+}
+
+impl Service {
+    fn light(&self, light: &SetLightRequest) -> Result<DeviceCommand, Status> {
+        Ok(DeviceCommand::Light {
+            color: value(&light.color, parse_color, 0)?,
+            pattern: value(&light.pattern, parse_led_pattern, 0)?,
+        })
+    }
+
+    fn buzz(&self, buzz: &BuzzRequest) -> Result<DeviceCommand, Status> {
+        Ok(DeviceCommand::Buzz {
+            pattern: value(&buzz.pattern, parse_buzzer_pattern, 0)?,
+            repetition: value(&buzz.repetition, parse_repetition, 0)?,
+            volume: value(&buzz.volume, parse_volume, self.daemon.volume())?,
+        })
+    }
+
+    fn volume(&self, volume: &SetVolumeRequest) -> Result<DeviceCommand, Status> {
+        Ok(DeviceCommand::Volume {
+            level: value(&volume.level, parse_volume, 0)?,
+        })
+    }
+
+    async fn execute<T>(&self, request: &Request<T>, command: DeviceCommand) -> Result<Response<CommandReply>, Status> {
+        let client: Arc<Client> = client(request, None)?;
+        match self.daemon.execute(&client, &command).await {
+            Ok((_, attempts)) => Ok(Response::new(CommandReply { attempts })),
+            Err(e) => Err(failed(e)),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Patlite for Service {
+    async fn set_light(&self, request: Request<SetLightRequest>) -> Result<Response<CommandReply>, Status> {
+        let command: DeviceCommand = self.light(request.get_ref())?;
+        self.execute(&request, command).await
+    }
+
+    async fn buzz(&self, request: Request<BuzzRequest>) -> Result<Response<CommandReply>, Status> {
+        let command: DeviceCommand = self.buzz(request.get_ref())?;
+        self.execute(&request, command).await
+    }
+
+    async fn set_volume(&self, request: Request<SetVolumeRequest>) -> Result<Response<CommandReply>, Status> {
+        let command: DeviceCommand = self.volume(request.get_ref())?;
+        self.execute(&request, command).await
+    }
+
+    async fn off(&self, request: Request<OffRequest>) -> Result<Response<CommandReply>, Status> {
+        self.execute(&request, DeviceCommand::Off).await
+    }
+
+    async fn get_state(&self, request: Request<GetStateRequest>) -> Result<Response<proto::DeviceState>, Status> {
+        client(&request, Some(Scope::ReadState))?;
+        Ok(Response::new(self.daemon.state().into()))
+    }
+
+    async fn list_devices(&self, request: Request<ListDevicesRequest>) -> Result<Response<ListDevicesReply>, Status> {
+        client(&request, Some(Scope::ReadState))?;
+        let devices: Vec<DeviceSummary> = self.daemon.devices().await.map_err(status)?;
+        Ok(Response::new(ListDevicesReply {
+            devices: devices.into_iter().map(Device::from).collect(),
+        }))
+    }
+
+    async fn play_sequence(&self, request: Request<PlaySequenceRequest>) -> Result<Response<CommandReply>, Status> {
+        let client: Arc<Client> = client(&request, None)?;
+        let steps: Vec<Step> = request
+            .into_inner()
+            .steps
+            .into_iter()
+            .enumerate()
+            .map(|(index, step)| {
+                let command: DeviceCommand = match &step.command {
+                    Some(Command::Light(light)) => self.light(light)?,
+                    Some(Command::Buzz(buzz)) => self.buzz(buzz)?,
+                    Some(Command::Volume(volume)) => self.volume(volume)?,
+                    Some(Command::Off(OffRequest {})) => DeviceCommand::Off,
+                    None => return Err(Status::invalid_argument(format!("Step {} has no command", index + 1))),
+                };
+                Ok(Step {
+                    command,
+                    hold: Duration::from_millis(step.hold_ms.into()),
+                })
+            })
+            .collect::<Result<_, Status>>()?;
+        let attempts: u32 = self.daemon.play(&client, &steps).await.map_err(failed)?;
+        Ok(Response::new(CommandReply { attempts }))
+    }
+
+    type WatchStateStream = Pin<Box<dyn Stream<Item = Result<proto::DeviceState, Status>> + Send>>;
+
+    async fn watch_state(&self, request: Request<WatchStateRequest>) -> Result<Response<Self::WatchStateStream>, Status> {
+        client(&request, Some(Scope::ReadState))?;
+        let states = WatchStream::new(self.daemon.watch()).map(|state| Ok(state.into()));
+        Ok(Response::new(Box::pin(states)))
+    }
+}
+
+// The gRPC service for the daemon's device, refusing calls without one of its tokens and
+// passing the client holding it on to the calls
+pub fn router(daemon: Daemon) -> axum::Router {
+    let checker: Daemon = daemon.clone();
+    let service = PatliteServer::with_interceptor(Service { daemon }, move |mut request: Request<()>| {
+        let client: Option<Arc<Client>> = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .and_then(|given| checker.client(given));
+        match client {
+            Some(client) => {
+                request.extensions_mut().insert(client);
+                Ok(request)
+            }
+            None => Err(Status::unauthenticated("Access")),
+        }
+    });
+    tonic::service::Routes::new(service).into_axum_router()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::access::AuditLog;
+    use crate::cli::config::ApiToken;
+    use crate::cli::serve::ServeOptions;
+    use axum::body::Body;
+    use axum::http;
+    use patlite_rs::{Patlite, ProfileRegistry, Simulator};
+    use prost::Message;
+    use std::io;
+    use tonic::codegen::Service as _;
+
+    const TOKEN: &str = "grpc-test-token";
+
+    // A daemon over the simulator, with one admin token
+    fn daemon() -> Daemon {
+        let options = ServeOptions {
+            listen: String::new(),
+            tokens: vec![ApiToken { name: "test".to_string(), token: TOKEN.to_string(), scopes: vec![Scope::Admin], buzzer_per_minute: None }],
+            buzzer_per_minute: 0,
+            audit_log: None,
+            tls: None,
+            volume: 4,
+            models: ProfileRegistry::default(),
+            presets: Default::default(),
+            state: DeviceState::default(),
+            hotplug: false,
+            serial: None,
+        };
+        Daemon::new(Patlite::new(Simulator::new()), None, AuditLog::new(Box::new(io::sink())), &options)
+    }
+
+    // Call `method` through the service's router as a gRPC client would, the message framed and
+    // the token passed as on the wire. Failures are the code and message of the status
+    async fn call<R: Message + Default>(daemon: &Daemon, method: &str, token: &str, message: impl Message) -> Result<R, (Code, String)> {
+        let mut body: Vec<u8> = vec![0];
+        body.extend((message.encoded_len() as u32).to_be_bytes());
+        message.encode(&mut body).expect("A Vec has room for any message");
+        let request = http::Request::post(format!("/patlite.v1.Patlite/{}", method))
+            .header("content-type", "application/grpc")
+            .header("te", "trailers")
+            .header("authorization", format!("Bearer {}", token))
+            .body(Body::from(body))
+            .expect("The request is valid");
+        let response = router(daemon.clone()).call(request).await.expect("The router never fails");
+        // Failures are answered with the status in the headers and no body
+        if let Some(status) = Status::from_header_map(response.headers()).filter(|status| status.code() != Code::Ok) {
+            return Err((status.code(), status.message().to_string()));
+        }
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.expect("The reply is read");
+        Ok(R::decode(&body[5..]).expect("The reply is a message"))
+    }
+
+    fn step(command: Command, hold_ms: u32) -> proto::Step {
+        proto::Step { command: Some(command), hold_ms }
+    }
+
+    #[tokio::test]
+    async fn sequence_plays_every_step() {
+        let daemon: Daemon = daemon();
+        let light = SetLightRequest { color: "red".to_string(), pattern: "blink".to_string() };
+        let buzz = BuzzRequest { pattern: "sweep".to_string(), repetition: "2".to_string(), volume: String::new() };
+        let request = PlaySequenceRequest {
+            steps: vec![step(Command::Light(light), 10), step(Command::Buzz(buzz), 0)],
+        };
+        let reply: CommandReply = call(&daemon, "PlaySequence", TOKEN, request).await.unwrap();
+        assert_eq!(reply.attempts, 1);
+
+        let state: DeviceState = daemon.state();
+        assert_eq!((state.color, state.pattern), (1, 2));
+        // The buzz left its volume empty, so it has the daemon's
+        assert_eq!((state.buzzer_pattern, state.repetition, state.volume), (2, 2, 4));
+
+        let read: proto::DeviceState = call(&daemon, "GetState", TOKEN, GetStateRequest {}).await.unwrap();
+        assert_eq!(read, state.into());
+    }
+
+    #[tokio::test]
+    async fn step_without_a_command_is_refused_before_anything_is_sent() {
+        let daemon: Daemon = daemon();
+        let request = PlaySequenceRequest {
+            steps: vec![step(Command::Off(OffRequest {}), 0), proto::Step { command: None, hold_ms: 0 }],
+        };
+        let refused = call::<CommandReply>(&daemon, "PlaySequence", TOKEN, request).await;
+        assert_eq!(refused, Err((Code::InvalidArgument, "Step 2 has no command".to_string())));
+        assert_eq!(daemon.state(), DeviceState::default());
+    }
+
+    #[tokio::test]
+    async fn calls_need_a_token() {
+        let refused = call::<CommandReply>(&daemon(), "Off", "wrong", OffRequest {}).await;
+        assert_eq!(refused, Err((Code::Unauthenticated, "Access".to_string())));
+    }
+}
@@ -0,0 +1,137 @@
+use patlite_rs::names::{names_of, BUZZER_PATTERN_NAMES, COLOR_NAMES, LED_PATTERN_NAMES, VOLUME_NAMES};
+use patlite_rs::DeviceState;
+use serde::Serialize;
+use std::str::FromStr;
+
+// Exit codes, 2 is left to clap for usage errors
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_DEVICE_NOT_FOUND: i32 = 3;
+pub const EXIT_ACCESS_DENIED: i32 = 4;
+pub const EXIT_TIMEOUT: i32 = 5;
+pub const EXIT_BUSY: i32 = 6;
+pub const EXIT_CONFIG: i32 = 7;
+pub const EXIT_REPLAY_INCOMPLETE: i32 = 8;
+
+// A control's value with the names accepted for it, for the info output
+#[derive(Serialize)]
+pub struct ControlValue {
+    pub value: u8,
+    pub names: Vec<&'static str>,
+}
+
+// Values 0 to `max` of a control followed by its keep value
+pub fn control_values(table: &[(&'static str, u8)], max: u8) -> Vec<ControlValue> {
+    (0..=max)
+        .chain([0xF])
+        .map(|value| ControlValue { value, names: names_of(table, value) })
+        .collect()
+}
+
+#[derive(Serialize)]
+pub struct AllControls {
+    pub color: Vec<ControlValue>,
+    pub led: Vec<ControlValue>,
+    pub buzzer: Vec<ControlValue>,
+    pub volume: Vec<ControlValue>,
+}
+
+// Every control, as `info all` shows them
+pub fn all_controls() -> AllControls {
+    AllControls {
+        color: control_values(COLOR_NAMES, 7),
+        led: control_values(LED_PATTERN_NAMES, 7),
+        buzzer: control_values(BUZZER_PATTERN_NAMES, 7),
+        volume: control_values(VOLUME_NAMES, 10),
+    }
+}
+
+pub const OUTPUT_FORMATS: [&str; 3] = ["table", "json", "yaml"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Yaml,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "yaml" => Ok(OutputFormat::Yaml),
+            _ => Err(format!("Unknown output format: {}", s)),
+        }
+    }
+}
+
+pub fn exit_code(error: &rusb::Error) -> i32 {
+    match error {
+        rusb::Error::NotFound | rusb::Error::NoDevice => EXIT_DEVICE_NOT_FOUND,
+        rusb::Error::Access => EXIT_ACCESS_DENIED,
+        rusb::Error::Timeout => EXIT_TIMEOUT,
+        rusb::Error::Busy => EXIT_BUSY,
+        _ => EXIT_FAILURE,
+    }
+}
+
+// Result of sending one command to the device
+#[derive(Debug, Serialize)]
+pub struct CommandResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<serde_json::Value>,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // Attempts sending took, when it was sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attempts: Option<u32>,
+    // The tracked state, for a state request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<DeviceState>,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorReport {
+    ok: bool,
+    error: String,
+    exit_code: i32,
+}
+
+// Print `value` as JSON or YAML, or the table built by `table` for the table format
+pub fn print<T: Serialize>(format: OutputFormat, value: &T, table: impl FnOnce() -> String) {
+    match format {
+        OutputFormat::Table => println!("{}", table()),
+        OutputFormat::Json => match serde_json::to_string_pretty(value) {
+            Ok(s) => println!("{}", s),
+            Err(e) => eprintln!("Failed to write JSON: {}", e),
+        },
+        OutputFormat::Yaml => match serde_yaml::to_string(value) {
+            Ok(s) => print!("{}", s),
+            Err(e) => eprintln!("Failed to write YAML: {}", e),
+        },
+    }
+}
+
+// Report a failure that stopped the command and return `exit_code` for it
+pub fn print_failure(format: OutputFormat, error: String, exit_code: i32) -> i32 {
+    let report = ErrorReport {
+        ok: false,
+        error,
+        exit_code,
+    };
+    match format {
+        OutputFormat::Table => eprintln!("Error: {}", report.error),
+        _ => print(format, &report, String::new),
+    }
+    report.exit_code
+}
+
+pub fn print_error(format: OutputFormat, error: &rusb::Error) -> i32 {
+    print_failure(format, error.to_string(), exit_code(error))
+}
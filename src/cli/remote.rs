@@ -0,0 +1,210 @@
+use patlite_rs::{error_from_name, Data, DeviceCommand, DeviceProfile, DeviceState, FrameFormat, Transport};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use ureq::tls::{parse_pem, Certificate, PemItem, RootCerts, TlsConfig};
+use ureq::Agent;
+
+// How long to wait for `serve`, which may itself be waiting out retries on its device
+const REMOTE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Body of `POST /v1/frames`, the frame as hex like in recordings
+#[derive(Serialize, Deserialize)]
+pub struct FrameRequest {
+    pub frame: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FrameReply {
+    pub sent: usize,
+    pub attempts: u32,
+}
+
+// One step of the body of `POST /v1/sequences`
+#[derive(Serialize, Deserialize)]
+pub struct StepRequest {
+    pub command: DeviceCommand,
+    #[serde(default)]
+    pub hold_ms: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SequenceRequest {
+    pub steps: Vec<StepRequest>,
+}
+
+// Reply of `POST /v1/sequences`, `attempts` is the most any step took
+#[derive(Serialize, Deserialize)]
+pub struct SequenceReply {
+    pub steps: usize,
+    pub attempts: u32,
+}
+
+// An entry of the reply of `GET /v1/presets`
+#[derive(Serialize, Deserialize)]
+pub struct PresetReply {
+    pub name: String,
+    pub action: String,
+    pub command: DeviceCommand,
+}
+
+// Reply of `GET /v1/profile`, `None` when the daemon drives a stand-in
+#[derive(Serialize, Deserialize)]
+pub struct ProfileReply {
+    pub profile: Option<DeviceProfile>,
+}
+
+// Body of every failed request, the `Debug` name of the error so it comes back as the same error.
+// Requests the daemon refused carry the reason
+#[derive(Serialize, Deserialize)]
+pub struct ErrorReply {
+    pub error: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+fn request_error(url: &str, e: ureq::Error) -> rusb::Error {
+    eprintln!("Failed to reach {}: {}", url, e);
+    match e {
+        ureq::Error::Timeout(_) => rusb::Error::Timeout,
+        ureq::Error::HostNotFound | ureq::Error::ConnectionFailed => rusb::Error::NoDevice,
+        ureq::Error::Io(e) => match e.kind() {
+            io::ErrorKind::TimedOut => rusb::Error::Timeout,
+            io::ErrorKind::ConnectionRefused => rusb::Error::NoDevice,
+            _ => rusb::Error::Io,
+        },
+        _ => rusb::Error::Other,
+    }
+}
+
+// Read a JSON reply, turning failures reported by the daemon back into the device's error
+fn read_reply<T: for<'de> Deserialize<'de>>(url: &str, response: ureq::http::Response<ureq::Body>) -> rusb::Result<T> {
+    let status = response.status();
+    let mut body = response.into_body();
+    if status.is_success() {
+        return body.read_json::<T>().map_err(|e| request_error(url, e));
+    }
+    match body.read_json::<ErrorReply>() {
+        Ok(reply) => {
+            let error: rusb::Error = error_from_name(&reply.error);
+            match reply.message {
+                Some(message) => eprintln!("{} refused the request: {}", url, message),
+                None if error == rusb::Error::Access => eprintln!("{} refused the token", url),
+                None => (),
+            }
+            Err(error)
+        }
+        Err(_) => {
+            eprintln!("{} answered {}", url, status);
+            Err(rusb::Error::Other)
+        }
+    }
+}
+
+// A device on another host, reached through `serve` there. Commands go as they are, for the
+// daemon to check against the token's scopes and buzzer limit, while raw frames need an admin token
+pub struct RemoteTransport {
+    agent: Agent,
+    url: String,
+    authorization: String,
+    profile: Option<DeviceProfile>,
+    attempts: u32,
+}
+
+impl RemoteTransport {
+    // Connect to `address`, `host:port` or a http:// or https:// URL, checking the token. `ca` is
+    // a PEM file of CA certificates to trust instead of the public roots, e.g. a private CA
+    pub fn connect(address: &str, token: &str, ca: Option<&Path>) -> rusb::Result<Self> {
+        let url: String = match address.contains("://") {
+            true => address.trim_end_matches('/').to_string(),
+            false => format!("http://{}", address),
+        };
+        let mut config = Agent::config_builder().timeout_global(Some(REMOTE_TIMEOUT)).http_status_as_error(false);
+        if let Some(ca) = ca {
+            let pem: Vec<u8> = fs::read(ca).map_err(|e| {
+                eprintln!("Failed to read {}: {}", ca.display(), e);
+                rusb::Error::Io
+            })?;
+            let certificates: Vec<Certificate<'static>> = parse_pem(&pem)
+                .filter_map(|item| match item {
+                    Ok(PemItem::Certificate(certificate)) => Some(certificate),
+                    _ => None,
+                })
+                .collect();
+            if certificates.is_empty() {
+                eprintln!("No certificates in {}", ca.display());
+                return Err(rusb::Error::InvalidParam);
+            }
+            config = config.tls_config(TlsConfig::builder().root_certs(RootCerts::new_with_certs(&certificates)).build());
+        }
+
+        let mut remote = RemoteTransport {
+            agent: config.build().into(),
+            url,
+            authorization: format!("Bearer {}", token),
+            profile: None,
+            attempts: 1,
+        };
+        let profile_url: String = format!("{}/v1/profile", remote.url);
+        let response = remote
+            .agent
+            .get(&profile_url)
+            .header("Authorization", &remote.authorization)
+            .call()
+            .map_err(|e| request_error(&profile_url, e))?;
+        remote.profile = read_reply::<ProfileReply>(&profile_url, response)?.profile;
+        Ok(remote)
+    }
+}
+
+impl RemoteTransport {
+    // POST `body` to `path` of the daemon, taking the attempts from its reply
+    fn post<T: Serialize>(&mut self, path: &str, body: T) -> rusb::Result<usize> {
+        let url: String = format!("{}{}", self.url, path);
+        let response = self
+            .agent
+            .post(&url)
+            .header("Authorization", &self.authorization)
+            .send_json(body)
+            .map_err(|e| request_error(&url, e))?;
+        let reply: FrameReply = read_reply(&url, response)?;
+        self.attempts = reply.attempts;
+        Ok(reply.sent)
+    }
+}
+
+impl Transport for RemoteTransport {
+    fn send(&mut self, data: Data) -> rusb::Result<usize> {
+        self.post("/v1/frames", FrameRequest { frame: data.to_hex() })
+    }
+
+    fn execute(&mut self, command: &DeviceCommand) -> rusb::Result<usize> {
+        self.post("/v1/commands", command)
+    }
+
+    fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    fn profile(&self) -> Option<&DeviceProfile> {
+        self.profile.as_ref()
+    }
+
+    // The state the daemon tracks, which like `Persisted` follows the single head of the NE-USB only
+    fn state(&self) -> Option<DeviceState> {
+        if self.profile.as_ref().is_some_and(|p| p.frame_format != FrameFormat::NeUsb) {
+            return None;
+        }
+        let url: String = format!("{}/v1/state", self.url);
+        let response = self
+            .agent
+            .get(&url)
+            .header("Authorization", &self.authorization)
+            .call()
+            .map_err(|e| request_error(&url, e))
+            .ok()?;
+        read_reply(&url, response).ok()
+    }
+}
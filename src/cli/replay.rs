@@ -0,0 +1,53 @@
+use patlite_rs::{Data, Direction, RecordedFrame, Transport};
+use serde::Serialize;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default, Serialize)]
+pub struct ReplaySummary {
+    pub sent: usize,
+    pub failed: usize,
+    pub skipped: usize,
+}
+
+// Send the frames recorded as sent to `transport`, waiting between them as long as was recorded
+// unless `delay` is false, and calling `show` after each one. Recorded reads are skipped since
+// the responses can't be asked for again
+pub fn run<T: Transport + ?Sized>(
+    transport: &mut T,
+    frames: &[RecordedFrame],
+    delay: bool,
+    mut show: impl FnMut(&T, &RecordedFrame, &Data),
+) -> ReplaySummary {
+    let mut summary = ReplaySummary::default();
+    let started = Instant::now();
+    for frame in frames {
+        if frame.direction == Direction::Received {
+            summary.skipped += 1;
+            continue;
+        }
+        let data: Data = match frame.to_data() {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("Skipping frame at {}ms: {}", frame.elapsed_ms, e);
+                summary.skipped += 1;
+                continue;
+            }
+        };
+        if delay {
+            let at = Duration::from_millis(frame.elapsed_ms);
+            if let Some(wait) = at.checked_sub(started.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+        match transport.send(data) {
+            Ok(_) => summary.sent += 1,
+            Err(e) => {
+                eprintln!("Failed to send {}: {}", data.to_hex(), e);
+                summary.failed += 1;
+            }
+        }
+        show(transport, frame, &data);
+    }
+    summary
+}
@@ -0,0 +1,580 @@
+use super::access::{required_scopes, sounds_buzzer, AuditLog, BuzzerLimit, Client, Refusal, Tokens};
+use super::config::{ApiToken, Preset, Scope};
+use super::output::all_controls;
+use super::remote::{ErrorReply, FrameReply, FrameRequest, PresetReply, ProfileReply, SequenceReply, SequenceRequest};
+use super::state_file;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, Request, State};
+use axum::http::header::{AUTHORIZATION, RETRY_AFTER};
+use axum::http::StatusCode;
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
+use hyper_util::service::TowerToHyperService;
+use patlite_rs::{
+    list_registered_devices, Data, DeviceCommand, DeviceProfile, DeviceState, DeviceSummary, FrameFormat, Patlite,
+    ProfileRegistry, Step, Transport,
+};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::WatchStream;
+use tokio_stream::{Stream, StreamExt};
+
+// How often to look for the device being unplugged or plugged back in
+const HOTPLUG_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct ServeOptions {
+    pub listen: String,
+    // At least one, --token and those of the config
+    pub tokens: Vec<ApiToken>,
+    // Commands sounding the buzzer each client may send a minute, 0 for no limit
+    pub buzzer_per_minute: u32,
+    // File to append the audit log to, stderr when `None`
+    pub audit_log: Option<PathBuf>,
+    // Certificate chain and private key, both PEM, to serve HTTPS
+    pub tls: Option<(PathBuf, PathBuf)>,
+    // Volume for buzzer commands that don't give one
+    pub volume: u8,
+    // Models to recognize when listing devices
+    pub models: ProfileRegistry,
+    // Presets of the config, offered by the control page
+    pub presets: BTreeMap<String, Preset>,
+    // The state to start tracking from, usually the one saved for the device
+    pub state: DeviceState,
+    // Whether the device is on USB, so it can be unplugged and plugged back in
+    pub hotplug: bool,
+    // Serial number of the device, any device when `None`
+    pub serial: Option<String>,
+}
+
+// Opens the transport again after the device went away, e.g. when it was unplugged and plugged
+// back in, so a long running daemon doesn't need restarting
+pub struct Reopening<F: FnMut() -> rusb::Result<Box<dyn Transport>>> {
+    inner: Option<Box<dyn Transport>>,
+    open: F,
+}
+
+impl<F: FnMut() -> rusb::Result<Box<dyn Transport>>> Reopening<F> {
+    pub fn new(inner: Box<dyn Transport>, open: F) -> Self {
+        Reopening {
+            inner: Some(inner),
+            open,
+        }
+    }
+
+    fn inner(&mut self) -> rusb::Result<&mut Box<dyn Transport>> {
+        if self.inner.is_none() {
+            self.inner = Some((self.open)()?);
+        }
+        Ok(self.inner.as_mut().expect("Opened above"))
+    }
+}
+
+impl<F: FnMut() -> rusb::Result<Box<dyn Transport>>> Transport for Reopening<F> {
+    fn send(&mut self, data: Data) -> rusb::Result<usize> {
+        match self.inner()?.send(data) {
+            Err(rusb::Error::NoDevice | rusb::Error::Io) => {
+                eprintln!("Lost the device, opening it again");
+                self.inner = None;
+                self.inner()?.send(data)
+            }
+            sent => sent,
+        }
+    }
+
+    fn receive(&mut self, buf: &mut [u8]) -> rusb::Result<usize> {
+        self.inner()?.receive(buf)
+    }
+
+    fn attempts(&self) -> u32 {
+        self.inner.as_ref().map_or(1, |inner| inner.attempts())
+    }
+
+    fn profile(&self) -> Option<&DeviceProfile> {
+        self.inner.as_ref().and_then(|inner| inner.profile())
+    }
+
+    fn state(&self) -> Option<DeviceState> {
+        self.inner.as_ref().and_then(|inner| inner.state())
+    }
+}
+
+// Why a request for the device failed
+#[derive(Debug)]
+pub enum Failure {
+    Refused(Refusal),
+    Device(rusb::Error),
+}
+
+impl From<rusb::Error> for Failure {
+    fn from(error: rusb::Error) -> Self {
+        Failure::Device(error)
+    }
+}
+
+impl From<Refusal> for Failure {
+    fn from(refusal: Refusal) -> Self {
+        Failure::Refused(refusal)
+    }
+}
+
+impl Failure {
+    // How the audit log records it
+    fn result(&self) -> String {
+        match self {
+            Failure::Refused(refusal) => refusal.to_string(),
+            Failure::Device(error) => format!("{:?}", error),
+        }
+    }
+}
+
+// The device and everything the REST and gRPC handlers share. Commands are laid out for the
+// device here so the state can follow the frames that were sent, and every command and frame
+// is checked against the client's scopes and buzzer limit before it gets there
+#[derive(Clone)]
+pub struct Daemon {
+    tower: Patlite,
+    tokens: Arc<Tokens>,
+    buzzer_limit: Arc<BuzzerLimit>,
+    audit: Arc<AuditLog>,
+    profile: Option<DeviceProfile>,
+    models: Arc<ProfileRegistry>,
+    presets: Arc<Vec<PresetReply>>,
+    state: watch::Sender<DeviceState>,
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    volume: u8,
+}
+
+impl Daemon {
+    // The daemon for `tower`, a device of `profile`, with the tokens, buzzer limit, presets and
+    // starting state of `options`
+    pub fn new(tower: Patlite, profile: Option<DeviceProfile>, audit: AuditLog, options: &ServeOptions) -> Daemon {
+        Daemon {
+            tower,
+            tokens: Arc::new(Tokens::new(options.tokens.clone())),
+            buzzer_limit: Arc::new(BuzzerLimit::new(options.buzzer_per_minute)),
+            audit: Arc::new(audit),
+            profile,
+            models: Arc::new(options.models.clone()),
+            presets: Arc::new(
+                options
+                    .presets
+                    .iter()
+                    .map(|(name, preset)| PresetReply {
+                        name: name.clone(),
+                        action: preset.action.clone(),
+                        command: preset.command.clone(),
+                    })
+                    .collect(),
+            ),
+            state: watch::Sender::new(options.state),
+            volume: options.volume,
+        }
+    }
+
+    // The client holding the token `given`
+    pub fn client(&self, given: &str) -> Option<Arc<Client>> {
+        self.tokens.client(given)
+    }
+
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    pub fn state(&self) -> DeviceState {
+        *self.state.borrow()
+    }
+
+    // Changes to the state, starting from the current one
+    pub fn watch(&self) -> watch::Receiver<DeviceState> {
+        self.state.subscribe()
+    }
+
+    pub async fn devices(&self) -> rusb::Result<Vec<DeviceSummary>> {
+        let models = self.models.clone();
+        tokio::task::spawn_blocking(move || list_registered_devices(&models))
+            .await
+            .map_err(|_| rusb::Error::Other)?
+    }
+
+    // Send a frame, returning how many attempts it took, and track the state it leaves the device in
+    async fn send(&self, data: Data) -> (rusb::Result<usize>, u32) {
+        let (sent, attempts) = self.tower.send_with_attempts(data).await;
+        // The state follows the single head of the NE-USB, tower frames don't map onto it
+        if sent.is_ok() && self.profile.as_ref().is_none_or(|p| p.frame_format == FrameFormat::NeUsb) {
+            self.state.send_if_modified(|state| {
+                let before: DeviceState = *state;
+                state.apply(&data);
+                *state != before
+            });
+        }
+        (sent, attempts)
+    }
+
+    // Send a raw frame from a --remote client, which only admin tokens may do since frames can
+    // change anything. Returns the bytes sent and the attempts it took
+    pub async fn relay(&self, client: &Client, data: Data) -> Result<(usize, u32), Failure> {
+        let result: Result<(usize, u32), Failure> = match client.missing(&[Scope::Admin]) {
+            Some(scope) => Err(Refusal::Scope(scope).into()),
+            None => match self.send(data).await {
+                (Ok(sent), attempts) => Ok((sent, attempts)),
+                (Err(e), _) => Err(e.into()),
+            },
+        };
+        let outcome: String = result.as_ref().map_or_else(Failure::result, |_| "ok".to_string());
+        self.audit.frame(client, &data.to_hex(), &outcome);
+        result
+    }
+
+    // Carry out a command for `client`, returning the bytes sent and the attempts it took
+    pub async fn execute(&self, client: &Client, command: &DeviceCommand) -> Result<(usize, u32), Failure> {
+        let result: Result<(usize, u32), Failure> = self.checked_execute(client, command).await;
+        let outcome: String = result.as_ref().map_or_else(Failure::result, |_| "ok".to_string());
+        self.audit.command(client, command, &outcome);
+        result
+    }
+
+    async fn checked_execute(&self, client: &Client, command: &DeviceCommand) -> Result<(usize, u32), Failure> {
+        if let Some(scope) = client.missing(&required_scopes(command)) {
+            return Err(Refusal::Scope(scope).into());
+        }
+        if let Err(e) = command.validate() {
+            eprintln!("Invalid command: {}", e);
+            return Err(rusb::Error::InvalidParam.into());
+        }
+        let data: Data = match command.encode(self.profile.as_ref().unwrap_or(&DeviceProfile::ne_usb())) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("Unsupported command: {}", e);
+                return Err(rusb::Error::NotSupported.into());
+            }
+        };
+        if sounds_buzzer(command) {
+            self.buzzer_limit.take(client)?;
+        }
+        match self.send(data).await {
+            (Ok(sent), attempts) => Ok((sent, attempts)),
+            (Err(e), _) => Err(e.into()),
+        }
+    }
+
+    // Send each step and wait out its hold time, returning the most attempts any step took. The
+    // scopes of every step are checked first so a sequence isn't cut short by one, though the
+    // buzzer limit can still stop it. Dropping the future cancels the sequence, as when the
+    // client goes away
+    pub async fn play(&self, client: &Client, steps: &[Step]) -> Result<u32, Failure> {
+        for step in steps {
+            if let Some(scope) = client.missing(&required_scopes(&step.command)) {
+                let refusal: Refusal = Refusal::Scope(scope);
+                self.audit.command(client, &step.command, &refusal.to_string());
+                return Err(refusal.into());
+            }
+        }
+        let mut most: u32 = 0;
+        for step in steps {
+            let (_, attempts) = self.execute(client, &step.command).await?;
+            most = most.max(attempts);
+            tokio::time::sleep(step.hold).await;
+        }
+        Ok(most)
+    }
+}
+
+fn failure(status: StatusCode, error: rusb::Error) -> Response {
+    let reply = ErrorReply {
+        error: format!("{:?}", error),
+        message: None,
+    };
+    (status, Json(reply)).into_response()
+}
+
+// Refusals come back to --remote as the errors closest to them, with the reason to print
+fn refused(refusal: Refusal) -> Response {
+    let (status, error) = match refusal {
+        Refusal::Scope(_) => (StatusCode::FORBIDDEN, rusb::Error::Access),
+        Refusal::BuzzerLimit(_) => (StatusCode::TOO_MANY_REQUESTS, rusb::Error::Busy),
+    };
+    let reply = ErrorReply {
+        error: format!("{:?}", error),
+        message: Some(refusal.to_string()),
+    };
+    match refusal {
+        Refusal::BuzzerLimit(wait) => (status, [(RETRY_AFTER, (wait.as_secs() + 1).to_string())], Json(reply)).into_response(),
+        Refusal::Scope(_) => (status, Json(reply)).into_response(),
+    }
+}
+
+// Look up the client of the request's token, which needs `scope` when given, for the handlers.
+// Browsers can't set headers on EventSource and WebSocket requests, so the token may also be
+// given as `?token=`, percent-encoded like the rest of the query
+async fn require_token(State((daemon, scope)): State<(Daemon, Option<Scope>)>, mut request: Request, next: Next) -> Response {
+    let given: Option<String> = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string)
+        .or_else(|| {
+            let Query(mut query) = Query::<BTreeMap<String, String>>::try_from_uri(request.uri()).ok()?;
+            query.remove("token")
+        });
+    let Some(client) = given.and_then(|given| daemon.client(&given)) else {
+        return failure(StatusCode::UNAUTHORIZED, rusb::Error::Access);
+    };
+    if let Some(scope) = scope.and_then(|scope| client.missing(&[scope])) {
+        return refused(Refusal::Scope(scope));
+    }
+    request.extensions_mut().insert(client);
+    next.run(request).await
+}
+
+async fn profile(State(daemon): State<Daemon>) -> Response {
+    Json(ProfileReply {
+        profile: daemon.profile.clone(),
+    })
+    .into_response()
+}
+
+// Errors in the request itself are the client's, the rest come from the device
+fn device_failure(error: rusb::Error) -> Response {
+    match error {
+        rusb::Error::InvalidParam | rusb::Error::NotSupported => failure(StatusCode::BAD_REQUEST, error),
+        _ => failure(StatusCode::BAD_GATEWAY, error),
+    }
+}
+
+fn failed(failure: Failure) -> Response {
+    match failure {
+        Failure::Refused(refusal) => refused(refusal),
+        Failure::Device(error) => device_failure(error),
+    }
+}
+
+fn sent_reply(result: Result<(usize, u32), Failure>) -> Response {
+    match result {
+        Ok((sent, attempts)) => Json(FrameReply { sent, attempts }).into_response(),
+        Err(e) => failed(e),
+    }
+}
+
+async fn send_frame(
+    State(daemon): State<Daemon>,
+    Extension(client): Extension<Arc<Client>>,
+    Json(request): Json<FrameRequest>,
+) -> Response {
+    match Data::from_hex(&request.frame) {
+        Ok(data) => sent_reply(daemon.relay(&client, data).await),
+        Err(_) => failure(StatusCode::BAD_REQUEST, rusb::Error::InvalidParam),
+    }
+}
+
+async fn send_command(
+    State(daemon): State<Daemon>,
+    Extension(client): Extension<Arc<Client>>,
+    Json(command): Json<DeviceCommand>,
+) -> Response {
+    sent_reply(daemon.execute(&client, &command).await)
+}
+
+async fn play_sequence(
+    State(daemon): State<Daemon>,
+    Extension(client): Extension<Arc<Client>>,
+    Json(request): Json<SequenceRequest>,
+) -> Response {
+    let steps: Vec<Step> = request
+        .steps
+        .into_iter()
+        .map(|step| Step {
+            command: step.command,
+            hold: Duration::from_millis(step.hold_ms),
+        })
+        .collect();
+    match daemon.play(&client, &steps).await {
+        Ok(attempts) => Json(SequenceReply { steps: steps.len(), attempts }).into_response(),
+        Err(e) => failed(e),
+    }
+}
+
+// Server-sent events of the state, the current one and then every change, each the JSON of `state`
+async fn events(State(daemon): State<Daemon>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = WatchStream::new(daemon.watch()).map(|state| {
+        let event: Event = Event::default().event("state");
+        Ok(event.json_data(state).unwrap_or_else(|_| Event::default().comment("unserializable state")))
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+// The same events as text messages over a WebSocket, until either side closes it
+async fn websocket(State(daemon): State<Daemon>, upgrade: WebSocketUpgrade) -> Response {
+    upgrade.on_upgrade(move |socket| send_states(socket, daemon.watch()))
+}
+
+async fn send_states(mut socket: WebSocket, mut states: watch::Receiver<DeviceState>) {
+    loop {
+        let state: DeviceState = *states.borrow_and_update();
+        let Ok(text) = serde_json::to_string(&state) else { return };
+        if socket.send(Message::Text(text.into())).await.is_err() {
+            return;
+        }
+        tokio::select! {
+            changed = states.changed() => if changed.is_err() { return },
+            // Clients have nothing to say, anything but a close is ignored
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => continue,
+            },
+        }
+    }
+}
+
+// Follow the device being unplugged and plugged back in. Either way it shows nothing until the
+// next command, so the state goes back to the power on state, keeping the connection display
+// setting the device remembers
+async fn watch_hotplug(daemon: Daemon, serial: Option<String>) {
+    let mut present: Option<bool> = None;
+    loop {
+        tokio::time::sleep(HOTPLUG_INTERVAL).await;
+        let Ok(devices) = daemon.devices().await else {
+            continue;
+        };
+        let found: bool = devices
+            .iter()
+            .any(|device| serial.is_none() || device.serial_number == serial);
+        if present.is_some_and(|present| present != found) {
+            eprintln!("Device {}", if found { "plugged in" } else { "unplugged" });
+            daemon.state.send_modify(|state| {
+                *state = DeviceState {
+                    connection_display: state.connection_display,
+                    ..DeviceState::default()
+                }
+            });
+            let _ = state_file::save(serial.as_deref(), &daemon.state());
+        }
+        present = Some(found);
+    }
+}
+
+async fn presets(State(daemon): State<Daemon>) -> Response {
+    Json(daemon.presets.as_slice()).into_response()
+}
+
+// The values and names of each control, the same as `info all`
+async fn controls() -> Response {
+    Json(all_controls()).into_response()
+}
+
+async fn state(State(daemon): State<Daemon>) -> Response {
+    Json(daemon.state()).into_response()
+}
+
+async fn devices(State(daemon): State<Daemon>) -> Response {
+    match daemon.devices().await {
+        Ok(devices) => Json(devices).into_response(),
+        Err(e) => failure(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+fn tls_acceptor(cert: &Path, key: &Path) -> Result<TlsAcceptor, String> {
+    let open = |path: &Path| File::open(path).map(BufReader::new).map_err(|e| format!("Failed to read {}: {}", path.display(), e));
+    let certs = rustls_pemfile::certs(&mut open(cert)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid certificate {}: {}", cert.display(), e))?;
+    let key = rustls_pemfile::private_key(&mut open(key)?)
+        .map_err(|e| format!("Invalid key {}: {}", key.display(), e))?
+        .ok_or_else(|| format!("No private key in {}", key.display()))?;
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("Invalid certificate or key: {}", e))?;
+    // gRPC clients only speak HTTP/2 and expect it to be offered
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+// Serve the REST API, and the gRPC service when built with it, for `tower` until the process
+// is stopped
+pub async fn run(tower: Patlite, options: ServeOptions) -> Result<(), String> {
+    let model: Option<DeviceProfile> = tower.profile().await.map_err(|e| format!("Failed to reach the device: {:?}", e))?;
+    let audit: AuditLog = AuditLog::open(options.audit_log.as_deref()).map_err(|e| {
+        let path: &Path = options.audit_log.as_deref().expect("Only files fail to open");
+        format!("Failed to open the audit log {}: {}", path.display(), e)
+    })?;
+    let daemon: Daemon = Daemon::new(tower, model, audit, &options);
+    let gate = |scope: Option<Scope>| middleware::from_fn_with_state((daemon.clone(), scope), require_token);
+    let reading: Router<Daemon> = Router::new()
+        .route("/v1/state", get(state))
+        .route("/v1/devices", get(devices))
+        .route("/v1/presets", get(presets))
+        .route("/v1/controls", get(controls))
+        .route("/v1/events", get(events))
+        .route("/v1/ws", get(websocket))
+        .route_layer(gate(Some(Scope::ReadState)));
+    // The scopes of commands depend on what they change, the daemon checks them. Any token may
+    // read the model, which --remote connects with
+    let commanding: Router<Daemon> = Router::new()
+        .route("/v1/profile", get(profile))
+        .route("/v1/frames", post(send_frame))
+        .route("/v1/commands", post(send_command))
+        .route("/v1/sequences", post(play_sequence))
+        .route_layer(gate(None));
+    let router: Router = reading
+        .merge(commanding)
+        .with_state(daemon.clone())
+        .merge(super::ui::router());
+    if options.hotplug {
+        tokio::spawn(watch_hotplug(daemon.clone(), options.serial));
+    }
+    // gRPC checks the token itself, it answers with a gRPC status rather than a JSON error
+    #[cfg(feature = "grpc")]
+    let router: Router = router.merge(super::grpc::router(daemon));
+
+    let acceptor: Option<TlsAcceptor> = match &options.tls {
+        Some((cert, key)) => Some(tls_acceptor(cert, key)?),
+        None => None,
+    };
+    let listener: TcpListener = TcpListener::bind(&options.listen)
+        .await
+        .map_err(|e| format!("Failed to listen on {}: {}", options.listen, e))?;
+    let scheme: &str = if acceptor.is_some() { "https" } else { "http" };
+    // The address bound, which has the port picked when listening on port 0
+    let address: String = listener.local_addr().map_or(options.listen, |address| address.to_string());
+    eprintln!("Serving on {}://{}", scheme, address);
+
+    let Some(acceptor) = acceptor else {
+        return axum::serve(listener, router).await.map_err(|e| e.to_string());
+    };
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("Failed to accept a connection: {}", e);
+                continue;
+            }
+        };
+        let (acceptor, router) = (acceptor.clone(), router.clone());
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => return eprintln!("TLS handshake with {} failed: {}", peer, e),
+            };
+            let service = TowerToHyperService::new(router);
+            if let Err(e) = Builder::new(TokioExecutor::new()).serve_connection(TokioIo::new(stream), service).await {
+                eprintln!("Connection with {} failed: {}", peer, e);
+            }
+        });
+    }
+}
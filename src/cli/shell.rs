@@ -0,0 +1,240 @@
+use super::config::{user_config_dir, Config};
+use patlite_rs::constants::BUZZER_VOLUME_KEEP;
+use patlite_rs::names::{
+    parse_setting, BUZZER_PATTERN_NAMES, COLOR_NAMES, LED_PATTERN_NAMES, REPETITION_NAMES, SETTING_NAMES,
+    VOLUME_NAMES,
+};
+use patlite_rs::{
+    parse_buzzer_pattern, parse_color, parse_led_pattern, parse_repetition, parse_volume,
+    DeviceCommand, DeviceState, TierSetting, Transport,
+};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Editor, Helper};
+use std::fs;
+use std::path::PathBuf;
+
+const PROMPT: &str = "patlite> ";
+const COMMANDS: &[&str] = &[
+    "light", "buzz", "volume", "master", "tier", "off", "connection-display", "state", "preset", "help", "exit",
+];
+const HELP: &str = "\
+light COLOR [PATTERN]                                 Set the light, the pattern defaults to on
+buzz PATTERN [REPETITION] [VOLUME]                    Sound the buzzer, continuously at the current volume by default
+volume LEVEL                                          Set the buzzer volume
+master COLOR PATTERN BUZZERPATTERN REPETITION VOLUME  Set everything at once
+tier TIER MODE [tier TIER MODE]...                    Set tiers of a tower by number or color to on, blink or off
+off                                                   Turn the light and buzzer off
+connection-display on|off                             Flash white when connected to a host, or not
+preset NAME                                           Apply a preset from the config file
+state                                                 Show the state the device was set to
+help                                                  Show this help
+exit                                                  Leave the shell
+
+Press tab to complete commands, colors, patterns and presets";
+
+// Names to complete for argument `index` of `command`
+fn argument_names(command: &str, index: usize) -> &'static [(&'static str, u8)] {
+    match (command, index) {
+        ("light", 0) | ("master", 0) => COLOR_NAMES,
+        ("light", 1) | ("master", 1) => LED_PATTERN_NAMES,
+        ("buzz", 0) | ("master", 2) => BUZZER_PATTERN_NAMES,
+        ("buzz", 1) | ("master", 3) => REPETITION_NAMES,
+        ("buzz", 2) | ("master", 4) | ("volume", 0) => VOLUME_NAMES,
+        ("connection-display", 0) => SETTING_NAMES,
+        _ => &[],
+    }
+}
+
+struct ShellHelper {
+    presets: Vec<String>,
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos].rfind(' ').map_or(0, |i| i + 1);
+        let prefix = line[start..pos].to_lowercase();
+        let words: Vec<&str> = line[..start].split_whitespace().collect();
+
+        let candidates: Vec<&str> = match words.split_first() {
+            None => COMMANDS.to_vec(),
+            Some((&"preset", [])) => self.presets.iter().map(String::as_str).collect(),
+            Some((command, args)) => argument_names(command, args.len())
+                .iter()
+                .map(|(name, _)| *name)
+                .collect(),
+        };
+        let pairs = candidates
+            .into_iter()
+            .filter(|c| c.starts_with(&prefix))
+            .map(|c| Pair {
+                display: c.to_string(),
+                replacement: format!("{} ", c),
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+fn history_path() -> Option<PathBuf> {
+    user_config_dir().map(|dir| dir.join("patlite-rs").join("history"))
+}
+
+fn argument<'a>(words: &[&'a str], index: usize, name: &str) -> Result<&'a str, String> {
+    words
+        .get(index)
+        .copied()
+        .ok_or_else(|| format!("Missing {}, see help", name))
+}
+
+fn parse_command(words: &[&str], config: &Config) -> Result<DeviceCommand, String> {
+    let (command, args) = words.split_first().expect("Line is not empty");
+    let optional = |index: usize, parse: fn(&str) -> Result<u8, String>, default: u8| {
+        args.get(index).map_or(Ok(default), |a| parse(a))
+    };
+    let command = command.to_lowercase();
+    let max_args = match command.as_str() {
+        "light" => 2,
+        "buzz" => 3,
+        "volume" => 1,
+        "master" => 5,
+        "off" => 0,
+        "connection-display" => 1,
+        "preset" => 1,
+        _ => usize::MAX,
+    };
+    if args.len() > max_args {
+        return Err(format!("Too many arguments for {}, see help", command));
+    }
+
+    Ok(match command.as_str() {
+        "light" => DeviceCommand::Light {
+            color: parse_color(argument(args, 0, "COLOR")?)?,
+            pattern: optional(1, parse_led_pattern, 1)?,
+        },
+        "buzz" => DeviceCommand::Buzz {
+            pattern: parse_buzzer_pattern(argument(args, 0, "PATTERN")?)?,
+            repetition: optional(1, parse_repetition, 0)?,
+            volume: optional(2, parse_volume, config.volume.unwrap_or(BUZZER_VOLUME_KEEP))?,
+        },
+        "volume" => DeviceCommand::Volume {
+            level: parse_volume(argument(args, 0, "LEVEL")?)?,
+        },
+        "master" => DeviceCommand::Master {
+            color: parse_color(argument(args, 0, "COLOR")?)?,
+            color_pattern: parse_led_pattern(argument(args, 1, "PATTERN")?)?,
+            buzzer_pattern: parse_buzzer_pattern(argument(args, 2, "BUZZERPATTERN")?)?,
+            repetition: parse_repetition(argument(args, 3, "REPETITION")?)?,
+            volume: parse_volume(argument(args, 4, "VOLUME")?)?,
+        },
+        "off" => DeviceCommand::Off,
+        "tier" => DeviceCommand::Tiers {
+            tiers: TierSetting::parse_list(args)?,
+        },
+        "connection-display" => DeviceCommand::ConnectionDisplay {
+            on: parse_setting(argument(args, 0, "SETTING")?)?,
+        },
+        "preset" => {
+            let name = argument(args, 0, "NAME")?;
+            config
+                .presets
+                .get(name)
+                .map(|p| p.command.clone())
+                .ok_or_else(|| format!("Unknown preset: {}", name))?
+        }
+        other => return Err(format!("Unknown command: {}, see help", other)),
+    })
+}
+
+// Read commands until `exit` or EOF, sending each to the already open `handle` and showing the
+// resulting state
+pub fn run(handle: &mut dyn Transport, config: &Config) -> rusb::Result<()> {
+    let mut editor: Editor<ShellHelper, DefaultHistory> = match Editor::new() {
+        Ok(e) => e,
+        Err(e) => {
+            eprintln!("Failed to start the shell: {}", e);
+            return Err(rusb::Error::Other);
+        }
+    };
+    editor.set_helper(Some(ShellHelper {
+        presets: config.presets.keys().cloned().collect(),
+    }));
+    let history = history_path();
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+
+    // Start from what the transport knows, e.g. the daemon behind --remote, otherwise from the
+    // state the device powers on in as it can't be read back
+    let mut state: DeviceState = handle.state().unwrap_or_default();
+    println!("Type help for commands, exit to leave");
+
+    loop {
+        let line = match editor.readline(PROMPT) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("Failed to read command: {}", e);
+                break;
+            }
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line.as_str());
+
+        match words[0].to_lowercase().as_str() {
+            "exit" | "quit" => break,
+            "help" => println!("{}", HELP),
+            "state" => println!("{}", state),
+            _ => match parse_command(&words, config).and_then(|c| c.validate().map(|_| c)) {
+                Ok(command) => match command.execute(handle) {
+                    Ok(_) => {
+                        match handle.state() {
+                            Some(tracked) => state = tracked,
+                            None => {
+                                if let Ok(data) = command.to_data() {
+                                    state.apply(&data);
+                                }
+                            }
+                        }
+                        println!("{}", state);
+                    }
+                    Err(e) => println!("Failed to send command: {:?}", e),
+                },
+                Err(e) => println!("{}", e),
+            },
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let _ = editor.save_history(path);
+    }
+    Ok(())
+}
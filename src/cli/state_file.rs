@@ -0,0 +1,86 @@
+use super::config::user_config_dir;
+use patlite_rs::{Data, DeviceProfile, DeviceState, FrameFormat, Transport};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+// Where the state of the device with `serial` is kept between runs, since the device can't be
+// read back. Without a serial it's the state of whichever device was opened first
+fn path(serial: Option<&str>) -> Option<PathBuf> {
+    let name: String = serial
+        .unwrap_or("default")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    user_config_dir().map(|dir| dir.join("patlite-rs").join(format!("state-{}.json", name)))
+}
+
+// The state saved for the device, or the device's power on state if nothing was saved yet or
+// the saved one isn't a state the device could be in
+pub fn load(serial: Option<&str>) -> DeviceState {
+    let Some(path) = path(serial) else { return DeviceState::default() };
+    let Ok(text) = fs::read_to_string(&path) else { return DeviceState::default() };
+    let state: Result<DeviceState, String> = serde_json::from_str(&text)
+        .map_err(|e| e.to_string())
+        .and_then(|state: DeviceState| state.validate().map(|_| state));
+    state.unwrap_or_else(|e| {
+        eprintln!("Ignoring the saved state {}: {}", path.display(), e);
+        DeviceState::default()
+    })
+}
+
+pub fn save(serial: Option<&str>, state: &DeviceState) -> io::Result<()> {
+    let path: PathBuf = path(serial).ok_or_else(|| io::Error::other("No config directory"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_json::to_string(state).map_err(io::Error::other)?)
+}
+
+// Sends with `inner` and saves the state after every frame the device took, so `state` can
+// report it later
+pub struct Persisted<T: Transport> {
+    inner: T,
+    serial: Option<String>,
+    state: DeviceState,
+}
+
+impl<T: Transport> Persisted<T> {
+    pub fn new(inner: T, serial: Option<String>) -> Self {
+        let state: DeviceState = load(serial.as_deref());
+        Persisted { inner, serial, state }
+    }
+
+    // The state follows the single head of the NE-USB, tower frames don't map onto it
+    fn tracked(&self) -> bool {
+        self.inner.profile().is_none_or(|p| p.frame_format == FrameFormat::NeUsb)
+    }
+}
+
+impl<T: Transport> Transport for Persisted<T> {
+    fn send(&mut self, data: Data) -> rusb::Result<usize> {
+        let sent = self.inner.send(data)?;
+        if self.tracked() {
+            self.state.apply(&data);
+        }
+        // Losing the saved state only makes `state` out of date, it shouldn't fail the command
+        let _ = save(self.serial.as_deref(), &self.state);
+        Ok(sent)
+    }
+
+    fn receive(&mut self, buf: &mut [u8]) -> rusb::Result<usize> {
+        self.inner.receive(buf)
+    }
+
+    fn attempts(&self) -> u32 {
+        self.inner.attempts()
+    }
+
+    fn profile(&self) -> Option<&DeviceProfile> {
+        self.inner.profile()
+    }
+
+    fn state(&self) -> Option<DeviceState> {
+        self.tracked().then_some(self.state)
+    }
+}
@@ -0,0 +1,78 @@
+use super::output::CommandResult;
+use patlite_rs::{DeviceCommand, DeviceState, Transport};
+use std::io::{self, BufRead, Write};
+
+fn invalid(id: Option<serde_json::Value>, error: String) -> CommandResult {
+    CommandResult {
+        id,
+        ok: false,
+        command: None,
+        error: Some(format!("Invalid command: {}", error)),
+        attempts: None,
+        state: None,
+    }
+}
+
+// Answer `{"command": "state"}` with what the device was last set to, which isn't a frame to send
+fn state(handle: &dyn Transport, id: Option<serde_json::Value>) -> CommandResult {
+    let state: Option<DeviceState> = handle.state();
+    CommandResult {
+        id,
+        ok: state.is_some(),
+        command: Some("state"),
+        error: state.is_none().then(|| "The state of this device isn't tracked".to_string()),
+        attempts: None,
+        state,
+    }
+}
+
+// One line of input: a command object, optionally with an `id` that is echoed back in its result.
+// The id is taken out first so that a command which fails to parse still answers to it
+fn apply(handle: &mut dyn Transport, line: &str) -> CommandResult {
+    let mut request: serde_json::Value = match serde_json::from_str(line) {
+        Ok(r) => r,
+        Err(e) => return invalid(None, e.to_string()),
+    };
+    let id: Option<serde_json::Value> = request.as_object_mut().and_then(|fields| fields.remove("id"));
+    if request.get("command").and_then(serde_json::Value::as_str) == Some("state") {
+        return state(handle, id);
+    }
+    let command: DeviceCommand = match serde_json::from_value(request) {
+        Ok(c) => c,
+        Err(e) => return invalid(id, e.to_string()),
+    };
+    let name = command.name();
+    let mut attempts: Option<u32> = None;
+    let outcome = command.validate().and_then(|_| {
+        let sent = command.execute(handle).map_err(|e| e.to_string());
+        attempts = Some(handle.attempts());
+        sent
+    });
+    CommandResult {
+        id,
+        ok: outcome.is_ok(),
+        command: Some(name),
+        error: outcome.err(),
+        attempts,
+        state: None,
+    }
+}
+
+// Read newline delimited JSON commands from stdin until EOF, applying each to the already open
+// `handle` and writing one JSON result line per command to stdout
+pub fn run(handle: &mut dyn Transport) -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+
+    for line in stdin.lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = apply(handle, &line);
+        serde_json::to_writer(&mut stdout, &result)?;
+        writeln!(stdout)?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,287 @@
+use patlite_rs::constants::*;
+use patlite_rs::names::{canonical_name, BUZZER_PATTERN_NAMES, COLOR_NAMES, LED_PATTERN_NAMES};
+use patlite_rs::{DeviceCommand, DeviceState, Transport};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Alignment, Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::io;
+use std::time::{Duration, Instant};
+
+// How often the screen is redrawn to animate the light pattern
+const TICK: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Color,
+    Pattern,
+    BuzzerPattern,
+    Repetition,
+    Volume,
+}
+
+const FIELDS: [Field; 5] = [
+    Field::Color,
+    Field::Pattern,
+    Field::BuzzerPattern,
+    Field::Repetition,
+    Field::Volume,
+];
+
+impl Field {
+    fn label(&self) -> &'static str {
+        match self {
+            Field::Color => "Color",
+            Field::Pattern => "Pattern",
+            Field::BuzzerPattern => "Buzzer",
+            Field::Repetition => "Repetition",
+            Field::Volume => "Volume",
+        }
+    }
+
+    fn max(&self) -> u8 {
+        match self {
+            Field::Color => LED_COLOR_WHITE,
+            Field::Pattern => LED_PATTERN6,
+            Field::BuzzerPattern => BUZZER_LONDON_BRIDGE,
+            Field::Repetition => BUZZER_COUNT_MAX,
+            Field::Volume => BUZZER_VOLUME_MAX,
+        }
+    }
+
+    fn value(&self, state: &DeviceState) -> u8 {
+        match self {
+            Field::Color => state.color,
+            Field::Pattern => state.pattern,
+            Field::BuzzerPattern => state.buzzer_pattern,
+            Field::Repetition => state.repetition,
+            Field::Volume => state.volume,
+        }
+    }
+
+    fn describe(&self, value: u8) -> String {
+        let name = match self {
+            Field::Color => canonical_name(COLOR_NAMES, value),
+            Field::Pattern => canonical_name(LED_PATTERN_NAMES, value),
+            Field::BuzzerPattern => canonical_name(BUZZER_PATTERN_NAMES, value),
+            Field::Repetition if value == BUZZER_COUNT_CONTINUE => Some("continuous"),
+            Field::Repetition | Field::Volume => None,
+        };
+        name.map_or(value.to_string(), str::to_string)
+    }
+
+    // A command changing only this field, everything else is sent as "keep"
+    fn command(&self, value: u8) -> DeviceCommand {
+        let value_of = |field: Field| if field == *self { value } else { field.keep() };
+        DeviceCommand::Master {
+            color: value_of(Field::Color),
+            color_pattern: value_of(Field::Pattern),
+            buzzer_pattern: value_of(Field::BuzzerPattern),
+            repetition: value_of(Field::Repetition),
+            volume: value_of(Field::Volume),
+        }
+    }
+
+    fn keep(&self) -> u8 {
+        match self {
+            Field::Color => LED_COLOR_KEEP,
+            Field::Pattern => LED_PATTERN_KEEP,
+            Field::BuzzerPattern => BUZZER_KEEP,
+            Field::Repetition => BUZZER_COUNT_KEEP,
+            Field::Volume => BUZZER_VOLUME_KEEP,
+        }
+    }
+}
+
+fn swatch(color: u8) -> Color {
+    match color {
+        LED_COLOR_RED => Color::Red,
+        LED_COLOR_GREEN => Color::Green,
+        LED_COLOR_YELLOW => Color::Yellow,
+        LED_COLOR_BLUE => Color::Blue,
+        LED_COLOR_PURPLE => Color::Magenta,
+        LED_COLOR_LIGHTBLUE => Color::Cyan,
+        LED_COLOR_WHITE => Color::White,
+        _ => Color::DarkGray,
+    }
+}
+
+struct App<'a> {
+    handle: &'a mut dyn Transport,
+    state: DeviceState,
+    selected: usize,
+    started: Instant,
+    status: String,
+}
+
+impl App<'_> {
+    // Send `command` and merge it into the shown state the same way the device does
+    fn send(&mut self, command: DeviceCommand) {
+        match command.execute(self.handle) {
+            Ok(_) => {
+                match self.handle.state() {
+                    Some(tracked) => self.state = tracked,
+                    None => {
+                        if let Ok(data) = command.to_data() {
+                            self.state.apply(&data);
+                        }
+                    }
+                }
+                self.status = "Sent".to_string();
+            }
+            Err(e) => self.status = format!("Failed to send command: {:?}", e),
+        }
+    }
+
+    fn step(&mut self, forward: bool) {
+        let field = FIELDS[self.selected];
+        let count = field.max() + 1;
+        let value = field.value(&self.state);
+        let value = if forward {
+            (value + 1) % count
+        } else {
+            (value + count - 1) % count
+        };
+        self.send(field.command(value));
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [tower_area, panel_area] =
+            Layout::horizontal([Constraint::Length(24), Constraint::Min(40)]).areas(frame.area());
+
+        let [lamp_area, base_area, _] = Layout::vertical([
+            Constraint::Length(10),
+            Constraint::Length(3),
+            Constraint::Min(0),
+        ])
+        .areas(tower_area);
+        let lit = self.state.is_lit(self.started.elapsed());
+        let lamp = if lit {
+            swatch(self.state.color)
+        } else {
+            Color::Black
+        };
+        frame.render_widget(
+            Block::bordered().title("Light").style(Style::default().bg(lamp)),
+            lamp_area,
+        );
+        frame.render_widget(
+            Paragraph::new(self.handle.profile().map_or("Unknown model", |profile| profile.name.as_str()))
+                .alignment(Alignment::Center)
+                .block(Block::bordered()),
+            base_area,
+        );
+
+        let [fields_area, volume_area, buzzer_area, help_area, status_area] = Layout::vertical([
+            Constraint::Length(FIELDS.len() as u16 + 2),
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .areas(panel_area);
+
+        let lines: Vec<Line> = FIELDS
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                let text = format!(
+                    "{} {:<11} {}",
+                    if i == self.selected { ">" } else { " " },
+                    field.label(),
+                    field.describe(field.value(&self.state))
+                );
+                if i == self.selected {
+                    Line::styled(text, Style::default().add_modifier(Modifier::REVERSED))
+                } else {
+                    Line::raw(text)
+                }
+            })
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title("State")),
+            fields_area,
+        );
+
+        frame.render_widget(
+            Gauge::default()
+                .block(Block::bordered().title("Volume"))
+                .ratio(f64::from(self.state.volume) / f64::from(BUZZER_VOLUME_MAX))
+                .label(format!("{}/{}", self.state.volume, BUZZER_VOLUME_MAX)),
+            volume_area,
+        );
+
+        let buzzer = if self.state.is_buzzing() {
+            // Alternate the notes to show the buzzer is sounding
+            let flip = (self.started.elapsed().as_millis() / 300).is_multiple_of(2);
+            let notes = if flip { "♪ ♫" } else { "♫ ♪" };
+            format!(
+                "{} {} {}",
+                Field::BuzzerPattern.describe(self.state.buzzer_pattern),
+                Field::Repetition.describe(self.state.repetition),
+                notes
+            )
+        } else {
+            "Silent".to_string()
+        };
+        frame.render_widget(
+            Paragraph::new(buzzer).block(Block::bordered().title("Buzzer")),
+            buzzer_area,
+        );
+
+        frame.render_widget(
+            Paragraph::new("↑/↓ select  ←/→ change  o off  q quit")
+                .block(Block::bordered().title("Keys")),
+            help_area,
+        );
+        frame.render_widget(Paragraph::new(self.status.as_str()), status_area);
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if !event::poll(TICK)? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Up | KeyCode::Char('k') => {
+                    self.selected = (self.selected + FIELDS.len() - 1) % FIELDS.len()
+                }
+                KeyCode::Down | KeyCode::Char('j') => {
+                    self.selected = (self.selected + 1) % FIELDS.len()
+                }
+                KeyCode::Right | KeyCode::Char('l') => self.step(true),
+                KeyCode::Left | KeyCode::Char('h') => self.step(false),
+                KeyCode::Char('o') => self.send(DeviceCommand::Off),
+                _ => {}
+            }
+        }
+    }
+}
+
+// Show the state of the already open `handle` and change it from the keyboard until `q`
+pub fn run(handle: &mut dyn Transport) -> io::Result<()> {
+    // Start from what the transport knows, otherwise from the state the device powers on in as it
+    // can't be read back
+    let state: DeviceState = handle.state().unwrap_or_default();
+    let mut app = App {
+        handle,
+        state,
+        selected: 0,
+        started: Instant::now(),
+        status: String::new(),
+    };
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    result
+}
@@ -0,0 +1,254 @@
+// Control page for `patlite-rs serve`, on top of the REST API and the state events
+
+const KEEP = 15;
+const TOKEN_KEY = "patlite-token";
+
+// How each color is drawn, by canonical name
+const SHADES = {
+  red: "#e53935",
+  green: "#43a047",
+  yellow: "#fdd835",
+  blue: "#1e88e5",
+  purple: "#8e24aa",
+  "light-blue": "#4fc3f7",
+  white: "#f5f5f5",
+};
+
+// One cycle of LED patterns 1 to 6 as [lit, milliseconds] steps, as in src/state.rs
+const PATTERN_CYCLES = [
+  [[true, 500], [false, 500]],
+  [[true, 250], [false, 250]],
+  [[true, 100], [false, 100], [true, 100], [false, 700]],
+  [[true, 100], [false, 100], [true, 100], [false, 100], [true, 100], [false, 500]],
+  [[true, 50], [false, 50]],
+  [[true, 200], [false, 200], [true, 200], [false, 1000]],
+];
+
+let token = localStorage.getItem(TOKEN_KEY);
+let controls = null;
+let profile = null;
+let state = null;
+let events = null;
+let started = performance.now();
+
+const $ = (id) => document.getElementById(id);
+
+function showError(message) {
+  $("error").textContent = message;
+}
+
+async function api(path, body) {
+  const request = { headers: { Authorization: `Bearer ${token}` } };
+  if (body !== undefined) {
+    request.method = "POST";
+    request.headers["Content-Type"] = "application/json";
+    request.body = JSON.stringify(body);
+  }
+  const response = await fetch(path, request);
+  if (response.status === 401) {
+    logout("The token was refused");
+    throw new Error("Access");
+  }
+  const reply = await response.json();
+  if (!response.ok) {
+    // Refusals of the daemon, e.g. a missing scope or the buzzer limit, carry the reason
+    throw new Error(reply.message || reply.error || response.statusText);
+  }
+  return reply;
+}
+
+// Send a command, showing why it failed if it did
+async function send(command) {
+  try {
+    await api("/v1/commands", command);
+    showError("");
+  } catch (e) {
+    showError(`${command.command} failed: ${e.message}`);
+  }
+}
+
+// The canonical name of `value` in a control of `info all`
+function nameOf(control, value) {
+  const entry = controls[control].find((v) => v.value === value);
+  return entry && entry.names.length ? entry.names[0] : String(value);
+}
+
+function fillSelect(select, control, selected) {
+  select.replaceChildren(
+    ...controls[control]
+      .filter((v) => v.value !== KEEP)
+      .map((v) => new Option(v.names.length ? `${v.names[0]} (${v.value})` : String(v.value), v.value, false, v.value === selected))
+  );
+}
+
+function isTower() {
+  return profile !== null && profile.frame_format !== "ne-usb";
+}
+
+function renderTower() {
+  const tower = $("tower");
+  if (isTower()) {
+    // The state only follows the single head of the NE-USB, so tower tiers are only labelled
+    tower.replaceChildren(
+      ...profile.colors.slice(0, profile.tiers).map((color, i) => {
+        const tier = document.createElement("button");
+        tier.className = "tier";
+        tier.style.setProperty("--shade", SHADES[nameOf("color", color)] || "#888");
+        tier.textContent = `${i + 1} ${nameOf("color", color)}`;
+        tier.title = "Light this tier";
+        tier.onclick = () => send({ command: "tiers", tiers: [{ tier: i + 1, mode: "on" }] });
+        return tier;
+      })
+    );
+  } else {
+    const light = document.createElement("div");
+    light.id = "light-head";
+    light.className = "head";
+    tower.replaceChildren(light);
+  }
+}
+
+// Whether the light is lit `elapsed` milliseconds into its pattern
+function isLit(elapsed) {
+  if (state.color === 0 || state.pattern === 0) {
+    return false;
+  }
+  const cycle = PATTERN_CYCLES[state.pattern - 2];
+  if (!cycle) {
+    return true;
+  }
+  const length = cycle.reduce((total, [, ms]) => total + ms, 0);
+  let at = elapsed % length;
+  for (const [lit, ms] of cycle) {
+    if (at < ms) {
+      return lit;
+    }
+    at -= ms;
+  }
+  return false;
+}
+
+function animate(now) {
+  const head = $("light-head");
+  if (head && state) {
+    const lit = isLit(now - started);
+    head.style.background = lit ? SHADES[nameOf("color", state.color)] || "#888" : "";
+    head.classList.toggle("lit", lit);
+  }
+  requestAnimationFrame(animate);
+}
+
+function renderState() {
+  const buzzer = state.buzzer_pattern === 0 ? "buzzer off" : `buzzer ${nameOf("buzzer", state.buzzer_pattern)}`;
+  $("state").textContent = isTower()
+    ? "Tiers aren't tracked, the light shows what was last sent"
+    : `${nameOf("color", state.color)} ${nameOf("led", state.pattern)}, ${buzzer}, volume ${state.volume}`;
+  $("silence").classList.toggle("sounding", state.buzzer_pattern !== 0);
+  started = performance.now();
+}
+
+async function loadPresets() {
+  const presets = await api("/v1/presets");
+  $("presets").replaceChildren(
+    ...presets.map((preset) => {
+      const button = document.createElement("button");
+      button.textContent = preset.name;
+      button.title = preset.action;
+      button.onclick = () => send(preset.command);
+      return button;
+    })
+  );
+  if (!presets.length) {
+    $("presets").textContent = "No presets configured";
+  }
+}
+
+async function loadDevices() {
+  try {
+    const devices = await api("/v1/devices");
+    $("devices").replaceChildren(
+      ...devices.map((device) => {
+        const row = document.createElement("tr");
+        for (const value of [device.model, device.serial_number || "", device.bus, device.address]) {
+          const cell = document.createElement("td");
+          cell.textContent = value;
+          row.append(cell);
+        }
+        return row;
+      })
+    );
+  } catch (e) {
+    $("devices").replaceChildren();
+    showError(`Failed to list devices: ${e.message}`);
+  }
+}
+
+function follow() {
+  events = new EventSource(`/v1/events?token=${encodeURIComponent(token)}`);
+  events.addEventListener("state", (event) => {
+    state = JSON.parse(event.data);
+    $("connection").textContent = "Connected";
+    renderState();
+  });
+  // EventSource reconnects by itself, e.g. after the daemon restarts
+  events.onerror = () => {
+    $("connection").textContent = "Reconnecting...";
+  };
+}
+
+async function connect() {
+  try {
+    controls = await api("/v1/controls");
+    profile = (await api("/v1/profile")).profile;
+  } catch (e) {
+    if (e.message !== "Access") {
+      logout(e.message);
+    }
+    return;
+  }
+  $("login").hidden = true;
+  $("panel").hidden = false;
+  $("model").textContent = profile ? profile.name : "Device";
+  fillSelect($("color"), "color", 1);
+  fillSelect($("pattern"), "led", 1);
+  fillSelect($("buzzer"), "buzzer", 1);
+  fillSelect($("volume"), "volume", 10);
+  renderTower();
+  follow();
+  loadPresets().catch((e) => showError(`Failed to load presets: ${e.message}`));
+  loadDevices();
+  setInterval(loadDevices, 10000);
+}
+
+function logout(message) {
+  localStorage.removeItem(TOKEN_KEY);
+  if (events) {
+    events.close();
+  }
+  $("panel").hidden = true;
+  $("login").hidden = false;
+  $("login-error").textContent = message || "";
+  $("connection").textContent = "Not connected";
+}
+
+$("login").onsubmit = (event) => {
+  event.preventDefault();
+  token = $("token").value;
+  localStorage.setItem(TOKEN_KEY, token);
+  connect();
+};
+
+const selected = (id) => Number($(id).value);
+$("light").onclick = () => send({ command: "light", color: selected("color"), pattern: selected("pattern") });
+$("buzz").onclick = () =>
+  send({ command: "buzz", pattern: selected("buzzer"), repetition: selected("repetition"), volume: selected("volume") });
+// Stop the buzzer and leave the light and volume as they are
+$("silence").onclick = () => send({ command: "buzz", pattern: 0, repetition: 0, volume: KEEP });
+$("off").onclick = () => send({ command: "off" });
+
+requestAnimationFrame(animate);
+if (token) {
+  connect();
+} else {
+  logout();
+}
@@ -0,0 +1,306 @@
+use patlite_rs::{set_blank, DeviceCommand, Transport};
+use regex::Regex;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How often the file is checked for new lines, rotation and truncation
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+// Volume used by `+ buzz` when the rule doesn't give one
+const DEFAULT_VOLUME: u8 = 10;
+
+// A rule of the form `REGEX -> ACTION`, e.g. `ERROR -> red blink + buzz`, where the action is
+// parsed by `DeviceCommand::parse_action`
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub regex: Regex,
+    pub command: DeviceCommand,
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, action) = s
+            .rsplit_once("->")
+            .ok_or_else(|| format!("Rule is missing `->`: {}", s))?;
+        let regex = Regex::new(pattern.trim()).map_err(|e| e.to_string())?;
+        let command = DeviceCommand::parse_action(action, DEFAULT_VOLUME)?;
+        command.validate()?;
+        Ok(Rule { regex, command })
+    }
+}
+
+pub struct WatchOptions {
+    pub rules: Vec<Rule>,
+    pub debounce: Duration,
+    pub clear_after: Option<Duration>,
+    pub from_start: bool,
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+// Follows a file like `tail -F`, reopening it when it is replaced (rotated) and rewinding when
+// it is truncated
+struct Follower {
+    path: PathBuf,
+    reader: BufReader<File>,
+    position: u64,
+    id: Option<u64>,
+    partial: String,
+}
+
+impl Follower {
+    fn open(path: &Path, from_start: bool) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let id = file_id(&file.metadata()?);
+        let mut reader = BufReader::new(file);
+        let position = if from_start {
+            0
+        } else {
+            reader.seek(SeekFrom::End(0))?
+        };
+        Ok(Follower {
+            path: path.to_path_buf(),
+            reader,
+            position,
+            id,
+            partial: String::new(),
+        })
+    }
+
+    fn read_lines(&mut self, lines: &mut Vec<String>) -> io::Result<()> {
+        loop {
+            let read = self.reader.read_line(&mut self.partial)?;
+            if read == 0 {
+                return Ok(());
+            }
+            self.position += read as u64;
+            // Keep an unterminated last line until the writer finishes it
+            if self.partial.ends_with('\n') {
+                lines.push(self.partial.trim_end().to_string());
+                self.partial.clear();
+            }
+        }
+    }
+
+    fn poll(&mut self) -> io::Result<Vec<String>> {
+        let mut lines = vec![];
+        self.read_lines(&mut lines)?;
+
+        // The file may be briefly missing while it is being rotated
+        let metadata = match fs::metadata(&self.path) {
+            Ok(m) => m,
+            Err(_) => return Ok(lines),
+        };
+        if file_id(&metadata) != self.id {
+            *self = Follower::open(&self.path, true)?;
+            self.read_lines(&mut lines)?;
+        } else if metadata.len() < self.position {
+            self.position = self.reader.seek(SeekFrom::Start(0))?;
+            self.partial.clear();
+            self.read_lines(&mut lines)?;
+        }
+        Ok(lines)
+    }
+}
+
+// When each rule last fired and when any rule last matched, to debounce the rules and clear the
+// light after a quiet period
+struct Alerts {
+    last_fired: Vec<Option<Instant>>,
+    last_match: Option<Instant>,
+    debounce: Duration,
+    clear_after: Option<Duration>,
+}
+
+impl Alerts {
+    fn new(options: &WatchOptions) -> Self {
+        Alerts {
+            last_fired: vec![None; options.rules.len()],
+            last_match: None,
+            debounce: options.debounce,
+            clear_after: options.clear_after,
+        }
+    }
+
+    // Whether rule `index` matching at `now` should be applied, not while it's debounced
+    fn matched(&mut self, index: usize, now: Instant) -> bool {
+        self.last_match = Some(now);
+        if self.last_fired[index].is_some_and(|t| now.duration_since(t) < self.debounce) {
+            return false;
+        }
+        self.last_fired[index] = Some(now);
+        true
+    }
+
+    // Whether the light should be turned off at `now`, once after each quiet period
+    fn quiet(&mut self, now: Instant) -> bool {
+        match (self.last_match, self.clear_after) {
+            (Some(t), Some(clear_after)) if now.duration_since(t) >= clear_after => {
+                self.last_match = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// Follow `path` forever, applying the first rule matching each new line to the light
+pub fn run(
+    handle: &mut dyn Transport,
+    path: &Path,
+    options: &WatchOptions,
+) -> rusb::Result<()> {
+    let mut follower = match Follower::open(path, options.from_start) {
+        Ok(f) => f,
+        Err(e) => {
+            println!("Failed to open {}: {}", path.display(), e);
+            return Err(rusb::Error::NotFound);
+        }
+    };
+    let mut alerts = Alerts::new(options);
+
+    loop {
+        let lines = match follower.poll() {
+            Ok(l) => l,
+            Err(e) => {
+                println!("Failed to read {}: {}", path.display(), e);
+                vec![]
+            }
+        };
+
+        for line in lines {
+            let Some(index) = options.rules.iter().position(|r| r.regex.is_match(&line)) else {
+                continue;
+            };
+            if !alerts.matched(index, Instant::now()) {
+                continue;
+            }
+
+            println!("{}", line);
+            if let Err(e) = options.rules[index].command.execute(handle) {
+                println!("Failed to send command: {:?}", e);
+            }
+        }
+
+        if alerts.quiet(Instant::now()) {
+            set_blank(handle)?;
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    // A log file of its own for each test
+    fn log_file(name: &str) -> PathBuf {
+        let path: PathBuf = std::env::temp_dir().join(format!("patlite-rs-{}-{}.log", name, std::process::id()));
+        fs::write(&path, "before\n").unwrap();
+        path
+    }
+
+    fn append(path: &Path, text: &str) {
+        OpenOptions::new().append(true).open(path).unwrap().write_all(text.as_bytes()).unwrap();
+    }
+
+    fn options(rules: &[&str], debounce: u64, clear_after: Option<u64>) -> WatchOptions {
+        WatchOptions {
+            rules: rules.iter().map(|rule| rule.parse().unwrap()).collect(),
+            debounce: Duration::from_secs(debounce),
+            clear_after: clear_after.map(Duration::from_secs),
+            from_start: false,
+        }
+    }
+
+    #[test]
+    fn follower_reads_only_new_and_finished_lines() {
+        let path: PathBuf = log_file("new-lines");
+        let mut follower: Follower = Follower::open(&path, false).unwrap();
+        append(&path, "one\ntw");
+        assert_eq!(follower.poll().unwrap(), ["one"]);
+        append(&path, "o\n");
+        assert_eq!(follower.poll().unwrap(), ["two"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn follower_rewinds_after_truncation() {
+        let path: PathBuf = log_file("truncated");
+        let mut follower: Follower = Follower::open(&path, true).unwrap();
+        assert_eq!(follower.poll().unwrap(), ["before"]);
+        fs::write(&path, "").unwrap();
+        append(&path, "after\n");
+        assert_eq!(follower.poll().unwrap(), ["after"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    // Rotation is told apart by the inode, which only Unix has
+    #[cfg(unix)]
+    #[test]
+    fn follower_reopens_a_rotated_file() {
+        let path: PathBuf = log_file("rotated");
+        let rotated: PathBuf = path.with_extension("log.1");
+        let mut follower: Follower = Follower::open(&path, false).unwrap();
+        append(&path, "last of the old file\n");
+        fs::rename(&path, &rotated).unwrap();
+        fs::write(&path, "first of the new file\n").unwrap();
+        assert_eq!(follower.poll().unwrap(), ["last of the old file", "first of the new file"]);
+        append(&path, "second\n");
+        assert_eq!(follower.poll().unwrap(), ["second"]);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&rotated).unwrap();
+    }
+
+    #[test]
+    fn rule_splits_at_the_last_arrow() {
+        let rule: Rule = "a -> b -> red blink + buzz".parse().unwrap();
+        assert!(rule.regex.is_match("a -> b"));
+        assert_eq!(rule.command, DeviceCommand::parse_action("red blink + buzz", DEFAULT_VOLUME).unwrap());
+    }
+
+    #[test]
+    fn malformed_rules_are_refused() {
+        assert_eq!("ERROR red".parse::<Rule>().unwrap_err(), "Rule is missing `->`: ERROR red");
+        assert!("(ERROR -> red".parse::<Rule>().is_err());
+        assert!("ERROR -> chartreuse".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn rules_are_debounced_each_on_their_own() {
+        let mut alerts = Alerts::new(&options(&["ERROR -> red", "WARN -> yellow"], 5, None));
+        let start: Instant = Instant::now();
+        assert!(alerts.matched(0, start));
+        assert!(!alerts.matched(0, start + Duration::from_secs(4)));
+        assert!(alerts.matched(1, start + Duration::from_secs(4)));
+        assert!(alerts.matched(0, start + Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn light_is_cleared_once_after_a_quiet_period() {
+        let mut alerts = Alerts::new(&options(&["ERROR -> red"], 0, Some(60)));
+        let start: Instant = Instant::now();
+        assert!(!alerts.quiet(start));
+        alerts.matched(0, start);
+        assert!(!alerts.quiet(start + Duration::from_secs(59)));
+        assert!(alerts.quiet(start + Duration::from_secs(60)));
+        assert!(!alerts.quiet(start + Duration::from_secs(120)));
+    }
+}
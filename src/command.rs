@@ -0,0 +1,258 @@
+use crate::constants::*;
+use crate::names::{parse_buzzer_pattern, parse_color, parse_led_pattern, parse_repetition, parse_volume};
+use crate::{Data, DeviceProfile, FrameFormat, TierMode, TierSelector, TierSetting, TowerState, Transport};
+use serde::{Deserialize, Serialize};
+
+// A single command for the device, the typed form of the CLI subcommands. Commands can be
+// deserialized from JSON objects tagged by `command`, e.g. `{"command": "light", "color": 1, "pattern": 1}`.
+// Omitted values default to 0, the same as the CLI
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum DeviceCommand {
+    Master {
+        #[serde(default)]
+        color: u8,
+        #[serde(default)]
+        color_pattern: u8,
+        #[serde(default)]
+        buzzer_pattern: u8,
+        #[serde(default)]
+        repetition: u8,
+        #[serde(default)]
+        volume: u8,
+    },
+    Light {
+        #[serde(default)]
+        color: u8,
+        #[serde(default)]
+        pattern: u8,
+    },
+    Buzz {
+        #[serde(default)]
+        pattern: u8,
+        #[serde(default)]
+        repetition: u8,
+        #[serde(default)]
+        volume: u8,
+    },
+    Volume {
+        #[serde(default)]
+        level: u8,
+    },
+    Off,
+    // Whether the device flashes white when it's connected to a host
+    #[serde(rename = "connection-display")]
+    ConnectionDisplay {
+        #[serde(default)]
+        on: bool,
+    },
+    // Tiers of a stacked tower by number from the top or by color, e.g.
+    // `{"command": "tiers", "tiers": [{"tier": 1, "mode": "on"}, {"tier": "blue", "mode": "blink"}]}`
+    Tiers {
+        tiers: Vec<TierSetting>,
+    },
+}
+
+// The tier mode closest to an LED pattern, every pattern that isn't steady blinks
+fn tier_mode(pattern: u8) -> TierMode {
+    match pattern {
+        LED_OFF => TierMode::Off,
+        LED_ON => TierMode::On,
+        LED_PATTERN_KEEP => TierMode::Keep,
+        _ => TierMode::Blink,
+    }
+}
+
+// Light the tier of `color` on a tower, or turn every tier off for the off color
+fn light_tower(tower: &mut TowerState, profile: &DeviceProfile, color: u8, pattern: u8) -> Result<(), String> {
+    match color {
+        LED_COLOR_KEEP => Ok(()),
+        LED_COLOR_OFF => {
+            *tower = TowerState { buzzer: tower.buzzer, ..TowerState::off(profile) };
+            Ok(())
+        }
+        color => tower.set(profile, TierSelector::Color(color), tier_mode(pattern)),
+    }
+}
+
+fn check_range(name: &str, value: u8) -> Result<(), String> {
+    if value > 0xF {
+        return Err(format!("{} {} is out of range 0-15", name, value));
+    }
+    Ok(())
+}
+
+impl DeviceCommand {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeviceCommand::Master { .. } => "master",
+            DeviceCommand::Light { .. } => "light",
+            DeviceCommand::Buzz { .. } => "buzz",
+            DeviceCommand::Volume { .. } => "volume",
+            DeviceCommand::Off => "off",
+            DeviceCommand::ConnectionDisplay { .. } => "connection-display",
+            DeviceCommand::Tiers { .. } => "tiers",
+        }
+    }
+
+    // Parse a short action such as `red blink`, `red pattern3 + strong-attention x3 vol 6` or
+    // `red blink + buzz`, in the form `COLOR [PATTERN] [+ [buzz] [BUZZERPATTERN] [xREPETITION] [vol VOLUME]]`.
+    // The pattern defaults to on. Without `+` the buzzer is left as it is, with it the buzzer
+    // defaults to continuous, 3 times, at `default_volume`
+    pub fn parse_action(action: &str, default_volume: u8) -> Result<Self, String> {
+        let (light, buzz) = match action.split_once('+') {
+            Some((light, buzz)) => (light, Some(buzz)),
+            None => (action, None),
+        };
+
+        let mut light = light.split_whitespace();
+        let color = parse_color(light.next().ok_or("Action is missing a color")?)?;
+        let color_pattern = light.next().map_or(Ok(LED_ON), parse_led_pattern)?;
+        if let Some(extra) = light.next() {
+            return Err(format!("Unexpected `{}` in action: {}", extra, action));
+        }
+
+        let Some(buzz) = buzz else {
+            return Ok(DeviceCommand::Master {
+                color,
+                color_pattern,
+                buzzer_pattern: BUZZER_KEEP,
+                repetition: BUZZER_COUNT_KEEP,
+                volume: BUZZER_VOLUME_KEEP,
+            });
+        };
+
+        let (mut buzzer_pattern, mut repetition, mut volume) = (None, None, None);
+        let mut words = buzz.split_whitespace().peekable();
+        if words.peek().is_some_and(|w| w.eq_ignore_ascii_case("buzz")) {
+            words.next();
+        }
+        while let Some(word) = words.next() {
+            let lower = word.to_lowercase();
+            if lower == "vol" || lower == "volume" {
+                let level = words.next().ok_or("Action is missing a volume after `vol`")?;
+                volume = Some(parse_volume(level)?);
+            } else if let Some(times) = lower.strip_prefix('x').filter(|t| !t.is_empty()) {
+                repetition = Some(parse_repetition(times)?);
+            } else if buzzer_pattern.is_none() {
+                buzzer_pattern = Some(parse_buzzer_pattern(word)?);
+            } else if repetition.is_none() {
+                repetition = Some(parse_repetition(word)?);
+            } else if volume.is_none() {
+                volume = Some(parse_volume(word)?);
+            } else {
+                return Err(format!("Unexpected `{}` in action: {}", word, action));
+            }
+        }
+
+        Ok(DeviceCommand::Master {
+            color,
+            color_pattern,
+            buzzer_pattern: buzzer_pattern.unwrap_or(BUZZER_ON),
+            repetition: repetition.unwrap_or(3),
+            volume: volume.unwrap_or(default_volume),
+        })
+    }
+
+    // Every value is packed into 4 bits of the frame
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            DeviceCommand::Master { color, color_pattern, buzzer_pattern, repetition, volume } => {
+                check_range("color", *color)?;
+                check_range("color_pattern", *color_pattern)?;
+                check_range("buzzer_pattern", *buzzer_pattern)?;
+                check_range("repetition", *repetition)?;
+                check_range("volume", *volume)
+            }
+            DeviceCommand::Light { color, pattern } => {
+                check_range("color", *color)?;
+                check_range("pattern", *pattern)
+            }
+            DeviceCommand::Buzz { pattern, repetition, volume } => {
+                check_range("pattern", *pattern)?;
+                check_range("repetition", *repetition)?;
+                check_range("volume", *volume)
+            }
+            DeviceCommand::Volume { level } => check_range("level", *level),
+            DeviceCommand::Off | DeviceCommand::ConnectionDisplay { .. } => Ok(()),
+            DeviceCommand::Tiers { tiers } if tiers.is_empty() => Err("tiers needs at least one tier".to_string()),
+            DeviceCommand::Tiers { .. } => Ok(()),
+        }
+    }
+
+    // The frame of the NE-SN-USB, see `encode` for other models. Fails for tiers it doesn't have
+    pub fn to_data(&self) -> Result<Data, String> {
+        match self {
+            DeviceCommand::Master { color, color_pattern, buzzer_pattern, repetition, volume } => {
+                let mut data = Data::default();
+                data.set_alarm_control((repetition & 0x0F) << 4 | (buzzer_pattern & 0x0F));
+                data.set_led_control((color & 0x0F) << 4 | (color_pattern & 0x0F));
+                data.set_volume(*volume);
+                Ok(data)
+            }
+            DeviceCommand::Light { color, pattern } => {
+                let mut data = Data::default();
+                data.set_led_control((color & 0x0F) << 4 | (pattern & 0x0F));
+                Ok(data)
+            }
+            DeviceCommand::Buzz { pattern, repetition, volume } => {
+                let mut data = Data::default();
+                data.set_alarm_control((repetition & 0x0F) << 4 | (pattern & 0x0F));
+                data.set_volume(*volume);
+                Ok(data)
+            }
+            DeviceCommand::Volume { level } => {
+                let mut data = Data::default();
+                data.set_volume(*level);
+                Ok(data)
+            }
+            DeviceCommand::Off => Ok(Data::blank()),
+            DeviceCommand::ConnectionDisplay { on } => Ok(Data::connection_display(*on)),
+            DeviceCommand::Tiers { .. } => self.encode(&DeviceProfile::ne_usb()),
+        }
+    }
+
+    // The frame for this command in the frame format of `profile`. On towers the light sets the
+    // tier of its color, and the volume is left out since they have no volume control
+    pub fn encode(&self, profile: &DeviceProfile) -> Result<Data, String> {
+        if profile.frame_format == FrameFormat::NeUsb && !matches!(self, DeviceCommand::Tiers { .. }) {
+            return self.to_data();
+        }
+
+        let mut tower = TowerState::new(profile);
+        match self {
+            DeviceCommand::Master { color, color_pattern, buzzer_pattern, repetition, .. } => {
+                tower.buzzer = (repetition & 0x0F) << 4 | (buzzer_pattern & 0x0F);
+                light_tower(&mut tower, profile, *color, *color_pattern)?;
+            }
+            DeviceCommand::Light { color, pattern } => light_tower(&mut tower, profile, *color, *pattern)?,
+            DeviceCommand::Buzz { pattern, repetition, .. } => {
+                tower.buzzer = (repetition & 0x0F) << 4 | (pattern & 0x0F);
+            }
+            DeviceCommand::Volume { .. } => return Err(format!("{} has no volume control", profile.name)),
+            DeviceCommand::Off => tower = TowerState { buzzer: BUZZER_OFF, ..TowerState::off(profile) },
+            DeviceCommand::ConnectionDisplay { .. } => {
+                return Err(format!("{} has no connection display setting", profile.name))
+            }
+            DeviceCommand::Tiers { tiers } => {
+                for setting in tiers {
+                    tower.set(profile, setting.tier, setting.mode)?;
+                }
+            }
+        }
+        profile.encode(&tower)
+    }
+
+    // The frame for this command on a device of `profile`, that of the NE-SN-USB without one
+    pub fn frame(&self, profile: Option<&DeviceProfile>) -> rusb::Result<Data> {
+        self.encode(profile.unwrap_or(&DeviceProfile::ne_usb())).map_err(|e| {
+            eprintln!("Unsupported command: {}", e);
+            rusb::Error::NotSupported
+        })
+    }
+
+    // Carry out this command on the device behind `transport`, see `Transport::execute`
+    pub fn execute<T: Transport + ?Sized>(&self, transport: &mut T) -> rusb::Result<usize> {
+        transport.execute(self)
+    }
+}
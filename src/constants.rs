@@ -8,6 +8,9 @@ pub const COMMAND_VERSION: u8 = 0x0;
 // Command ID
 pub const COMMAND_ID_CONTROL: u8 = 0x0;
 pub const COMMAND_ID_SETTING: u8 = 0x1;
+// 0x80 reads from the device on ENDPOINT_ADDRESS_GET, but the protocol notes below don't describe
+// its reply and nothing says it holds the light, buzzer or connection display settings. Until
+// that's known the state is tracked from the frames sent instead, see `DeviceState`
 // pub const COMMAND_ID_GETSTATE: u8 = 0x80;
 
 // Endpoint address for sending to host -> USB controlled multicolor indicator
@@ -29,38 +32,42 @@ pub const LED_COLOR_WHITE: u8 = 7; // White
 pub const LED_COLOR_KEEP: u8 = 0xF; // Keep the current settings
 
 // LED pattern
-// pub const LED_OFF: u8 = 0x0; // Off
-// pub const LED_ON: u8 = 0x1; // On
-// pub const LED_PATTERN1: u8 = 0x2; // LED pattern1
-// pub const LED_PATTERN2: u8 = 0x3; // LED pattern2
-// pub const LED_PATTERN3: u8 = 0x4; // LED pattern3
-// pub const LED_PATTERN4: u8 = 0x5; // LED pattern4
-// pub const LED_PATTERN5: u8 = 0x6; // LED pattern5
-// pub const LED_PATTERN6: u8 = 0x7; // LED pattern6
+pub const LED_OFF: u8 = 0x0; // Off
+pub const LED_ON: u8 = 0x1; // On
+pub const LED_PATTERN1: u8 = 0x2; // LED pattern1
+pub const LED_PATTERN2: u8 = 0x3; // LED pattern2
+pub const LED_PATTERN3: u8 = 0x4; // LED pattern3
+pub const LED_PATTERN4: u8 = 0x5; // LED pattern4
+pub const LED_PATTERN5: u8 = 0x6; // LED pattern5
+pub const LED_PATTERN6: u8 = 0x7; // LED pattern6
 pub const LED_PATTERN_KEEP: u8 = 0xF; // Keep the current settings
 
+// Default baud rate of the experimental serial transport
+pub const SERIAL_BAUD_RATE: u32 = 9600;
+
 // Number of buzzers
-// pub const BUZZER_COUNT_CONTINUE: u8 = 0x0; // Continuous operation
+pub const BUZZER_COUNT_CONTINUE: u8 = 0x0; // Continuous operation
+pub const BUZZER_COUNT_MAX: u8 = 0xE; // Most repetitions before the buzzer stops
 pub const BUZZER_COUNT_KEEP: u8 = 0xF; // Keep the current settings
 
 // Buzzer pattern
-// pub const BUZZER_OFF: u8 = 0x0; // Stop
-// pub const BUZZER_ON: u8 = 0x1; // Blow (continuous)
-// pub const BUZZER_SWEEP: u8 = 0x2; // Sweep sound
-// pub const BUZZER_INTERMITTENT: u8 = 0x3; // Intermittent sound
-// pub const BUZZER_WEEK_ATTENTION: u8 = 0x4; // Weak caution sound
-// pub const BUZZER_STRONG_ATTENTION: u8 = 0x5; // Strong attention sound
-// pub const BUZZER_SHINING_STAR: u8 = 0x6; // shining star
-// pub const BUZZER_LONDON_BRIDGE: u8 = 0x7; // London bridge
+pub const BUZZER_OFF: u8 = 0x0; // Stop
+pub const BUZZER_ON: u8 = 0x1; // Blow (continuous)
+pub const BUZZER_SWEEP: u8 = 0x2; // Sweep sound
+pub const BUZZER_INTERMITTENT: u8 = 0x3; // Intermittent sound
+pub const BUZZER_WEEK_ATTENTION: u8 = 0x4; // Weak caution sound
+pub const BUZZER_STRONG_ATTENTION: u8 = 0x5; // Strong attention sound
+pub const BUZZER_SHINING_STAR: u8 = 0x6; // shining star
+pub const BUZZER_LONDON_BRIDGE: u8 = 0x7; // London bridge
 pub const BUZZER_KEEP: u8 = 0xF; // Keep the current settings
 
 // Buzzer volume
-// pub const BUZZER_VOLUME_OFF: u8 = 0x0; // Mute
-// pub const BUZZER_VOLUME_MAX: u8 = 0xA; // Maximum volume
+pub const BUZZER_VOLUME_OFF: u8 = 0x0; // Mute
+pub const BUZZER_VOLUME_MAX: u8 = 0xA; // Maximum volume
 pub const BUZZER_VOLUME_KEEP: u8 = 0xF; // Keep the current settings
 
 // Setting
-// pub const SETTING_OFF: u8 = 0x0; // OFF
+pub const SETTING_OFF: u8 = 0x0; // OFF
 pub const SETTING_ON: u8 = 0x1; // ON
 
 // others
@@ -0,0 +1,154 @@
+use crate::constants::*;
+use crate::{get_buzzer_pattern, get_led_color, get_led_pattern, Data};
+
+fn repetition_name(count: u8) -> String {
+    match count {
+        BUZZER_COUNT_CONTINUE => "continuous".to_string(),
+        BUZZER_COUNT_KEEP => "keep".to_string(),
+        count => format!("x{}", count),
+    }
+}
+
+fn volume_name(volume: u8) -> String {
+    match volume {
+        0..=BUZZER_VOLUME_MAX => volume.to_string(),
+        _ => "keep".to_string(),
+    }
+}
+
+// Bytes as two digit hex separated by spaces, e.g. `00 00 01 06 11 00 00 00`
+pub fn bytes_to_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// The reverse of `bytes_to_hex`
+pub fn hex_to_bytes(hex: &str) -> Result<Vec<u8>, String> {
+    hex.split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16).map_err(|_| format!("Not a hex byte: {}", byte)))
+        .collect()
+}
+
+impl Data {
+    pub fn to_hex(&self) -> String {
+        bytes_to_hex(&self.to_array())
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Data, String> {
+        let bytes: [u8; 8] = hex_to_bytes(hex)?
+            .try_into()
+            .map_err(|bytes: Vec<u8>| format!("A frame is 8 bytes, got {}", bytes.len()))?;
+        Ok(Data::from_array(bytes))
+    }
+
+    // Fields of the frame decoded the way the device reads them (see the protocol in `constants`)
+    pub fn describe(&self) -> String {
+        match self.command_id {
+            COMMAND_ID_CONTROL => format!(
+                "control: color {}, pattern {}, buzzer {}, repeat {}, volume {}",
+                get_led_color(self.led_control >> 4),
+                get_led_pattern(self.led_control & 0x0F),
+                get_buzzer_pattern(self.alarm_control & 0x0F),
+                repetition_name(self.alarm_control >> 4),
+                volume_name(self.volume & 0x0F),
+            ),
+            COMMAND_ID_SETTING => format!(
+                "setting: connection display {}",
+                match self.alarm_control {
+                    SETTING_OFF => "off",
+                    SETTING_ON => "on",
+                    _ => "invalid",
+                },
+            ),
+            id => format!("unknown command id {:#04x}", id),
+        }
+    }
+}
+
+impl Data {
+    // Check the frame against the protocol, naming the first byte that doesn't fit. Values in the
+    // "maintain current status" ranges are valid, only the fixed bytes and unknown ids are not
+    pub fn validate(&self) -> Result<(), String> {
+        if self.command_version != COMMAND_VERSION {
+            return Err(format!("command version must be {:#04x}, got {:#04x}", COMMAND_VERSION, self.command_version));
+        }
+        match self.command_id {
+            COMMAND_ID_CONTROL => {
+                if self.volume >> 4 != BLANK {
+                    return Err(format!("upper bits of the volume byte must be 0, got {:#04x}", self.volume));
+                }
+            }
+            COMMAND_ID_SETTING => {
+                if self.alarm_control > SETTING_ON {
+                    return Err(format!("connection display setting must be 0 or 1, got {:#04x}", self.alarm_control));
+                }
+                if self.volume != BLANK || self.led_control != BLANK {
+                    return Err("bytes 4 and 5 of a setting command must be 0".to_string());
+                }
+            }
+            id => return Err(format!("unknown command id {:#04x}", id)),
+        }
+        if [self.reserved_first, self.reserved_second, self.reserved_third] != [BLANK; 3] {
+            return Err("bytes 6 to 8 must be 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_round_trips() {
+        let data: Data = Data::from_hex("00 00 01 06 11 00 00 00").unwrap();
+        assert_eq!(data.to_array(), [0x00, 0x00, 0x01, 0x06, 0x11, 0x00, 0x00, 0x00]);
+        assert_eq!(data.to_hex(), "00 00 01 06 11 00 00 00");
+        assert_eq!(hex_to_bytes("  ff\t0a "), Ok(vec![0xFF, 0x0A]));
+    }
+
+    #[test]
+    fn malformed_hex_is_refused() {
+        assert_eq!(hex_to_bytes("00 0g"), Err("Not a hex byte: 0g".to_string()));
+        assert_eq!(hex_to_bytes("100"), Err("Not a hex byte: 100".to_string()));
+        assert!(Data::from_hex("00 00 01 06 11 00 00 zz").is_err());
+    }
+
+    #[test]
+    fn frames_of_the_wrong_length_are_refused() {
+        assert_eq!(Data::from_hex("00 00 01 06 11 00 00"), Err("A frame is 8 bytes, got 7".to_string()));
+        assert_eq!(Data::from_hex("00 00 01 06 11 00 00 00 00"), Err("A frame is 8 bytes, got 9".to_string()));
+        assert_eq!(Data::from_hex(""), Err("A frame is 8 bytes, got 0".to_string()));
+    }
+
+    #[test]
+    fn frames_are_described_field_by_field() {
+        assert_eq!(
+            Data::from_hex("00 00 11 0F 11 00 00 00").unwrap().describe(),
+            "control: color Red, pattern On, buzzer Continuous, repeat x1, volume keep"
+        );
+        assert_eq!(Data::from_hex("00 01 01 00 00 00 00 00").unwrap().describe(), "setting: connection display on");
+        assert_eq!(Data::from_hex("00 07 00 00 00 00 00 00").unwrap().describe(), "unknown command id 0x07");
+    }
+
+    #[test]
+    fn frames_breaking_the_protocol_are_invalid() {
+        assert_eq!(Data::from_hex("00 00 01 06 11 00 00 00").unwrap().validate(), Ok(()));
+        assert_eq!(
+            Data::from_hex("01 00 01 06 11 00 00 00").unwrap().validate(),
+            Err("command version must be 0x00, got 0x01".to_string())
+        );
+        assert_eq!(
+            Data::from_hex("00 00 01 16 11 00 00 00").unwrap().validate(),
+            Err("upper bits of the volume byte must be 0, got 0x16".to_string())
+        );
+        assert_eq!(
+            Data::from_hex("00 01 02 00 00 00 00 00").unwrap().validate(),
+            Err("connection display setting must be 0 or 1, got 0x02".to_string())
+        );
+        assert_eq!(Data::from_hex("00 00 01 06 11 00 00 01").unwrap().validate(), Err("bytes 6 to 8 must be 0".to_string()));
+    }
+}
@@ -1,10 +1,42 @@
-mod constants;
-
+mod command;
+pub mod constants;
+mod frame;
+pub mod names;
+mod network;
+#[cfg(feature = "async")]
+mod patlite;
+mod profile;
+mod recording;
+#[cfg(feature = "serial")]
+mod serial;
+mod simulator;
+mod state;
+mod transport;
+mod tower;
+mod usb;
+
+pub use command::DeviceCommand;
+pub use state::DeviceState;
+pub use frame::{bytes_to_hex, hex_to_bytes};
+pub use network::{pns_frame, pns_packet, Emulator, PnsTransport, PNS_PORT};
+#[cfg(feature = "async")]
+pub use patlite::{Patlite, Step};
+pub use profile::{DeviceProfile, FrameFormat, ProfileRegistry};
+pub use recording::{error_from_name, read_recording, Direction, RecordedFrame, Recorder, Replayer};
+#[cfg(feature = "serial")]
+pub use serial::SerialTransport;
+pub use constants::SERIAL_BAUD_RATE;
+pub use simulator::Simulator;
+pub use tower::{Tier, TierMode, TierSelector, TierSetting, TowerState};
+pub use transport::{DryRun, Trace, Transport};
+pub use usb::{RetryPolicy, UsbTransport, RETRYABLE_ERRORS};
+pub use names::{parse_buzzer_pattern, parse_color, parse_led_pattern, parse_repetition, parse_volume};
 
 use constants::*;
 use rusb::{Context, Device, DeviceHandle, Result, UsbContext};
 use std::time::Duration;
 use std::fmt;
+use serde::Serialize;
 use tabled::{builder::Builder, settings::Style};
 
 #[derive(Debug)]
@@ -46,7 +78,7 @@ impl fmt::Display for LEDColors {
     }
 }
 
-fn get_led_color(color: u8) -> LEDColors {
+pub fn get_led_color(color: u8) -> LEDColors {
     match color {
         LED_COLOR_OFF => LEDColors::Off,
         LED_COLOR_RED => LEDColors::Red,
@@ -62,6 +94,97 @@ fn get_led_color(color: u8) -> LEDColors {
     }
 }
 
+#[derive(Debug)]
+pub enum LEDPatterns {
+    Off,
+    On,
+    Pattern1,
+    Pattern2,
+    Pattern3,
+    Pattern4,
+    Pattern5,
+    Pattern6,
+    Keep,
+}
+
+impl fmt::Display for LEDPatterns {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pattern_str = match self {
+            LEDPatterns::Off => "Off",
+            LEDPatterns::On => "On",
+            LEDPatterns::Pattern1 => "Pattern1",
+            LEDPatterns::Pattern2 => "Pattern2",
+            LEDPatterns::Pattern3 => "Pattern3",
+            LEDPatterns::Pattern4 => "Pattern4",
+            LEDPatterns::Pattern5 => "Pattern5",
+            LEDPatterns::Pattern6 => "Pattern6",
+            LEDPatterns::Keep => "Keep",
+        };
+        write!(f, "{}", pattern_str)
+    }
+}
+
+pub fn get_led_pattern(pattern: u8) -> LEDPatterns {
+    match pattern {
+        LED_OFF => LEDPatterns::Off,
+        LED_ON => LEDPatterns::On,
+        LED_PATTERN1 => LEDPatterns::Pattern1,
+        LED_PATTERN2 => LEDPatterns::Pattern2,
+        LED_PATTERN3 => LEDPatterns::Pattern3,
+        LED_PATTERN4 => LEDPatterns::Pattern4,
+        LED_PATTERN5 => LEDPatterns::Pattern5,
+        LED_PATTERN6 => LEDPatterns::Pattern6,
+        0x8..=0xF => LEDPatterns::Keep,
+        _ => LEDPatterns::Off,
+    }
+}
+
+#[derive(Debug)]
+pub enum BuzzerPatterns {
+    Off,
+    Continuous,
+    Sweep,
+    Intermittent,
+    WeakAttention,
+    StrongAttention,
+    ShiningStar,
+    LondonBridge,
+    Keep,
+}
+
+impl fmt::Display for BuzzerPatterns {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pattern_str = match self {
+            BuzzerPatterns::Off => "Off",
+            BuzzerPatterns::Continuous => "Continuous",
+            BuzzerPatterns::Sweep => "Sweep",
+            BuzzerPatterns::Intermittent => "Intermittent",
+            BuzzerPatterns::WeakAttention => "WeakAttention",
+            BuzzerPatterns::StrongAttention => "StrongAttention",
+            BuzzerPatterns::ShiningStar => "ShiningStar",
+            BuzzerPatterns::LondonBridge => "LondonBridge",
+            BuzzerPatterns::Keep => "Keep",
+        };
+        write!(f, "{}", pattern_str)
+    }
+}
+
+pub fn get_buzzer_pattern(pattern: u8) -> BuzzerPatterns {
+    match pattern {
+        BUZZER_OFF => BuzzerPatterns::Off,
+        BUZZER_ON => BuzzerPatterns::Continuous,
+        BUZZER_SWEEP => BuzzerPatterns::Sweep,
+        BUZZER_INTERMITTENT => BuzzerPatterns::Intermittent,
+        BUZZER_WEEK_ATTENTION => BuzzerPatterns::WeakAttention,
+        BUZZER_STRONG_ATTENTION => BuzzerPatterns::StrongAttention,
+        BUZZER_SHINING_STAR => BuzzerPatterns::ShiningStar,
+        BUZZER_LONDON_BRIDGE => BuzzerPatterns::LondonBridge,
+        0x8..=0xF => BuzzerPatterns::Keep,
+        _ => BuzzerPatterns::Off,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Data {
   pub command_version: u8,
   pub command_id: u8,
@@ -73,8 +196,8 @@ pub struct Data {
   pub reserved_third: u8,
 }
 
-impl Data {
-    pub fn default() -> Self {
+impl Default for Data {
+    fn default() -> Self {
         Data {
             command_version: COMMAND_VERSION,
             command_id: COMMAND_ID_CONTROL,
@@ -86,6 +209,9 @@ impl Data {
             reserved_third: BLANK,
         }
     }
+}
+
+impl Data {
     pub fn blank() -> Self {
         Data {
             command_version: COMMAND_VERSION,
@@ -98,11 +224,16 @@ impl Data {
             reserved_third: BLANK,
         }
     }
+    // Turns the connection display on, see `connection_display`
     pub fn settings() -> Self {
+        Data::connection_display(true)
+    }
+    // Whether the device flashes white when it's connected to a host
+    pub fn connection_display(on: bool) -> Self {
         Data {
             command_version: COMMAND_VERSION,
             command_id: COMMAND_ID_SETTING,
-            alarm_control: SETTING_ON, // or SETTING_OFF
+            alarm_control: if on { SETTING_ON } else { SETTING_OFF },
             volume: BLANK,
             led_control: BLANK,
             reserved_first: BLANK,
@@ -295,16 +426,39 @@ impl Data {
 
 
 pub fn setup_device() -> Result<DeviceHandle<rusb::Context>> {
+	setup_device_with_serial(None)
+}
+
+// Open and claim the device with the given serial number, or the first device found for `None`
+pub fn setup_device_with_serial(serial: Option<&str>) -> Result<DeviceHandle<rusb::Context>> {
+	setup_endpoint_with_serial(serial).map(|(handle, _)| handle)
+}
+
+// Like `setup_device_with_serial`, also returning the endpoint that was claimed
+pub fn setup_endpoint_with_serial(serial: Option<&str>) -> Result<(DeviceHandle<rusb::Context>, Endpoint)> {
+	setup_registered_device(&ProfileRegistry::default(), serial).map(|(handle, endpoint, _)| (handle, endpoint))
+}
+
+// Open and claim the device with the given serial number, or the first device found for `None`,
+// of any model in `registry`. Returns the claimed endpoint and the model's profile
+pub fn setup_registered_device(
+	registry: &ProfileRegistry,
+	serial: Option<&str>,
+) -> Result<(DeviceHandle<rusb::Context>, Endpoint, DeviceProfile)> {
 	let mut context: Context = match Context::new() {
 			Ok(c) => c,
 			Err(_) => {
-					println!("Failed to create USB context");
+					eprintln!("Failed to create USB context");
+					return Err(rusb::Error::NotFound);
+			}
+	};
+	let (mut device, mut handle, profile) = match open_registered_device(&mut context, registry, serial) {
+			Some(d) => d,
+			None => {
+					eprintln!("Failed to open USB device");
 					return Err(rusb::Error::NotFound);
 			}
 	};
-	let (mut device, mut handle) =
-			open_device(&mut context, VENDOR_ID, DEVICE_ID)
-					.expect("Failed to open USB device");
 	// check if device exists and hasn't been claimed
 	// if device.is_some() {
 	//     let (device, handle) = device.unwrap();
@@ -313,28 +467,64 @@ pub fn setup_device() -> Result<DeviceHandle<rusb::Context>> {
 	let endpoints: Vec<Endpoint> = match find_readable_endpoints(&mut device) {
 			Ok(endpoints) => endpoints,
 			Err(e) => {
-					println!("Failed to find readable endpoints: {:?}", e);
+					eprintln!("Failed to find readable endpoints: {:?}", e);
 					return Err(rusb::Error::NotFound);
 			}
 	};
 
 	// println!("Endpoints: {:#?}", endpoints);
-	let endpoint: &Endpoint = endpoints
-			.first()
-			.expect("No Configurable endpoint found on device");
+	let endpoint: Endpoint = match endpoints.into_iter().next() {
+			Some(e) => e,
+			None => {
+					eprintln!("No Configurable endpoint found on device");
+					return Err(rusb::Error::NotFound);
+			}
+	};
 	// get endpoint with address 0x01
 	// let endpoint = endpoints.iter().find(|e| e.address == ENDPOINT_ADDRESS_GET).expect("No Configurable endpoint found on device");
 	// println!("Endpoint: {:#?}", endpoint);
 	// claim and configure device
-	let _endpoint_config = match configure_endpoint(&mut handle, endpoint) {
-			Ok(_) => (),
-			Err(e) => {
-					println!("Failed to configure endpoint: {:?}", e);
-					return Err(rusb::Error::NotFound);
+	if let Err(e) = configure_endpoint(&mut handle, &endpoint) {
+			eprintln!("Failed to configure endpoint: {:?}", e);
+			return Err(e);
+	}
+
+	Ok((handle, endpoint, profile))
+}
+
+// The first device of a model in `registry`, with the given serial number if there is one
+pub fn open_registered_device<T: UsbContext>(
+	context: &mut T,
+	registry: &ProfileRegistry,
+	serial: Option<&str>,
+) -> Option<(Device<T>, DeviceHandle<T>, DeviceProfile)> {
+	let devices: rusb::DeviceList<T> = context.devices().ok()?;
+
+	for device in devices.iter() {
+			let device_desc: rusb::DeviceDescriptor = match device.device_descriptor() {
+					Ok(d) => d,
+					Err(_) => continue,
+			};
+			if device_desc.vendor_id() != VENDOR_ID {
+					continue;
 			}
-	};
+			let Some(profile) = registry.find(device_desc.product_id()) else {
+					continue;
+			};
+			let mut handle = match device.open() {
+					Ok(h) => h,
+					Err(_) => continue,
+			};
+			if let Some(serial) = serial {
+					match read_device_info(&mut handle) {
+							Ok(info) if info.serial_number.as_deref() == Some(serial) => (),
+							_ => continue,
+					}
+			}
+			return Some((device, handle, profile.clone()));
+	}
 
-	Ok(handle)
+	None
 }
 
 pub fn open_device<T: UsbContext>(
@@ -364,30 +554,85 @@ pub fn open_device<T: UsbContext>(
 	None
 }
 
-pub fn print_device_info<T: UsbContext>(
-	handle: &mut DeviceHandle<T>,
-	table_builder: &mut Builder,
-) -> Result<()> {
+pub fn open_device_with_serial<T: UsbContext>(
+	context: &mut T,
+	vid: u16,
+	pid: u16,
+	serial: &str,
+) -> Option<(Device<T>, DeviceHandle<T>)> {
+	let devices: rusb::DeviceList<T> = match context.devices() {
+			Ok(d) => d,
+			Err(_) => return None,
+	};
+
+	for device in devices.iter() {
+			let device_desc: rusb::DeviceDescriptor = match device.device_descriptor() {
+					Ok(d) => d,
+					Err(_) => continue,
+			};
+			if device_desc.vendor_id() != vid || device_desc.product_id() != pid {
+					continue;
+			}
+			let mut handle = match device.open() {
+					Ok(h) => h,
+					Err(_) => continue,
+			};
+			match read_device_info(&mut handle) {
+					Ok(info) if info.serial_number.as_deref() == Some(serial) => return Some((device, handle)),
+					_ => continue,
+			}
+	}
+
+	None
+}
+
+// String descriptors of an opened device, read in its first supported language
+#[derive(Debug, Serialize)]
+pub struct DeviceInfo {
+    pub language_id: Option<u16>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+}
+
+pub fn read_device_info<T: UsbContext>(handle: &mut DeviceHandle<T>) -> Result<DeviceInfo> {
 	let device_desc = handle.device().device_descriptor()?;
 	let timeout = Duration::from_secs(1);
 	let languages = handle.read_languages(timeout)?;
 
-	// println!("Active configuration: {}", handle.active_configuration()?);
+	let language: rusb::Language = match languages.first() {
+			Some(l) => *l,
+			None => {
+					return Ok(DeviceInfo {
+							language_id: None,
+							manufacturer: None,
+							product: None,
+							serial_number: None,
+					})
+			}
+	};
+	Ok(DeviceInfo {
+			language_id: Some(language.lang_id()),
+			manufacturer: handle.read_manufacturer_string(language, &device_desc, timeout).ok(),
+			product: handle.read_product_string(language, &device_desc, timeout).ok(),
+			serial_number: handle.read_serial_number_string(language, &device_desc, timeout).ok(),
+	})
+}
+
+pub fn print_device_info<T: UsbContext>(
+	handle: &mut DeviceHandle<T>,
+	table_builder: &mut Builder,
+) -> Result<()> {
+	let info: DeviceInfo = read_device_info(handle)?;
+	let not_found = |s: Option<String>| s.unwrap_or("Not Found".to_string());
 
-	if !languages.is_empty() {
-			let language: rusb::Language = languages[0];
+	if let Some(language_id) = info.language_id {
 			table_builder.push_record(["Language", "Manufacturer", "Product", "Serial Number"]);
 			table_builder.push_record([
-					format!("{:?}", language),
-					handle
-							.read_manufacturer_string(language, &device_desc, timeout)
-							.unwrap_or("Not Found".to_string()),
-					handle
-							.read_product_string(language, &device_desc, timeout)
-							.unwrap_or("Not Found".to_string()),
-					handle
-							.read_serial_number_string(language, &device_desc, timeout)
-							.unwrap_or("Not Found".to_string()),
+					format!("{:#06x}", language_id),
+					not_found(info.manufacturer),
+					not_found(info.product),
+					not_found(info.serial_number),
 			]);
 			let table: String = table_builder
 					.clone()
@@ -399,6 +644,58 @@ pub fn print_device_info<T: UsbContext>(
 	Ok(())
 }
 
+// A connected Patlite device, as found on the bus without claiming it
+#[derive(Debug, Serialize)]
+pub struct DeviceSummary {
+    pub bus: u8,
+    pub address: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub model: String,
+    pub serial_number: Option<String>,
+}
+
+pub fn list_devices() -> Result<Vec<DeviceSummary>> {
+	list_registered_devices(&ProfileRegistry::default())
+}
+
+// Connected devices of every model in `registry`
+pub fn list_registered_devices(registry: &ProfileRegistry) -> Result<Vec<DeviceSummary>> {
+	let context: Context = Context::new()?;
+	let timeout = Duration::from_secs(1);
+	let mut summaries: Vec<DeviceSummary> = vec![];
+
+	for device in context.devices()?.iter() {
+			let device_desc: rusb::DeviceDescriptor = match device.device_descriptor() {
+					Ok(d) => d,
+					Err(_) => continue,
+			};
+			if device_desc.vendor_id() != VENDOR_ID {
+					continue;
+			}
+			let Some(profile) = registry.find(device_desc.product_id()) else {
+					continue;
+			};
+			// Reading the serial number needs the device to be opened, which may not be permitted
+			let serial_number: Option<String> = device.open().ok().and_then(|handle| {
+					let language = *handle.read_languages(timeout).ok()?.first()?;
+					handle
+							.read_serial_number_string(language, &device_desc, timeout)
+							.ok()
+			});
+			summaries.push(DeviceSummary {
+					bus: device.bus_number(),
+					address: device.address(),
+					vendor_id: device_desc.vendor_id(),
+					product_id: device_desc.product_id(),
+					model: profile.name.clone(),
+					serial_number,
+			});
+	}
+
+	Ok(summaries)
+}
+
 pub fn find_readable_endpoints<T: UsbContext>(device: &mut Device<T>) -> Result<Vec<Endpoint>> {
 	let device_desc: rusb::DeviceDescriptor = device.device_descriptor()?;
 	let mut endpoints: Vec<Endpoint> = vec![];
@@ -456,8 +753,8 @@ pub fn read_interrupt<T: UsbContext>(handle: &mut DeviceHandle<T>) {
 	print!("Read interrupt: {:?}", res);
 }
 
-pub fn set_master_controls_command(
-	handle: &mut DeviceHandle<rusb::Context>,
+pub fn set_master_controls_command<T: Transport + ?Sized>(
+	handle: &mut T,
 	color: &u8,
 	color_pattern: &u8,
 	buzzer_pattern: &u8,
@@ -465,16 +762,16 @@ pub fn set_master_controls_command(
 	volume: &u8,
 ) -> Result<bool> {
 	// Set controls for everything, i.e., alarm, buzzer and led controls
-	let buzzer_control: u8 = repetition << 4 | buzzer_pattern;
-	let led_control: u8 = color << 4 | color_pattern; // Combine color and pattern into a single byte
-
-	let mut master_controls: Data = Data::default();
-	master_controls.set_alarm_control(buzzer_control);
-	master_controls.set_led_control(led_control);
-	master_controls.set_volume(*volume);
+	let master_controls: DeviceCommand = DeviceCommand::Master {
+			color: *color,
+			color_pattern: *color_pattern,
+			buzzer_pattern: *buzzer_pattern,
+			repetition: *repetition,
+			volume: *volume,
+	};
 
-	match send_command(handle, master_controls) {
-			Ok(u) => (),
+	match master_controls.execute(handle) {
+			Ok(_) => (),
 			Err(e) => {
 					println!("Failed to send command: {:?}", e);
 					return Ok(false);
@@ -484,94 +781,100 @@ pub fn set_master_controls_command(
 	Ok(true)
 }
 
-pub fn set_light_command(
-	handle: &mut DeviceHandle<rusb::Context>,
+pub fn set_light_command<T: Transport + ?Sized>(
+	handle: &mut T,
 	color: &u8,
 	pattern: &u8,
-  duration: &u16,
+  _duration: &u16,
 ) -> Result<bool> {
-	// Ensure color is in the range 0-15 (4 bits) and pattern is in the range 0-15 (4 bits)
-	let mut light_data: Data = Data::default();
-	let led_control: u8 = (color & 0x0F) << 4 | (pattern & 0x0F); // Combine color and pattern into a single byte
-	light_data.set_led_control(led_control);
+	// Color and pattern are masked to 4 bits each and combined into a single byte
+	let light_data: DeviceCommand = DeviceCommand::Light {
+			color: *color,
+			pattern: *pattern,
+	};
 
   // TODO: Set the duration of the light
 
-	match send_command(handle, light_data) {
-			Ok(u) => (),
+	match light_data.execute(handle) {
+			Ok(_) => (),
 			Err(e) => {
 					println!("Failed to send command: {:?}", e);
 					return Ok(false);
 			}
 	}
-	return Ok(true);
+	Ok(true)
 }
 
-pub fn set_buzz_command(
-	handle: &mut DeviceHandle<rusb::Context>,
+pub fn set_buzz_command<T: Transport + ?Sized>(
+	handle: &mut T,
 	pattern: &u8,
 	repetition: &u8,
 	volume: &u8,
 ) -> Result<bool> {
 	// Specify the buzzer pattern, number of times to repeat the buzzer, and the volume
-	let mut buzz_data: Data = Data::default();
-	let buzz_control: u8 = (repetition & 0x0F) << 4 | (pattern & 0x0F);
-	buzz_data.set_alarm_control(buzz_control);
-	buzz_data.set_volume(*volume);
+	let buzz_data: DeviceCommand = DeviceCommand::Buzz {
+			pattern: *pattern,
+			repetition: *repetition,
+			volume: *volume,
+	};
 
-	match send_command(handle, buzz_data) {
-			Ok(u) => (),
+	match buzz_data.execute(handle) {
+			Ok(_) => (),
 			Err(e) => {
 					println!("Failed to send command: {:?}", e);
 					return Ok(false);
 			}
 	}
 
-	return Ok(true);
+	Ok(true)
 }
 
-pub fn set_blank(handle: &mut DeviceHandle<rusb::Context>) -> Result<bool> {
+pub fn set_blank<T: Transport + ?Sized>(handle: &mut T) -> Result<bool> {
 	// Turn off the light, buzzer, and volume to off
-	let blank_data: Data = Data::blank();
+	let blank_data: DeviceCommand = DeviceCommand::Off;
 
-	match send_command(handle, blank_data) {
-			Ok(u) => (),
+	match blank_data.execute(handle) {
+			Ok(_) => (),
 			Err(e) => {
 					println!("Failed to send command: {:?}", e);
 					return Ok(false);
 			}
 	}
 
-	return Ok(true);
+	Ok(true)
+}
+
+#[deprecated(note = "this turns the connection display on rather than reading anything, use set_connection_display")]
+pub fn get_settings<T: Transport + ?Sized>(handle: &mut T) -> Result<bool> {
+	set_connection_display(handle, true)
 }
 
-pub fn get_settings(handle: &mut DeviceHandle<rusb::Context>) -> Result<bool> {
-	// Get the current settings of the device
-	let get_settings: Data = Data::settings();
+pub fn set_connection_display<T: Transport + ?Sized>(handle: &mut T, on: bool) -> Result<bool> {
+	// Turn the white flash on connecting to a host on or off
+	let connection_display: DeviceCommand = DeviceCommand::ConnectionDisplay { on };
 
-	match send_command(handle, get_settings) {
-			Ok(u) => (),
+	match connection_display.execute(handle) {
+			Ok(_) => (),
 			Err(e) => {
 					println!("Failed to send command: {:?}", e);
 					return Ok(false);
 			}
 	}
 
-	return Ok(true);
+	Ok(true)
 }
 
-pub fn set_volume_command(handle: &mut DeviceHandle<rusb::Context>, volume: &u8) -> Result<bool> {
+pub fn set_volume_command<T: Transport + ?Sized>(handle: &mut T, volume: &u8) -> Result<bool> {
 	// Set the volume level of the buzzer
-	let mut set_volume: Data = Data::default();
-	set_volume.set_volume(*volume);
+	let set_volume: DeviceCommand = DeviceCommand::Volume { level: *volume };
 
-	match send_command(handle, set_volume) {
-			Ok(u) => (),
+	match set_volume.execute(handle) {
+			Ok(_) => (),
 			Err(e) => {
 					println!("Failed to send command: {:?}", e);
 					return Ok(false);
 			}
 	}
 
-	return Ok(true);
+	Ok(true)
 }
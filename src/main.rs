@@ -1,13 +1,359 @@
-use rusb::{Context, DeviceHandle, Result};
-use clap::{arg, command, ArgMatches, Command};
+mod cli;
+
+use rusb::{Context, Result};
+use clap::parser::ValueSource;
+use clap::{arg, command, ArgAction, ArgMatches, Command};
+use clap_complete::CompleteEnv;
+use cli::exec::{ExecOptions, ExecState};
+use cli::completions::{device_candidates, name_candidates, preset_candidates, write_man, write_registration, COMPLETE_VAR, SHELLS};
+use cli::config::{Config, TOKEN_VAR};
+#[cfg(feature = "remote")]
+use cli::config::{ApiToken, Scope};
+#[cfg(feature = "remote")]
+use cli::remote::RemoteTransport;
+#[cfg(feature = "remote")]
+use cli::serve::{Reopening, ServeOptions};
+#[cfg(feature = "remote")]
+use patlite_rs::Patlite;
+use cli::replay::ReplaySummary;
+use cli::state_file::Persisted;
+use cli::output::{all_controls, control_values, print, print_error, print_failure, CommandResult, OutputFormat, EXIT_CONFIG, EXIT_FAILURE, EXIT_REPLAY_INCOMPLETE, EXIT_SUCCESS, OUTPUT_FORMATS};
+use cli::watch_log::{Rule, WatchOptions};
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+use serde::Serialize;
 use tabled::{builder::Builder, settings::Style};
-use patlite_rs::{get_settings, print_device_info, set_blank, set_buzz_command, set_light_command, set_master_controls_command, set_volume_command, setup_device};
+use patlite_rs::constants::{BUZZER_KEEP, BUZZER_VOLUME_KEEP, LED_COLOR_KEEP, LED_PATTERN_KEEP};
+use patlite_rs::names::{canonical_name, names_of, parse_setting, BUZZER_PATTERN_NAMES, COLOR_NAMES, LED_PATTERN_NAMES, REPETITION_NAMES, SETTING_NAMES, VOLUME_NAMES};
+#[cfg(feature = "serial")]
+use patlite_rs::SerialTransport;
+use patlite_rs::{list_registered_devices, read_recording, parse_buzzer_pattern, parse_color, parse_led_pattern, parse_repetition, parse_volume, print_device_info, read_device_info, bytes_to_hex, Data, DeviceCommand, DeviceProfile, DeviceState, DryRun, Emulator, FrameFormat, PnsTransport, SERIAL_BAUD_RATE, RecordedFrame, Recorder, Replayer, RetryPolicy, Simulator, TierSetting, Trace, Transport, UsbTransport};
+
+// Names accepted on the command line for `value`, for the info tables
+fn names(table: &[(&'static str, u8)], value: u8) -> String {
+    names_of(table, value).join(", ")
+}
+
+// An error named for --retry-on
+fn parse_retryable(s: &str) -> std::result::Result<rusb::Error, String> {
+    match s.trim().to_lowercase().as_str() {
+        "timeout" => Ok(rusb::Error::Timeout),
+        "pipe" => Ok(rusb::Error::Pipe),
+        "busy" => Ok(rusb::Error::Busy),
+        "io" => Ok(rusb::Error::Io),
+        "interrupted" => Ok(rusb::Error::Interrupted),
+        "overflow" => Ok(rusb::Error::Overflow),
+        "no-device" => Ok(rusb::Error::NoDevice),
+        _ => Err(format!(
+            "Unknown error: {}, expected timeout, pipe, busy, io, interrupted, overflow or no-device",
+            s
+        )),
+    }
+}
+
+// A byte of a raw frame in hex, e.g. `11` or `0x11`
+fn parse_byte(s: &str) -> std::result::Result<u8, String> {
+    let digits: &str = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+    u8::from_str_radix(digits, 16).map_err(|_| format!("Not a hex byte: {}", s))
+}
+
+// A raw frame as sent, and the response if one was read
+#[derive(Serialize)]
+struct RawResult {
+    sent: String,
+    decoded: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    received: Option<String>,
+}
+
+fn load_recording(path: &Path) -> Result<Vec<RecordedFrame>> {
+    File::open(path)
+        .and_then(|file| read_recording(BufReader::new(file)))
+        .map_err(|e| {
+            eprintln!("Failed to read recording {}: {}", path.display(), e);
+            rusb::Error::Io
+        })
+}
+
+// Settings from the global arguments and the config file
+#[derive(Clone)]
+struct Globals {
+    output: OutputFormat,
+    serial: Option<String>,
+    config: Config,
+    dry_run: bool,
+    trace: bool,
+    record: Option<PathBuf>,
+    // The recording of --replay, shared by every transport opened so what's left can be checked
+    replay: Option<Replayer>,
+    host: Option<String>,
+    serial_port: Option<String>,
+    #[cfg_attr(not(feature = "serial"), allow(dead_code))]
+    baud_rate: u32,
+    // The model behind --host and --serial-port, or the one emulated
+    model: DeviceProfile,
+    remote: Option<String>,
+    #[cfg_attr(not(feature = "remote"), allow(dead_code))]
+    token: Option<String>,
+    #[cfg_attr(not(feature = "remote"), allow(dead_code))]
+    remote_ca: Option<PathBuf>,
+    usb_timeout: Duration,
+    retry: RetryPolicy,
+}
+
+// Where frames are shown with --dry-run and --trace
+#[derive(Clone, Copy, PartialEq)]
+enum FrameLog {
+    Stdout,
+    Stderr,
+    Hidden,
+}
+
+impl FrameLog {
+    fn writer(self) -> Box<dyn Write> {
+        match self {
+            FrameLog::Stdout => Box::new(std::io::stdout()),
+            FrameLog::Stderr => Box::new(std::io::stderr()),
+            FrameLog::Hidden => Box::new(std::io::sink()),
+        }
+    }
+}
+
+impl Globals {
+    fn open_device(&self) -> Result<UsbTransport<Context>> {
+        let mut transport: UsbTransport<Context> =
+            UsbTransport::open_registered(&self.config.models, self.serial.as_deref())?;
+        transport.set_timeout(self.usb_timeout);
+        transport.set_retry_policy(self.retry.clone());
+        Ok(transport)
+    }
+
+    // The device to send frames to, or a stand-in printing them to `log` with --dry-run.
+    // Frames are printed to stderr rather than stdout when stdout carries JSON or YAML
+    fn transport(&self, log: FrameLog) -> Result<Box<dyn Transport>> {
+        let log: FrameLog = match (log, self.output) {
+            (FrameLog::Stdout, OutputFormat::Json | OutputFormat::Yaml) => FrameLog::Stderr,
+            (log, _) => log,
+        };
+        let mut transport: Box<dyn Transport> = if self.dry_run {
+            Box::new(DryRun::new(log.writer()))
+        } else if let Some(replayer) = &self.replay {
+            Box::new(replayer.clone())
+        } else if let Some(host) = &self.host {
+            let model: DeviceProfile = self.pns_model()?;
+            let mut transport: PnsTransport = PnsTransport::connect(host, self.usb_timeout)?;
+            transport.set_profile(model);
+            Box::new(transport)
+        } else if let Some(address) = &self.remote {
+            self.remote_transport(address)?
+        } else if let Some(path) = &self.serial_port {
+            self.serial_transport(path)?
+        } else {
+            Box::new(Persisted::new(self.open_device()?, self.serial.clone()))
+        };
+        if self.trace && !self.dry_run && log != FrameLog::Hidden {
+            transport = Box::new(Trace::new(transport, FrameLog::Stderr.writer()));
+        }
+        if let Some(path) = &self.record {
+            let file: File = File::create(path).map_err(|e| {
+                eprintln!("Failed to create recording {}: {}", path.display(), e);
+                rusb::Error::Io
+            })?;
+            transport = Box::new(Recorder::new(transport, file));
+        }
+        Ok(transport)
+    }
+
+    // The model behind --host or --serial-port. PNS packets are made from tower frames, so it has to be a tower
+    fn pns_model(&self) -> Result<DeviceProfile> {
+        if self.model.frame_format != FrameFormat::LrUsb {
+            eprintln!("{} isn't a PNS unit, --host and --serial-port need a tower model such as LA6-PNS", self.model.name);
+            return Err(rusb::Error::NotSupported);
+        }
+        Ok(self.model.clone())
+    }
 
-fn main() -> Result<()> {
-    let matches: ArgMatches = command!()
+    #[cfg(feature = "remote")]
+    fn remote_transport(&self, address: &str) -> Result<Box<dyn Transport>> {
+        let Some(token) = &self.token else {
+            eprintln!("--remote needs a --token or {}", TOKEN_VAR);
+            return Err(rusb::Error::Access);
+        };
+        Ok(Box::new(RemoteTransport::connect(address, token, self.remote_ca.as_deref())?))
+    }
+
+    #[cfg(not(feature = "remote"))]
+    fn remote_transport(&self, _address: &str) -> Result<Box<dyn Transport>> {
+        eprintln!("--remote needs patlite-rs built with the remote feature");
+        Err(rusb::Error::NotSupported)
+    }
+
+    #[cfg(feature = "serial")]
+    fn serial_transport(&self, path: &str) -> Result<Box<dyn Transport>> {
+        let model: DeviceProfile = self.pns_model()?;
+        let mut transport: SerialTransport = SerialTransport::open_serial(path, self.baud_rate, self.usb_timeout)?;
+        transport.set_profile(model);
+        Ok(Box::new(transport))
+    }
+
+    #[cfg(not(feature = "serial"))]
+    fn serial_transport(&self, _path: &str) -> Result<Box<dyn Transport>> {
+        eprintln!("--serial-port is experimental, it needs patlite-rs built with the serial feature");
+        Err(rusb::Error::NotSupported)
+    }
+
+    // Whether commands go to a USB device rather than a stand-in or another transport
+    fn on_usb(&self) -> bool {
+        !self.dry_run && self.replay.is_none() && self.host.is_none() && self.remote.is_none() && self.serial_port.is_none()
+    }
+
+    // The configured default volume unless `id` was given on the command line
+    fn volume(&self, sub_matches: &ArgMatches, id: &str) -> u8 {
+        let given: u8 = *sub_matches.get_one::<u8>(id).expect("Volume has a default");
+        match (sub_matches.value_source(id), self.config.volume) {
+            (Some(ValueSource::DefaultValue), Some(volume)) => volume,
+            _ => given,
+        }
+    }
+}
+
+// Send a single command to the device and report how it went
+fn run_command(command: DeviceCommand, globals: &Globals) -> Result<i32> {
+    let output: OutputFormat = globals.output;
+    let mut transport: Box<dyn Transport> = globals.transport(FrameLog::Stdout)?;
+    let sent = command.execute(&mut transport);
+    let result = CommandResult {
+        id: None,
+        ok: sent.is_ok(),
+        command: Some(command.name()),
+        error: sent.as_ref().err().map(|e| e.to_string()),
+        attempts: Some(transport.attempts()),
+        state: None,
+    };
+    print(output, &result, || match (&sent, transport.attempts()) {
+        (Ok(_), 1) => "Completed!~".to_string(),
+        (Ok(_), attempts) => format!("Completed!~ after {} attempts", attempts),
+        (Err(e), 1) => format!("Failed to send command: {:?}", e),
+        (Err(e), attempts) => format!("Failed to send command after {} attempts: {:?}", attempts, e),
+    });
+    match sent {
+        Ok(_) => Ok(EXIT_SUCCESS),
+        Err(e) => Ok(cli::output::exit_code(&e)),
+    }
+}
+
+fn cli() -> Command {
+    command!()
     .version("1.0")
     .about("Patlite NE-SN-USB CLI Tool")
     .propagate_version(true)
+    .arg(
+      arg!(-o --output <FORMAT> "Output format for results")
+        .value_parser(OUTPUT_FORMATS)
+        .default_value("table")
+        .global(true)
+    )
+    .arg(
+      arg!(-d --device <DEVICE> "Serial number or configured alias of the device to use")
+        .add(device_candidates())
+        .global(true)
+    )
+    .arg(
+      arg!(--config <FILE> "Config file to use instead of the system and user config files")
+        .value_parser(clap::value_parser!(PathBuf))
+        .global(true)
+    )
+    .arg(
+      arg!(--profile <NAME> "Config profile to apply")
+        .global(true)
+    )
+    .arg(
+      arg!(--"dry-run" "Print the frames that would be sent instead of opening the device")
+        .global(true)
+    )
+    .arg(
+      arg!(--trace "Log every frame sent to the device on stderr")
+        .global(true)
+    )
+    .arg(
+      arg!(--"usb-timeout" <MS> "Milliseconds to wait for each USB transfer, or for each answer with --host")
+        .value_parser(clap::value_parser!(u64).range(1..))
+        .default_value("3000")
+        .global(true)
+    )
+    .arg(
+      arg!(--attempts <N> "Times to try sending a frame before giving up")
+        .value_parser(clap::value_parser!(u32).range(1..))
+        .default_value("1")
+        .global(true)
+    )
+    .arg(
+      arg!(--"retry-backoff" <MS> "Milliseconds to wait before the first retry, doubling after each one")
+        .value_parser(clap::value_parser!(u64))
+        .default_value("100")
+        .global(true)
+    )
+    .arg(
+      arg!(--"retry-on" <ERRORS> "Comma separated errors worth retrying")
+        .value_parser(parse_retryable)
+        .value_delimiter(',')
+        .default_value("timeout,pipe,busy")
+        .global(true)
+    )
+    .arg(
+      arg!(--record <FILE> "Record every frame sent to or read from the device to a file")
+        .value_parser(clap::value_parser!(PathBuf))
+        .global(true)
+    )
+    .arg(
+      arg!(--replay <FILE> "Check the frames sent against a recording instead of opening the device")
+        .value_parser(clap::value_parser!(PathBuf))
+        .conflicts_with("dry-run")
+        .global(true)
+    )
+    .arg(
+      arg!(--host <ADDRESS> "Drive a LAN connected unit with the PNS protocol, e.g. 10.0.0.5:10000, instead of a USB device")
+        .conflicts_with_all(["dry-run", "replay", "device"])
+        .global(true)
+    )
+    .arg(
+      arg!(--"serial-port" <PORT> "Drive a serial signal unit with the PNS protocol, e.g. /dev/ttyUSB0 or COM3 (experimental, needs the serial feature)")
+        .conflicts_with_all(["dry-run", "replay", "device", "host"])
+        .global(true)
+    )
+    .arg(
+      arg!(--"baud-rate" <BAUD>)
+        .help(format!("Baud rate of --serial-port [default: {}]", SERIAL_BAUD_RATE))
+        .value_parser(clap::value_parser!(u32).range(1..))
+        .global(true)
+    )
+    .arg(
+      arg!(--remote <ADDRESS> "Drive the device of a serve instance on another host, e.g. buildserver:7070 or https://buildserver:7070")
+        .conflicts_with_all(["dry-run", "replay", "device", "host", "serial-port"])
+        .global(true)
+    )
+    .arg(
+      arg!(--token <TOKEN> "Shared token of serve and --remote")
+        .env(TOKEN_VAR)
+        .hide_env_values(true)
+        .global(true)
+    )
+    .arg(
+      arg!(--"remote-ca" <FILE> "PEM certificate of the CA that signed the certificate of an https --remote, instead of the public roots")
+        .value_parser(clap::value_parser!(PathBuf))
+        .requires("remote")
+        .global(true)
+    )
+    .arg(
+      arg!(--model <NAME> "Model of the unit behind --host or --serial-port, or to emulate, see the models subcommand")
+        .default_value("LA6-PNS")
+        .global(true)
+    )
     // .subcommand_required(true)
     // .arg_required_else_help(true)
     .subcommand(
@@ -15,27 +361,32 @@ fn main() -> Result<()> {
       .about("Master control to set light and buzzer controls")
       .arg(
         arg!([COLOR] "Color to set the light to")
-          .value_parser(clap::value_parser!(u8).range(0..16))
-          .default_value("0")
+          .value_parser(parse_color)
+          .add(name_candidates(COLOR_NAMES))
+          .default_value("off")
       )
       .arg(
         arg!([COLORPATTERN] "Pattern to set the light to")
-          .value_parser(clap::value_parser!(u8).range(0..16))
-          .default_value("0")
+          .value_parser(parse_led_pattern)
+          .add(name_candidates(LED_PATTERN_NAMES))
+          .default_value("off")
       )
       .arg(
         arg!([BUZZERPATTERN] "Pattern to set the buzzer to")
-          .value_parser(clap::value_parser!(u8).range(0..16))
-          .default_value("0")
+          .value_parser(parse_buzzer_pattern)
+          .add(name_candidates(BUZZER_PATTERN_NAMES))
+          .default_value("off")
       )
       .arg(
         arg!([REPETITION] "Number of times to repeat the buzzer")
-          .value_parser(clap::value_parser!(u8).range(0..16))
+          .value_parser(parse_repetition)
+          .add(name_candidates(REPETITION_NAMES))
           .default_value("0")
       )
       .arg(
         arg!([VOLUME] "Volume level to set the buzzer to")
-          .value_parser(clap::value_parser!(u8).range(0..16))
+          .value_parser(parse_volume)
+          .add(name_candidates(VOLUME_NAMES))
           .default_value("0")
       )
     )
@@ -44,13 +395,15 @@ fn main() -> Result<()> {
       .about("Light control")
       .arg(
         arg!([COLOR] "Color to set the light to")
-          .value_parser(clap::value_parser!(u8).range(0..16))
-          .default_value("0")
+          .value_parser(parse_color)
+          .add(name_candidates(COLOR_NAMES))
+          .default_value("off")
       )
       .arg(
         arg!([PATTERN] "Pattern to set the light to")
-          .value_parser(clap::value_parser!(u8).range(0..16))
-          .default_value("0")
+          .value_parser(parse_led_pattern)
+          .add(name_candidates(LED_PATTERN_NAMES))
+          .default_value("off")
       )
       .arg(
         arg!(["DURATION"] "Duration to set the light to")
@@ -63,18 +416,33 @@ fn main() -> Result<()> {
       .about("Create a buzzer")
       .arg(
         arg!([PATTERN] "Pattern to set the buzz to")
-        .value_parser(clap::value_parser!(u8).range(0..16))
-        .default_value("0")
+        .value_parser(parse_buzzer_pattern)
+        .add(name_candidates(BUZZER_PATTERN_NAMES))
+        .default_value("off")
       )
       .arg(
         arg!([REPETITION] "Number of times to repeat the buzz")
-        .value_parser(clap::value_parser!(u8).range(0..16))
+        .value_parser(parse_repetition)
+        .add(name_candidates(REPETITION_NAMES))
         .default_value("0")
       )
       .arg(
         arg!([VOLUME] "Volume level to set the buzz to")
-        .value_parser(clap::value_parser!(u8).range(0..16))
+        .value_parser(parse_volume)
+        .add(name_candidates(VOLUME_NAMES))
         .default_value("0")
+      )
+      .arg(
+        arg!(--times <REPETITION> "Number of times to repeat the buzz, instead of REPETITION")
+        .value_parser(parse_repetition)
+        .add(name_candidates(REPETITION_NAMES))
+        .conflicts_with("REPETITION")
+      )
+      .arg(
+        arg!(--volume <VOLUME> "Volume level to set the buzz to, instead of VOLUME")
+        .value_parser(parse_volume)
+        .add(name_candidates(VOLUME_NAMES))
+        .conflicts_with("VOLUME")
       ),
     )
     .subcommand(
@@ -82,13 +450,52 @@ fn main() -> Result<()> {
       .about("Set the volume level")
       .arg(
         arg!([LEVEL] "Volume level to set")
-          .value_parser(clap::value_parser!(u8).range(0..16))
+          .value_parser(parse_volume)
+          .add(name_candidates(VOLUME_NAMES))
           .default_value("0")
       ),
     )
+    .subcommand(
+      Command::new("tier")
+      .about("Set tiers of a stacked tower, by number from the top or by color, e.g. tier 1 on tier 3 blink")
+      .arg(
+        arg!(<TIERS> ... "TIER MODE pairs, each optionally after `tier`, with modes on, blink, off or keep")
+          .trailing_var_arg(true)
+      ),
+    )
     .subcommand(
       Command::new("state")
-      .about("Get the current state of the device")
+      .about("Show the state the device was set to, tracked from the commands sent since it can't be read back")
+    )
+    .subcommand(
+      Command::new("settings")
+      .about("Change device settings")
+      .subcommand_required(true)
+      .subcommand(
+        Command::new("connection-display")
+        .about("Whether the light flashes white when the device is connected to a host")
+        .arg(
+          arg!(<SETTING> "on or off")
+            .value_parser(parse_setting)
+            .add(name_candidates(SETTING_NAMES))
+        )
+      )
+    )
+    .subcommand(
+      Command::new("devices")
+      .about("List connected devices")
+    )
+    .subcommand(
+      Command::new("models")
+      .about("List the device models that are looked for, built in and from the config file")
+    )
+    .subcommand(
+      Command::new("preset")
+      .about("Apply a preset from the config file, or list the presets")
+      .arg(
+        arg!([NAME] "Name of the preset to apply")
+          .add(preset_candidates())
+      )
     )
     .subcommand(
       Command::new("off")
@@ -101,11 +508,357 @@ fn main() -> Result<()> {
       .arg(
         arg!([CONTROL] "Control to get information on")
           .value_parser(["color", "led", "buzzer", "volume", "device", "all"])
+          .default_value("all")
+      )
+    )
+    .subcommand(
+      Command::new("exec")
+      .about("Run a command and show its status on the light")
+      .arg(
+        arg!(--"running-color" <COLOR> "Color to show while the command runs")
+          .value_parser(parse_color)
+          .add(name_candidates(COLOR_NAMES))
+          .default_value("yellow")
+      )
+      .arg(
+        arg!(--"running-pattern" <PATTERN> "Light pattern to show while the command runs")
+          .value_parser(parse_led_pattern)
+          .add(name_candidates(LED_PATTERN_NAMES))
+          .default_value("blink-1")
+      )
+      .arg(
+        arg!(--"success-color" <COLOR> "Color to show when the command succeeds")
+          .value_parser(parse_color)
+          .add(name_candidates(COLOR_NAMES))
+          .default_value("green")
+      )
+      .arg(
+        arg!(--"success-pattern" <PATTERN> "Light pattern to show when the command succeeds")
+          .value_parser(parse_led_pattern)
+          .add(name_candidates(LED_PATTERN_NAMES))
+          .default_value("on")
+      )
+      .arg(
+        arg!(--"failure-color" <COLOR> "Color to show when the command fails")
+          .value_parser(parse_color)
+          .add(name_candidates(COLOR_NAMES))
+          .default_value("red")
+      )
+      .arg(
+        arg!(--"failure-pattern" <PATTERN> "Light pattern to show when the command fails")
+          .value_parser(parse_led_pattern)
+          .add(name_candidates(LED_PATTERN_NAMES))
+          .default_value("on")
+      )
+      .arg(
+        arg!(--"failure-buzzer" <BUZZERPATTERN> "Buzzer pattern to sound when the command fails")
+          .value_parser(parse_buzzer_pattern)
+          .add(name_candidates(BUZZER_PATTERN_NAMES))
+          .default_value("strong-attention")
+      )
+      .arg(
+        arg!(--"failure-repetition" <REPETITION> "Number of times to repeat the failure buzzer")
+          .value_parser(parse_repetition)
+          .add(name_candidates(REPETITION_NAMES))
+          .default_value("3")
+      )
+      .arg(
+        arg!(--"failure-volume" <VOLUME> "Volume level of the failure buzzer")
+          .value_parser(parse_volume)
+          .add(name_candidates(VOLUME_NAMES))
+          .default_value("max")
+      )
+      .arg(
+        arg!(--timeout <SECONDS> "Kill the command and report failure after this many seconds")
+          .value_parser(clap::value_parser!(u64))
           .required(false)
       )
+      .arg(
+        arg!(<COMMAND> ... "Command to run, e.g. `exec -- cargo test`")
+          .trailing_var_arg(true)
+          .allow_hyphen_values(true)
+      )
     )
-    .get_matches();
+    .subcommand(
+      Command::new("watch-log")
+      .about("Follow a log file and raise alerts on the light for matching lines")
+      .after_help("Rules have the form `REGEX -> COLOR [PATTERN] [+ buzz [BUZZERPATTERN] [REPETITION] [VOLUME]]`,\n\
+        e.g. --rule 'ERROR -> red blink + buzz' --rule 'WARN -> yellow'. The first matching rule wins.")
+      .arg(
+        arg!(<FILE> "Log file to follow, rotation and truncation are detected")
+          .value_parser(clap::value_parser!(PathBuf))
+      )
+      .arg(
+        arg!(-r --rule <RULE> "Alert rule, can be given multiple times")
+          .value_parser(Rule::from_str)
+          .action(ArgAction::Append)
+          .required(true)
+      )
+      .arg(
+        arg!(--debounce <SECONDS> "Ignore repeated matches of the same rule for this many seconds")
+          .value_parser(clap::value_parser!(u64))
+          .default_value("5")
+      )
+      .arg(
+        arg!(--"clear-after" <SECONDS> "Turn the light off after this many seconds without matches")
+          .value_parser(clap::value_parser!(u64))
+          .required(false)
+      )
+      .arg(
+        arg!(--"from-start" "Read the file from the beginning instead of only new lines")
+      )
+    )
+    .subcommand(
+      Command::new("shell")
+      .about("Open the device once and type commands interactively, with completion and history")
+    )
+    .subcommand(
+      Command::new("tui")
+      .about("Control panel showing a live picture of the light, buzzer and volume")
+    )
+    .subcommand(
+      Command::new("completions")
+      .about("Print the script that sets up tab completion for a shell")
+      .after_help("For bash add `source <(patlite-rs completions bash)` to ~/.bashrc. Connected devices and presets\n\
+        are looked up while completing, so source the script on shell startup rather than saving it.")
+      .arg(
+        arg!(<SHELL> "Shell to complete for")
+          .value_parser(SHELLS)
+      )
+    )
+    .subcommand(
+      Command::new("man")
+      .about("Print the man page in roff format")
+      .arg(
+        arg!(--"out-dir" <DIR> "Write a page for every subcommand into this directory instead")
+          .value_parser(clap::value_parser!(PathBuf))
+      )
+    )
+    .subcommand(
+      Command::new("stream")
+      .about("Apply newline delimited JSON commands from stdin, writing a JSON result line for each")
+      .after_help("Each line is a command object, e.g. {\"command\": \"light\", \"color\": 1, \"pattern\": 1}.\n\
+        Commands are master, light, buzz, volume, off, connection-display and tiers with the same values as\n\
+        the subcommands, e.g. {\"command\": \"connection-display\", \"on\": false} or\n\
+        {\"command\": \"tiers\", \"tiers\": [{\"tier\": 1, \"mode\": \"on\"}, {\"tier\": \"blue\", \"mode\": \"blink\"}]}.\n\
+        {\"command\": \"state\"} answers with what the device was last set to, as the state subcommand.\n\
+        An optional \"id\" is echoed back in the result.")
+    )
+    .subcommand(
+      Command::new("serve")
+      .about("Serve the device to --remote clients, the REST API and gRPC, opening it as for any command")
+      .arg(
+        arg!(--listen <ADDRESS> "Address to listen on")
+          .default_value("127.0.0.1:7070")
+      )
+      .arg(
+        arg!(--"tls-cert" <FILE> "PEM certificate chain, to serve https")
+          .value_parser(clap::value_parser!(PathBuf))
+          .requires("tls-key")
+      )
+      .arg(
+        arg!(--"tls-key" <FILE> "PEM private key of --tls-cert")
+          .value_parser(clap::value_parser!(PathBuf))
+          .requires("tls-cert")
+      )
+      .arg(
+        arg!(--"buzzer-per-minute" <N> "Commands sounding the buzzer each client may send a minute, 0 for no limit")
+          .value_parser(clap::value_parser!(u32))
+          .default_value("10")
+      )
+      .arg(
+        arg!(--"audit-log" <FILE> "Append a JSON line for every command and frame to this file instead of stderr")
+          .value_parser(clap::value_parser!(PathBuf))
+      )
+      .arg(arg!(--simulate "Serve the simulator instead of the device, e.g. to try out the control page or a client"))
+      .after_help("--token has every scope. More tokens, each with its own scopes and buzzer limit, can be added\n\
+        in the [tokens] table of the config. Scopes: read-state, light, buzzer, admin.")
+    )
+    .subcommand(
+      Command::new("emulate")
+      .about("Act as a LAN connected unit for --host, showing what the simulated unit does")
+      .after_help("Clients speak the PNS protocol, or send the 8 byte USB frames of the model as they are.\n\
+        Each packet is answered with ACK (06), or NAK (15) for packets the model can't carry out.")
+      .arg(
+        arg!(--listen <ADDRESS> "Address to listen on")
+          .default_value("127.0.0.1:10000")
+      )
+    )
+    .subcommand(
+      Command::new("replay")
+      .about("Play back the frames of a recording made with --record")
+      .arg(
+        arg!(<FILE> "Recording to play")
+          .value_parser(clap::value_parser!(PathBuf))
+      )
+      .arg(arg!(--simulate "Play to the simulator and print the state after each frame instead of using the device"))
+      .arg(arg!(--"no-delay" "Send the frames straight after each other instead of with the recorded timing"))
+    )
+    .subcommand(
+      Command::new("raw")
+      .about("Send a frame given byte by byte, for trying out the protocol")
+      .after_help("Bytes are hex, with or without 0x. Red light on with a continuous sound:\n\
+        patlite-rs raw 00 00 01 06 11 00 00 00")
+      .arg(
+        arg!(<BYTES> ... "The 8 bytes of the frame")
+          .value_name("BYTE")
+          .num_args(8)
+          .value_parser(parse_byte)
+      )
+      .arg(arg!(--read "Wait for a response from the device after sending"))
+      .arg(arg!(--force "Send the frame even if it isn't a valid command"))
+    )
+}
+
+// Subcommands that still run, with a warning, when the config can't be loaded
+const WITHOUT_CONFIG: &[&str] = &["info", "completions", "man", "models", "devices"];
+
+fn main() {
+    CompleteEnv::with_factory(cli).var(COMPLETE_VAR).complete();
 
+    let matches: ArgMatches = cli().get_matches();
+
+    let output: OutputFormat = matches
+        .get_one::<String>("output")
+        .expect("Output has a default")
+        .parse()
+        .expect("Output is one of OUTPUT_FORMATS");
+    let config: Config = match Config::load(
+        matches.get_one::<PathBuf>("config").map(PathBuf::as_path),
+        matches.get_one::<String>("profile").map(String::as_str),
+    ) {
+        Ok(c) => c,
+        // These don't need the config, so a broken one shouldn't keep them from running
+        Err(e) if matches.subcommand_name().is_some_and(|name| WITHOUT_CONFIG.contains(&name)) => {
+            eprintln!("Ignoring the config: {}", e);
+            Config::default()
+        }
+        Err(e) => std::process::exit(print_failure(output, e, EXIT_CONFIG)),
+    };
+    let model: DeviceProfile = match config.model(matches.get_one::<String>("model").expect("Model has a default")) {
+        Ok(model) => model,
+        Err(e) => std::process::exit(print_failure(output, e, EXIT_CONFIG)),
+    };
+    let replay: Option<Replayer> = match matches.get_one::<PathBuf>("replay") {
+        Some(path) => match load_recording(path) {
+            Ok(frames) => Some(Replayer::new(frames)),
+            Err(e) => std::process::exit(print_error(output, &e)),
+        },
+        None => None,
+    };
+    let globals = Globals {
+        output,
+        serial: config.serial(matches.get_one::<String>("device").map(String::as_str)),
+        config,
+        dry_run: matches.get_flag("dry-run"),
+        trace: matches.get_flag("trace"),
+        record: matches.get_one::<PathBuf>("record").cloned(),
+        replay,
+        host: matches.get_one::<String>("host").cloned(),
+        serial_port: matches.get_one::<String>("serial-port").cloned(),
+        baud_rate: matches.get_one::<u32>("baud-rate").copied().unwrap_or(SERIAL_BAUD_RATE),
+        model,
+        remote: matches.get_one::<String>("remote").cloned(),
+        token: matches.get_one::<String>("token").cloned(),
+        remote_ca: matches.get_one::<PathBuf>("remote-ca").cloned(),
+        usb_timeout: Duration::from_millis(
+            *matches.get_one::<u64>("usb-timeout").expect("USB timeout has a default"),
+        ),
+        retry: RetryPolicy {
+            attempts: *matches.get_one::<u32>("attempts").expect("Attempts has a default"),
+            backoff: Duration::from_millis(
+                *matches.get_one::<u64>("retry-backoff").expect("Retry backoff has a default"),
+            ),
+            retry_on: matches
+                .get_many::<rusb::Error>("retry-on")
+                .expect("Retry on has a default")
+                .copied()
+                .collect(),
+        },
+    };
+    let mut code: i32 = match run(&matches, &globals) {
+        Ok(code) => code,
+        Err(e) => print_error(output, &e),
+    };
+    // A run that stops short of the recording dropped frames it used to send
+    if let Some(remaining) = globals.replay.as_ref().map(Replayer::remaining).filter(|n| *n > 0) {
+        let message: String = format!("Replay mismatch: {} recorded frames weren't sent or received", remaining);
+        let failed: i32 = print_failure(output, message, EXIT_REPLAY_INCOMPLETE);
+        if code == EXIT_SUCCESS {
+            code = failed;
+        }
+    }
+    std::process::exit(code);
+}
+
+// Open the device on a thread of its own and serve it to --remote clients and the APIs
+#[cfg(feature = "remote")]
+fn serve(sub_matches: &ArgMatches, globals: &Globals) -> Result<i32> {
+    let output: OutputFormat = globals.output;
+    let mut tokens: Vec<ApiToken> = globals.config.tokens.clone();
+    if let Some(token) = globals.token.clone().filter(|t| !t.is_empty()) {
+        if tokens.iter().any(|other| other.token == token) {
+            return Ok(print_failure(output, "--token is also configured in [tokens]".to_string(), EXIT_CONFIG));
+        }
+        tokens.push(ApiToken {
+            name: "default".to_string(),
+            token,
+            scopes: vec![Scope::Admin],
+            buzzer_per_minute: None,
+        });
+    }
+    if tokens.is_empty() {
+        let message: String = format!("serve needs a --token, {} or [tokens] in the config", TOKEN_VAR);
+        return Ok(print_failure(output, message, EXIT_CONFIG));
+    }
+    let simulate: bool = sub_matches.get_flag("simulate");
+    let options = ServeOptions {
+        listen: sub_matches.get_one::<String>("listen").expect("Listen has a default").clone(),
+        tokens,
+        buzzer_per_minute: *sub_matches.get_one::<u32>("buzzer-per-minute").expect("Buzzer limit has a default"),
+        audit_log: sub_matches.get_one::<PathBuf>("audit-log").cloned(),
+        tls: sub_matches
+            .get_one::<PathBuf>("tls-cert")
+            .cloned()
+            .zip(sub_matches.get_one::<PathBuf>("tls-key").cloned()),
+        volume: globals.config.volume.unwrap_or(0),
+        models: globals.config.models.clone(),
+        presets: globals.config.presets.clone(),
+        // The simulator starts out like the device when it's powered on
+        state: if simulate { DeviceState::default() } else { cli::state_file::load(globals.serial.as_deref()) },
+        hotplug: globals.on_usb() && !simulate,
+        serial: globals.serial.clone(),
+    };
+    let runtime: tokio::runtime::Runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => return Ok(print_failure(output, format!("Failed to start the runtime: {}", e), EXIT_FAILURE)),
+    };
+    let opener: Globals = globals.clone();
+    runtime.block_on(async move {
+        let tower: Patlite = if simulate {
+            Patlite::new(Simulator::new())
+        } else {
+            Patlite::open_with(move || {
+                let transport: Box<dyn Transport> = opener.transport(FrameLog::Stderr)?;
+                Ok(Reopening::new(transport, move || opener.transport(FrameLog::Stderr)))
+            })
+            .await?
+        };
+        match cli::serve::run(tower, options).await {
+            Ok(()) => Ok(EXIT_SUCCESS),
+            Err(e) => Ok(print_failure(output, e, EXIT_FAILURE)),
+        }
+    })
+}
+
+#[cfg(not(feature = "remote"))]
+fn serve(_sub_matches: &ArgMatches, globals: &Globals) -> Result<i32> {
+    let message: String = "serve needs patlite-rs built with the remote feature".to_string();
+    Ok(print_failure(globals.output, message, EXIT_FAILURE))
+}
+
+fn run(matches: &ArgMatches, globals: &Globals) -> Result<i32> {
+    let output: OutputFormat = globals.output;
     match matches.subcommand() {
         Some(("master", sub_matches)) => {
             let color: &u8 = sub_matches
@@ -117,22 +870,21 @@ fn main() -> Result<()> {
             let buzzer_pattern: &u8 = sub_matches
                 .get_one::<u8>("BUZZERPATTERN")
                 .expect("Buzzer Pattern is required");
-            let volume: &u8 = sub_matches
-                .get_one::<u8>("VOLUME")
-                .expect("Volume is required");
+            let volume: u8 = globals.volume(sub_matches, "VOLUME");
             let repetition: &u8 = sub_matches
                 .get_one::<u8>("REPETITION")
                 .expect("Repetition is required");
 
-            let mut handle: DeviceHandle<Context> = setup_device()?;
-            set_master_controls_command(
-                &mut handle,
-                color,
-                color_pattern,
-                buzzer_pattern,
-                repetition,
-                volume,
-            )?;
+            run_command(
+                DeviceCommand::Master {
+                    color: *color,
+                    color_pattern: *color_pattern,
+                    buzzer_pattern: *buzzer_pattern,
+                    repetition: *repetition,
+                    volume,
+                },
+                globals,
+            )
         }
         Some(("light", sub_matches)) => {
             let color: &u8 = sub_matches
@@ -141,42 +893,146 @@ fn main() -> Result<()> {
             let pattern: &u8 = sub_matches
                 .get_one::<u8>("PATTERN")
                 .expect("Pattern is required");
-            let duration: &u16 = sub_matches
-                .get_one::<u16>("DURATION")
-                .expect("Duration is required");
 
-            let mut handle: DeviceHandle<Context> = setup_device()?;
-            set_light_command(&mut handle, color, pattern, duration)?;
+            run_command(
+                DeviceCommand::Light {
+                    color: *color,
+                    pattern: *pattern,
+                },
+                globals,
+            )
         }
         Some(("buzz", sub_matches)) => {
             let buzzer_pattern: &u8 = sub_matches
                 .get_one::<u8>("PATTERN")
                 .expect("Buzzer Pattern is required");
-            let volume: &u8 = sub_matches
-                .get_one::<u8>("VOLUME")
-                .expect("Volume is required");
+            let volume: u8 = sub_matches
+                .get_one::<u8>("volume")
+                .copied()
+                .unwrap_or_else(|| globals.volume(sub_matches, "VOLUME"));
             let repetition: &u8 = sub_matches
-                .get_one::<u8>("REPETITION")
+                .get_one::<u8>("times")
+                .or_else(|| sub_matches.get_one::<u8>("REPETITION"))
                 .expect("Repetition is required");
 
-            let mut handle: DeviceHandle<Context> = setup_device()?;
-            set_buzz_command(&mut handle, buzzer_pattern, repetition, volume)?;
+            run_command(
+                DeviceCommand::Buzz {
+                    pattern: *buzzer_pattern,
+                    repetition: *repetition,
+                    volume,
+                },
+                globals,
+            )
         }
         Some(("volume", sub_matches)) => {
             let level: &u8 = sub_matches
                 .get_one::<u8>("LEVEL")
                 .expect("Level is required");
 
-            let mut handle: DeviceHandle<Context> = setup_device()?;
-            set_volume_command(&mut handle, level)?;
+            run_command(DeviceCommand::Volume { level: *level }, globals)
+        }
+        Some(("tier", sub_matches)) => {
+            let words: Vec<&str> = sub_matches
+                .get_many::<String>("TIERS")
+                .expect("Tiers are required")
+                .map(String::as_str)
+                .collect();
+            match TierSetting::parse_list(&words) {
+                Ok(tiers) => run_command(DeviceCommand::Tiers { tiers }, globals),
+                Err(e) => cli().error(clap::error::ErrorKind::InvalidValue, e).exit(),
+            }
         }
         Some(("state", _)) => {
-            let mut handle: DeviceHandle<Context> = setup_device()?;
-            get_settings(&mut handle)?;
+            // USB devices have their state saved after every command, other transports may keep
+            // track of it while they're open or, for --remote, ask serve for the one it tracks
+            let state: Option<DeviceState> = if globals.on_usb() || globals.dry_run {
+                Some(cli::state_file::load(globals.serial.as_deref()))
+            } else {
+                globals.transport(FrameLog::Hidden)?.state()
+            };
+            match state {
+                Some(state) => {
+                    print(output, &state, || state.to_string());
+                    Ok(EXIT_SUCCESS)
+                }
+                None => {
+                    let message: String = "The state of this device isn't tracked, only that of USB devices and their serve instances".to_string();
+                    Ok(print_failure(output, message, EXIT_FAILURE))
+                }
+            }
+        }
+        Some(("settings", sub_matches)) => match sub_matches.subcommand() {
+            Some(("connection-display", setting_matches)) => {
+                let on: bool = *setting_matches
+                    .get_one::<bool>("SETTING")
+                    .expect("Setting is required");
+                run_command(DeviceCommand::ConnectionDisplay { on }, globals)
+            }
+            _ => unreachable!("subcommand_required prevents `None`"),
+        },
+        Some(("off", _)) => run_command(DeviceCommand::Off, globals),
+        Some(("preset", sub_matches)) => {
+            let presets = &globals.config.presets;
+            let Some(name) = sub_matches.get_one::<String>("NAME") else {
+                let actions: std::collections::BTreeMap<&String, &String> =
+                    presets.iter().map(|(name, preset)| (name, &preset.action)).collect();
+                print(output, &actions, || {
+                    let mut builder: Builder = Builder::new();
+                    builder.push_record(["Preset", "Action"]);
+                    for (name, action) in &actions {
+                        builder.push_record([name.as_str(), action.as_str()]);
+                    }
+                    builder.build().with(Style::rounded()).to_string()
+                });
+                return Ok(EXIT_SUCCESS);
+            };
+            match presets.get(name) {
+                Some(preset) => run_command(preset.command.clone(), globals),
+                None => Ok(print_failure(output, format!("Unknown preset: {}", name), EXIT_CONFIG)),
+            }
+        }
+        Some(("devices", _)) => {
+            let devices = list_registered_devices(&globals.config.models)?;
+            print(output, &devices, || {
+                let mut builder: Builder = Builder::new();
+                builder.push_record(["Bus", "Address", "Vendor ID", "Product ID", "Model", "Serial Number"]);
+                for device in &devices {
+                    builder.push_record([
+                        device.bus.to_string(),
+                        device.address.to_string(),
+                        format!("{:#06x}", device.vendor_id),
+                        format!("{:#06x}", device.product_id),
+                        device.model.clone(),
+                        device.serial_number.clone().unwrap_or("Not Found".to_string()),
+                    ]);
+                }
+                builder.build().with(Style::rounded()).to_string()
+            });
+            Ok(EXIT_SUCCESS)
         }
-        Some(("off", _)) => {
-            let mut handle: DeviceHandle<Context> = setup_device()?;
-            set_blank(&mut handle)?;
+        Some(("models", _)) => {
+            let models = globals.config.models.profiles();
+            print(output, &models, || {
+                let mut builder: Builder = Builder::new();
+                builder.push_record(["Model", "Product ID", "Colors", "Tiers", "Buzzer", "Frame Format"]);
+                for model in models {
+                    let colors: Vec<String> = model
+                        .colors
+                        .iter()
+                        .map(|c| canonical_name(COLOR_NAMES, *c).unwrap_or("?").to_string())
+                        .collect();
+                    builder.push_record([
+                        model.name.clone(),
+                        format!("{:#06x}", model.product_id),
+                        colors.join(", "),
+                        model.tiers.to_string(),
+                        if model.buzzer { "yes" } else { "no" }.to_string(),
+                        format!("{:?}", model.frame_format),
+                    ]);
+                }
+                builder.build().with(Style::rounded()).to_string()
+            });
+            Ok(EXIT_SUCCESS)
         }
         Some(("info", sub_matches)) => {
             let control: &String = sub_matches
@@ -184,69 +1040,94 @@ fn main() -> Result<()> {
                 .expect("Control type is required");
             let mut builder: Builder = Builder::new();
 
+            // The descriptors are read from a USB device itself, which --dry-run and the other transports never touch
+            if control == "device" && !globals.on_usb() {
+                let message: String =
+                    "info device reads a USB device, it can't be used with --dry-run, --replay, --host, --serial-port or --remote"
+                        .to_string();
+                return Ok(print_failure(output, message, EXIT_FAILURE));
+            }
+
+            if output != OutputFormat::Table {
+                match control.as_str() {
+                    "color" => print(output, &control_values(COLOR_NAMES, 7), String::new),
+                    "led" => print(output, &control_values(LED_PATTERN_NAMES, 7), String::new),
+                    "buzzer" => print(output, &control_values(BUZZER_PATTERN_NAMES, 7), String::new),
+                    "volume" => print(output, &control_values(VOLUME_NAMES, 10), String::new),
+                    "device" => {
+                        let mut transport: UsbTransport<Context> = globals.open_device()?;
+                        print(output, &read_device_info(transport.handle())?, String::new);
+                    }
+                    _ => {
+                        print(output, &all_controls(), String::new);
+                    }
+                }
+                return Ok(EXIT_SUCCESS);
+            }
+
             match control.as_str() {
                 "color" => {
-                    builder.push_record(["Color", "Value"]);
-                    builder.push_record(["Off", "0"]);
-                    builder.push_record(["Red", "1"]);
-                    builder.push_record(["Green", "2"]);
-                    builder.push_record(["Yellow", "3"]);
-                    builder.push_record(["Blue", "4"]);
-                    builder.push_record(["Purple", "5"]);
-                    builder.push_record(["Sky Blue", "6"]);
-                    builder.push_record(["White", "7"]);
-                    builder.push_record(["Keep", "8 - 15"]);
+                    builder.push_record(["Color", "Value", "Names"]);
+                    builder.push_record(["Off", "0", &names(COLOR_NAMES, 0)]);
+                    builder.push_record(["Red", "1", &names(COLOR_NAMES, 1)]);
+                    builder.push_record(["Green", "2", &names(COLOR_NAMES, 2)]);
+                    builder.push_record(["Yellow", "3", &names(COLOR_NAMES, 3)]);
+                    builder.push_record(["Blue", "4", &names(COLOR_NAMES, 4)]);
+                    builder.push_record(["Purple", "5", &names(COLOR_NAMES, 5)]);
+                    builder.push_record(["Sky Blue", "6", &names(COLOR_NAMES, 6)]);
+                    builder.push_record(["White", "7", &names(COLOR_NAMES, 7)]);
+                    builder.push_record(["Keep", "8 - 15", &names(COLOR_NAMES, LED_COLOR_KEEP)]);
                     let table: String = builder.build().with(Style::rounded()).to_string();
                     println!("{}", table);
                 }
                 "led" => {
-                    builder.push_record(["LED Pattern", "Value"]);
-                    builder.push_record(["Off", "0"]);
-                    builder.push_record(["On", "1"]);
-                    builder.push_record(["Pattern 1", "2"]);
-                    builder.push_record(["Pattern 2", "3"]);
-                    builder.push_record(["Pattern 3", "4"]);
-                    builder.push_record(["Pattern 4", "5"]);
-                    builder.push_record(["Pattern 5", "6"]);
-                    builder.push_record(["Pattern 6", "7"]);
-                    builder.push_record(["Keep", "8 - 15"]);
+                    builder.push_record(["LED Pattern", "Value", "Names"]);
+                    builder.push_record(["Off", "0", &names(LED_PATTERN_NAMES, 0)]);
+                    builder.push_record(["On", "1", &names(LED_PATTERN_NAMES, 1)]);
+                    builder.push_record(["Pattern 1", "2", &names(LED_PATTERN_NAMES, 2)]);
+                    builder.push_record(["Pattern 2", "3", &names(LED_PATTERN_NAMES, 3)]);
+                    builder.push_record(["Pattern 3", "4", &names(LED_PATTERN_NAMES, 4)]);
+                    builder.push_record(["Pattern 4", "5", &names(LED_PATTERN_NAMES, 5)]);
+                    builder.push_record(["Pattern 5", "6", &names(LED_PATTERN_NAMES, 6)]);
+                    builder.push_record(["Pattern 6", "7", &names(LED_PATTERN_NAMES, 7)]);
+                    builder.push_record(["Keep", "8 - 15", &names(LED_PATTERN_NAMES, LED_PATTERN_KEEP)]);
                     let table: String = builder.build().with(Style::rounded()).to_string();
                     println!("{}", table);
                 }
                 "buzzer" => {
-                    builder.push_record(["Buzzer Pattern", "Value"]);
-                    builder.push_record(["Off", "0"]);
-                    builder.push_record(["Continuously On", "1"]);
-                    builder.push_record(["Sweep", "2"]);
-                    builder.push_record(["Intermittent", "3"]);
-                    builder.push_record(["Weak Attention", "4"]);
-                    builder.push_record(["Strong Attention", "5"]);
-                    builder.push_record(["Shining Star Melody", "6"]);
-                    builder.push_record(["London Bridge Melody", "7"]);
-                    builder.push_record(["Keep", "8 - 15"]);
+                    builder.push_record(["Buzzer Pattern", "Value", "Names"]);
+                    builder.push_record(["Off", "0", &names(BUZZER_PATTERN_NAMES, 0)]);
+                    builder.push_record(["Continuously On", "1", &names(BUZZER_PATTERN_NAMES, 1)]);
+                    builder.push_record(["Sweep", "2", &names(BUZZER_PATTERN_NAMES, 2)]);
+                    builder.push_record(["Intermittent", "3", &names(BUZZER_PATTERN_NAMES, 3)]);
+                    builder.push_record(["Weak Attention", "4", &names(BUZZER_PATTERN_NAMES, 4)]);
+                    builder.push_record(["Strong Attention", "5", &names(BUZZER_PATTERN_NAMES, 5)]);
+                    builder.push_record(["Shining Star Melody", "6", &names(BUZZER_PATTERN_NAMES, 6)]);
+                    builder.push_record(["London Bridge Melody", "7", &names(BUZZER_PATTERN_NAMES, 7)]);
+                    builder.push_record(["Keep", "8 - 15", &names(BUZZER_PATTERN_NAMES, BUZZER_KEEP)]);
                     let table: String = builder.build().with(Style::rounded()).to_string();
                     println!("{}", table);
                 }
                 "volume" => {
-                    builder.push_record(["Volume Level", "Value"]);
-                    builder.push_record(["Silent", "0"]);
-                    builder.push_record(["1", "1"]);
-                    builder.push_record(["2", "2"]);
-                    builder.push_record(["3", "3"]);
-                    builder.push_record(["4", "4"]);
-                    builder.push_record(["5", "5"]);
-                    builder.push_record(["6", "6"]);
-                    builder.push_record(["7", "7"]);
-                    builder.push_record(["8", "8"]);
-                    builder.push_record(["9", "9"]);
-                    builder.push_record(["Max", "10"]);
-                    builder.push_record(["Keep", "11 - 15"]);
+                    builder.push_record(["Volume Level", "Value", "Names"]);
+                    builder.push_record(["Silent", "0", &names(VOLUME_NAMES, 0)]);
+                    builder.push_record(["1", "1", &names(VOLUME_NAMES, 1)]);
+                    builder.push_record(["2", "2", &names(VOLUME_NAMES, 2)]);
+                    builder.push_record(["3", "3", &names(VOLUME_NAMES, 3)]);
+                    builder.push_record(["4", "4", &names(VOLUME_NAMES, 4)]);
+                    builder.push_record(["5", "5", &names(VOLUME_NAMES, 5)]);
+                    builder.push_record(["6", "6", &names(VOLUME_NAMES, 6)]);
+                    builder.push_record(["7", "7", &names(VOLUME_NAMES, 7)]);
+                    builder.push_record(["8", "8", &names(VOLUME_NAMES, 8)]);
+                    builder.push_record(["9", "9", &names(VOLUME_NAMES, 9)]);
+                    builder.push_record(["Max", "10", &names(VOLUME_NAMES, 10)]);
+                    builder.push_record(["Keep", "11 - 15", &names(VOLUME_NAMES, BUZZER_VOLUME_KEEP)]);
                     let table: String = builder.build().with(Style::rounded()).to_string();
                     println!("{}", table);
                 }
                 "device" => {
-                    let mut handle: DeviceHandle<Context> = setup_device()?;
-                    print_device_info(&mut handle, &mut builder)?;
+                    let mut transport: UsbTransport<Context> = globals.open_device()?;
+                    print_device_info(transport.handle(), &mut builder)?;
                 }
                 "all" => {
                     builder.push_record([
@@ -320,13 +1201,207 @@ fn main() -> Result<()> {
                 }
                 _ => println!("Invalid control type"),
             }
+            Ok(EXIT_SUCCESS)
+        }
+        Some(("exec", sub_matches)) => {
+            let command: Vec<String> = sub_matches
+                .get_many::<String>("COMMAND")
+                .expect("Command is required")
+                .cloned()
+                .collect();
+            let value = |id: &str| -> u8 {
+                *sub_matches.get_one::<u8>(id).expect("Exec state has a default")
+            };
+            let options = ExecOptions {
+                running: ExecState {
+                    color: value("running-color"),
+                    pattern: value("running-pattern"),
+                    buzzer_pattern: 0,
+                    repetition: 0,
+                    volume: BUZZER_VOLUME_KEEP,
+                },
+                success: ExecState {
+                    color: value("success-color"),
+                    pattern: value("success-pattern"),
+                    buzzer_pattern: 0,
+                    repetition: 0,
+                    volume: BUZZER_VOLUME_KEEP,
+                },
+                failure: ExecState {
+                    color: value("failure-color"),
+                    pattern: value("failure-pattern"),
+                    buzzer_pattern: value("failure-buzzer"),
+                    repetition: value("failure-repetition"),
+                    volume: value("failure-volume"),
+                },
+                timeout: sub_matches
+                    .get_one::<u64>("timeout")
+                    .map(|s| Duration::from_secs(*s)),
+            };
+
+            let mut transport: Box<dyn Transport> = globals.transport(FrameLog::Stderr)?;
+            cli::exec::run(transport.as_mut(), &command, &options)
+        }
+        Some(("watch-log", sub_matches)) => {
+            let path: &PathBuf = sub_matches
+                .get_one::<PathBuf>("FILE")
+                .expect("File is required");
+            let options = WatchOptions {
+                rules: sub_matches
+                    .get_many::<Rule>("rule")
+                    .expect("At least one rule is required")
+                    .cloned()
+                    .collect(),
+                debounce: Duration::from_secs(
+                    *sub_matches.get_one::<u64>("debounce").expect("Debounce has a default"),
+                ),
+                clear_after: sub_matches
+                    .get_one::<u64>("clear-after")
+                    .map(|s| Duration::from_secs(*s)),
+                from_start: sub_matches.get_flag("from-start"),
+            };
+
+            let mut transport: Box<dyn Transport> = globals.transport(FrameLog::Stdout)?;
+            cli::watch_log::run(transport.as_mut(), path, &options)?;
+            Ok(EXIT_SUCCESS)
+        }
+        Some(("shell", _)) => {
+            let mut transport: Box<dyn Transport> = globals.transport(FrameLog::Stdout)?;
+            cli::shell::run(transport.as_mut(), &globals.config)?;
+            Ok(EXIT_SUCCESS)
+        }
+        Some(("tui", _)) => {
+            // The frames would draw over the control panel, which shows their effect anyway
+            let mut transport: Box<dyn Transport> = globals.transport(FrameLog::Hidden)?;
+            if let Err(e) = cli::tui::run(transport.as_mut()) {
+                eprintln!("Failed to run the terminal UI: {}", e);
+                return Ok(EXIT_FAILURE);
+            }
+            Ok(EXIT_SUCCESS)
+        }
+        Some(("completions", sub_matches)) => {
+            let shell: &String = sub_matches
+                .get_one::<String>("SHELL")
+                .expect("Shell is required");
+            if let Err(e) = write_registration(&cli(), shell, &mut std::io::stdout()) {
+                eprintln!("Failed to write completions: {}", e);
+                return Ok(EXIT_FAILURE);
+            }
+            Ok(EXIT_SUCCESS)
+        }
+        Some(("man", sub_matches)) => {
+            let dir = sub_matches.get_one::<PathBuf>("out-dir").map(PathBuf::as_path);
+            if let Err(e) = write_man(cli(), dir, &mut std::io::stdout()) {
+                eprintln!("Failed to write man page: {}", e);
+                return Ok(EXIT_FAILURE);
+            }
+            Ok(EXIT_SUCCESS)
+        }
+        Some(("raw", sub_matches)) => {
+            let bytes: Vec<u8> = sub_matches
+                .get_many::<u8>("BYTES")
+                .expect("Bytes are required")
+                .copied()
+                .collect();
+            let data: Data = Data::from_array(bytes.try_into().expect("Exactly 8 bytes"));
+            if let Err(e) = data.validate() {
+                if !sub_matches.get_flag("force") {
+                    let message = format!("Invalid frame, {}. Use --force to send it anyway", e);
+                    return Ok(print_failure(output, message, EXIT_FAILURE));
+                }
+            }
+
+            // The frame is shown below, so --dry-run doesn't print it a second time
+            let log: FrameLog = if globals.dry_run { FrameLog::Hidden } else { FrameLog::Stdout };
+            let mut transport: Box<dyn Transport> = globals.transport(log)?;
+            transport.send(data)?;
+            let mut result = RawResult {
+                sent: data.to_hex(),
+                decoded: data.describe(),
+                received: None,
+            };
+            if sub_matches.get_flag("read") {
+                let mut buf: [u8; 64] = [0u8; 64];
+                let read: usize = transport.receive(&mut buf)?;
+                result.received = Some(bytes_to_hex(&buf[..read]));
+            }
+            print(output, &result, || {
+                let label: &str = if globals.dry_run { "Would send" } else { "Sent" };
+                let mut lines: String = format!("{}: {}  ({})", label, result.sent, result.decoded);
+                if let Some(received) = &result.received {
+                    lines.push_str(&format!("\nReceived: {}", received));
+                }
+                lines
+            });
+            Ok(EXIT_SUCCESS)
+        }
+        Some(("serve", sub_matches)) => serve(sub_matches, globals),
+        Some(("emulate", sub_matches)) => {
+            let listen: &String = sub_matches.get_one::<String>("listen").expect("Listen has a default");
+            let profile: &DeviceProfile = &globals.model;
+            let listener: TcpListener = match TcpListener::bind(listen) {
+                Ok(listener) => listener,
+                Err(e) => return Ok(print_failure(output, format!("Failed to listen on {}: {}", listen, e), EXIT_FAILURE)),
+            };
+            eprintln!("Emulating {} on {}", profile.name, listen);
+
+            let emulator: Emulator = Emulator::new(Simulator::for_profile(profile.clone()));
+            let show = move |simulator: &Simulator| match simulator.tower() {
+                Some(tower) => print(output, tower, || tower.to_string()),
+                None => print(output, simulator.state(), || simulator.state().to_string()),
+            };
+            // A thread per client, each seeing the changes of the others
+            for stream in listener.incoming() {
+                let mut stream: TcpStream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        eprintln!("Failed to accept a connection: {}", e);
+                        continue;
+                    }
+                };
+                let peer: String = stream.peer_addr().map_or("?".to_string(), |a| a.to_string());
+                eprintln!("{} connected", peer);
+                let emulator: Emulator = emulator.clone();
+                thread::spawn(move || match emulator.serve(&mut stream, show) {
+                    Ok(()) => eprintln!("{} disconnected", peer),
+                    Err(e) => eprintln!("{} disconnected: {}", peer, e),
+                });
+            }
+            Ok(EXIT_SUCCESS)
+        }
+        Some(("replay", sub_matches)) => {
+            let path: &PathBuf = sub_matches
+                .get_one::<PathBuf>("FILE")
+                .expect("File is required");
+            let frames: Vec<RecordedFrame> = load_recording(path)?;
+            let delay: bool = !sub_matches.get_flag("no-delay");
+
+            let summary: ReplaySummary = if sub_matches.get_flag("simulate") {
+                cli::replay::run(&mut Simulator::new(), &frames, delay, |simulator, frame, data| {
+                    println!("{:>6}ms  {}  {}", frame.elapsed_ms, data.to_hex(), simulator.state());
+                })
+            } else {
+                let mut transport: Box<dyn Transport> = globals.transport(FrameLog::Stdout)?;
+                cli::replay::run(transport.as_mut(), &frames, delay, |_, _, _| {})
+            };
+            print(output, &summary, || {
+                format!(
+                    "Replayed {} frames, {} failed, {} skipped",
+                    summary.sent, summary.failed, summary.skipped
+                )
+            });
+            Ok(if summary.failed == 0 { EXIT_SUCCESS } else { EXIT_FAILURE })
+        }
+        Some(("stream", _)) => {
+            let mut transport: Box<dyn Transport> = globals.transport(FrameLog::Stderr)?;
+            if let Err(e) = cli::stream::run(transport.as_mut()) {
+                eprintln!("Failed to read commands: {}", e);
+                return Ok(EXIT_FAILURE);
+            }
+            Ok(EXIT_SUCCESS)
         }
         _ => {
             unreachable!("Exhausted list of subcommands and subcommand_required prevents `None`")
         }
     }
-    // cleanup after use
-    // handle.release_interface(endpoint.iface)?;
-    println!("Completed!~");
-    Ok(())
 }
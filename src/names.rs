@@ -0,0 +1,224 @@
+use crate::constants::*;
+
+// Names accepted on the command line for each control, compared ignoring case, `-`, `_` and
+// spaces. The first name listed for a value is its canonical name
+pub const COLOR_NAMES: &[(&str, u8)] = &[
+    ("off", LED_COLOR_OFF),
+    ("red", LED_COLOR_RED),
+    ("green", LED_COLOR_GREEN),
+    ("yellow", LED_COLOR_YELLOW),
+    ("amber", LED_COLOR_YELLOW),
+    ("blue", LED_COLOR_BLUE),
+    ("purple", LED_COLOR_PURPLE),
+    ("magenta", LED_COLOR_PURPLE),
+    ("light-blue", LED_COLOR_LIGHTBLUE),
+    ("sky-blue", LED_COLOR_LIGHTBLUE),
+    ("cyan", LED_COLOR_LIGHTBLUE),
+    ("white", LED_COLOR_WHITE),
+    ("keep", LED_COLOR_KEEP),
+];
+
+pub const LED_PATTERN_NAMES: &[(&str, u8)] = &[
+    ("off", LED_OFF),
+    ("on", LED_ON),
+    ("pattern-1", LED_PATTERN1),
+    ("blink-1", LED_PATTERN1),
+    ("blink", LED_PATTERN1),
+    ("pattern-2", LED_PATTERN2),
+    ("blink-2", LED_PATTERN2),
+    ("pattern-3", LED_PATTERN3),
+    ("blink-3", LED_PATTERN3),
+    ("pattern-4", LED_PATTERN4),
+    ("blink-4", LED_PATTERN4),
+    ("pattern-5", LED_PATTERN5),
+    ("blink-5", LED_PATTERN5),
+    ("pattern-6", LED_PATTERN6),
+    ("blink-6", LED_PATTERN6),
+    ("keep", LED_PATTERN_KEEP),
+];
+
+pub const BUZZER_PATTERN_NAMES: &[(&str, u8)] = &[
+    ("off", BUZZER_OFF),
+    ("stop", BUZZER_OFF),
+    ("continuous", BUZZER_ON),
+    ("on", BUZZER_ON),
+    ("sweep", BUZZER_SWEEP),
+    ("intermittent", BUZZER_INTERMITTENT),
+    ("call-sign", BUZZER_INTERMITTENT),
+    ("weak-attention", BUZZER_WEEK_ATTENTION),
+    ("low-urgency", BUZZER_WEEK_ATTENTION),
+    ("strong-attention", BUZZER_STRONG_ATTENTION),
+    ("high-urgency", BUZZER_STRONG_ATTENTION),
+    ("shining-star", BUZZER_SHINING_STAR),
+    ("twinkle-star", BUZZER_SHINING_STAR),
+    ("london-bridge", BUZZER_LONDON_BRIDGE),
+    ("keep", BUZZER_KEEP),
+];
+
+pub const VOLUME_NAMES: &[(&str, u8)] = &[
+    ("silent", BUZZER_VOLUME_OFF),
+    ("mute", BUZZER_VOLUME_OFF),
+    ("max", BUZZER_VOLUME_MAX),
+    ("keep", BUZZER_VOLUME_KEEP),
+];
+
+pub const REPETITION_NAMES: &[(&str, u8)] = &[
+    ("continuous", BUZZER_COUNT_CONTINUE),
+    ("keep", BUZZER_COUNT_KEEP),
+];
+
+pub const SETTING_NAMES: &[(&str, u8)] = &[
+    ("on", SETTING_ON),
+    ("enabled", SETTING_ON),
+    ("1", SETTING_ON),
+    ("off", SETTING_OFF),
+    ("disabled", SETTING_OFF),
+    ("0", SETTING_OFF),
+];
+
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, '-' | '_' | ' '))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+pub fn lookup(names: &[(&str, u8)], name: &str) -> Option<u8> {
+    let name = normalize(name);
+    names
+        .iter()
+        .find(|(n, _)| normalize(n) == name)
+        .map(|(_, value)| *value)
+}
+
+// Every name accepted for `value`, canonical name first
+pub fn names_of(names: &[(&'static str, u8)], value: u8) -> Vec<&'static str> {
+    names
+        .iter()
+        .filter(|(_, v)| *v == value)
+        .map(|(n, _)| *n)
+        .collect()
+}
+
+pub fn canonical_name(names: &[(&'static str, u8)], value: u8) -> Option<&'static str> {
+    names_of(names, value).first().copied()
+}
+
+fn canonical_names(names: &[(&'static str, u8)]) -> Vec<&'static str> {
+    names
+        .iter()
+        .filter(|(n, v)| canonical_name(names, *v) == Some(*n))
+        .map(|(n, _)| *n)
+        .collect()
+}
+
+// Parse a name or a number up to `max`. Numbers above `max` that still fit in 4 bits are what the
+// protocol treats as "keep the current setting", so they are only accepted spelled as `keep`
+fn parse_value(
+    s: &str,
+    names: &[(&'static str, u8)],
+    max: u8,
+    what: &str,
+) -> Result<u8, String> {
+    match s.trim().parse::<u8>() {
+        Ok(n) if n <= max => Ok(n),
+        Ok(n) if n <= 0xF => Err(format!(
+            "{} {} keeps the current setting, use `keep` if that is intended",
+            what, n
+        )),
+        Ok(n) => Err(format!("{} {} is out of range 0-{}", what, n, max)),
+        Err(_) => lookup(names, s).ok_or_else(|| {
+            format!(
+                "Unknown {}: {} (expected 0-{} or one of {})",
+                what,
+                s,
+                max,
+                canonical_names(names).join(", ")
+            )
+        }),
+    }
+}
+
+pub fn parse_color(s: &str) -> Result<u8, String> {
+    parse_value(s, COLOR_NAMES, LED_COLOR_WHITE, "color")
+}
+
+pub fn parse_led_pattern(s: &str) -> Result<u8, String> {
+    parse_value(s, LED_PATTERN_NAMES, LED_PATTERN6, "light pattern")
+}
+
+pub fn parse_buzzer_pattern(s: &str) -> Result<u8, String> {
+    parse_value(s, BUZZER_PATTERN_NAMES, BUZZER_LONDON_BRIDGE, "buzzer pattern")
+}
+
+pub fn parse_volume(s: &str) -> Result<u8, String> {
+    parse_value(s, VOLUME_NAMES, BUZZER_VOLUME_MAX, "volume")
+}
+
+pub fn parse_repetition(s: &str) -> Result<u8, String> {
+    parse_value(s, REPETITION_NAMES, BUZZER_COUNT_MAX, "repetition")
+}
+
+// Parse an on/off setting such as the connection display
+pub fn parse_setting(s: &str) -> Result<bool, String> {
+    lookup(SETTING_NAMES, s)
+        .map(|value| value == SETTING_ON)
+        .ok_or_else(|| format!("Unknown setting: {} (expected on or off)", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_ignore_case_and_separators() {
+        assert_eq!(parse_color("Light_Blue"), Ok(LED_COLOR_LIGHTBLUE));
+        assert_eq!(parse_color("SKY BLUE"), Ok(LED_COLOR_LIGHTBLUE));
+        assert_eq!(parse_led_pattern("Blink-2"), Ok(LED_PATTERN2));
+        assert_eq!(parse_buzzer_pattern("londonbridge"), Ok(BUZZER_LONDON_BRIDGE));
+        assert_eq!(parse_volume("MUTE"), Ok(BUZZER_VOLUME_OFF));
+    }
+
+    #[test]
+    fn numbers_up_to_the_maximum_are_accepted() {
+        assert_eq!(parse_color("0"), Ok(LED_COLOR_OFF));
+        assert_eq!(parse_color(" 7 "), Ok(LED_COLOR_WHITE));
+        assert_eq!(parse_volume("10"), Ok(BUZZER_VOLUME_MAX));
+        assert_eq!(parse_repetition("14"), Ok(BUZZER_COUNT_MAX));
+    }
+
+    #[test]
+    fn keep_is_only_accepted_by_name() {
+        assert_eq!(parse_color("keep"), Ok(LED_COLOR_KEEP));
+        assert_eq!(parse_repetition("Keep"), Ok(BUZZER_COUNT_KEEP));
+        assert_eq!(
+            parse_color("15"),
+            Err("color 15 keeps the current setting, use `keep` if that is intended".to_string())
+        );
+        assert!(parse_volume("11").unwrap_err().contains("keeps the current setting"));
+    }
+
+    #[test]
+    fn out_of_range_numbers_and_unknown_names_are_refused() {
+        assert_eq!(parse_color("16"), Err("color 16 is out of range 0-7".to_string()));
+        assert_eq!(parse_repetition("300"), Err("Unknown repetition: 300 (expected 0-14 or one of continuous, keep)".to_string()));
+        assert_eq!(
+            parse_led_pattern("strobe"),
+            Err("Unknown light pattern: strobe (expected 0-7 or one of off, on, pattern-1, pattern-2, pattern-3, pattern-4, pattern-5, pattern-6, keep)".to_string())
+        );
+    }
+
+    #[test]
+    fn canonical_name_is_the_first_listed() {
+        assert_eq!(canonical_name(COLOR_NAMES, LED_COLOR_YELLOW), Some("yellow"));
+        assert_eq!(names_of(BUZZER_PATTERN_NAMES, BUZZER_OFF), ["off", "stop"]);
+        assert_eq!(canonical_name(VOLUME_NAMES, 5), None);
+    }
+
+    #[test]
+    fn settings_are_on_or_off() {
+        assert_eq!(parse_setting("Enabled"), Ok(true));
+        assert_eq!(parse_setting("0"), Ok(false));
+        assert!(parse_setting("maybe").is_err());
+    }
+}
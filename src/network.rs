@@ -0,0 +1,265 @@
+use crate::constants::*;
+use crate::{Data, DeviceProfile, FrameFormat, Simulator, Transport};
+use rusb::Result;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+// The port PNS devices listen on unless it was changed in their settings
+pub const PNS_PORT: u16 = 10000;
+
+// PNS packets start with the product category "AB", then the command identifier
+pub const PNS_PRODUCT_CATEGORY: [u8; 2] = *b"AB";
+pub const PNS_OPERATION_CONTROL: u8 = b'S';
+pub const PNS_CLEAR: u8 = b'C';
+pub const PNS_ACK: u8 = 0x06;
+pub const PNS_NAK: u8 = 0x15;
+
+// LED unit and buzzer values in an operation control packet
+pub const PNS_OFF: u8 = 0x00;
+pub const PNS_ON: u8 = 0x01;
+pub const PNS_PATTERN1: u8 = 0x02;
+pub const PNS_NO_CHANGE: u8 = 0x09;
+
+impl DeviceProfile {
+    // LAN connected NH and LA6 series units speaking the PNS protocol. They take the tower
+    // frames of the LR-USB, translated by `PnsTransport`
+    pub fn pns() -> Self {
+        DeviceProfile {
+            name: "LA6-PNS".to_string(),
+            // Not a USB device, so never found by `open_device`
+            product_id: 0,
+            colors: vec![LED_COLOR_RED, LED_COLOR_YELLOW, LED_COLOR_GREEN, LED_COLOR_BLUE, LED_COLOR_WHITE],
+            tiers: 5,
+            buzzer: true,
+            frame_format: FrameFormat::LrUsb,
+        }
+    }
+}
+
+// The PNS value for a tier nibble of an LR-USB frame
+fn pns_tier(nibble: u8) -> u8 {
+    match nibble {
+        0x0 => PNS_OFF,
+        0x1 => PNS_ON,
+        0x2 => PNS_PATTERN1,
+        _ => PNS_NO_CHANGE,
+    }
+}
+
+// The PNS buzzer pattern for an alarm control byte. PNS units have buzzer patterns 1 to 4 and no
+// repetition count, later NE-USB patterns sound as pattern 4. Values past London Bridge keep the
+// buzzer as it is, like they do on the NE-USB
+fn pns_buzzer(alarm_control: u8) -> u8 {
+    match alarm_control & 0x0F {
+        BUZZER_OFF => PNS_OFF,
+        pattern @ BUZZER_ON..=BUZZER_LONDON_BRIDGE => pattern.min(0x4),
+        _ => PNS_NO_CHANGE,
+    }
+}
+
+// The operation control packet for an LR-USB frame: LED units 1 to 5 from the top, then the buzzer
+pub fn pns_packet(data: &Data) -> Vec<u8> {
+    let tiers: [u8; 3] = [data.led_control, data.reserved_first, data.reserved_second];
+    let mut packet: Vec<u8> = PNS_PRODUCT_CATEGORY.to_vec();
+    packet.extend([PNS_OPERATION_CONTROL, BLANK, 0x00, 0x06]);
+    packet.extend(tiers.iter().flat_map(|byte| [pns_tier(byte >> 4), pns_tier(byte & 0x0F)]).take(5));
+    packet.push(pns_buzzer(data.alarm_control));
+    packet
+}
+
+// The LR-USB frame for the LED units and buzzer of an operation control packet, undoing `pns_packet`.
+// Every blinking and flashing pattern becomes a blinking tier
+pub fn pns_frame(units: &[u8; 5], buzzer: u8) -> Data {
+    let nibbles: Vec<u8> = units
+        .iter()
+        .map(|unit| match *unit {
+            PNS_OFF => 0x0,
+            PNS_ON => 0x1,
+            PNS_NO_CHANGE => 0xF,
+            _ => 0x2,
+        })
+        .chain([0xF])
+        .collect();
+    let alarm_pattern: u8 = match buzzer {
+        PNS_OFF => BUZZER_OFF,
+        PNS_NO_CHANGE => BUZZER_KEEP,
+        pattern => pattern,
+    };
+    Data::from_array([
+        COMMAND_VERSION,
+        COMMAND_ID_CONTROL,
+        BUZZER_COUNT_KEEP << 4 | alarm_pattern,
+        0xFF,
+        nibbles[0] << 4 | nibbles[1],
+        nibbles[2] << 4 | nibbles[3],
+        nibbles[4] << 4 | nibbles[5],
+        BLANK,
+    ])
+}
+
+pub(crate) fn io_error(e: io::Error) -> rusb::Error {
+    match e.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => rusb::Error::Timeout,
+        io::ErrorKind::ConnectionRefused | io::ErrorKind::NotFound => rusb::Error::NoDevice,
+        io::ErrorKind::PermissionDenied => rusb::Error::Access,
+        _ => rusb::Error::Io,
+    }
+}
+
+// A unit driven with the PNS protocol, over TCP for LAN connected units or any other stream such
+// as a serial port. Each packet is answered with ACK, or NAK for packets the unit refuses
+pub struct PnsTransport<S: Read + Write = TcpStream> {
+    stream: S,
+    profile: DeviceProfile,
+}
+
+impl PnsTransport<TcpStream> {
+    // Connect to `host`, `address:port` or just the address for the default port, waiting at
+    // most `timeout` to connect and for each answer
+    pub fn connect(host: &str, timeout: Duration) -> Result<Self> {
+        let addresses: Vec<SocketAddr> = match host.to_socket_addrs() {
+            Ok(addresses) => addresses.collect(),
+            Err(_) => (host, PNS_PORT).to_socket_addrs().map_err(io_error)?.collect(),
+        };
+        let mut last_error: rusb::Error = rusb::Error::NotFound;
+        for address in addresses {
+            match TcpStream::connect_timeout(&address, timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(timeout)).map_err(io_error)?;
+                    stream.set_write_timeout(Some(timeout)).map_err(io_error)?;
+                    let _ = stream.set_nodelay(true);
+                    return Ok(PnsTransport {
+                        stream,
+                        profile: DeviceProfile::pns(),
+                    });
+                }
+                Err(e) => {
+                    eprintln!("Failed to connect to {}: {}", address, e);
+                    last_error = io_error(e);
+                }
+            }
+        }
+        Err(last_error)
+    }
+}
+
+impl<S: Read + Write> PnsTransport<S> {
+    // A unit already connected through `stream`
+    pub fn new(stream: S, profile: DeviceProfile) -> Self {
+        PnsTransport { stream, profile }
+    }
+
+    // The model of the unit, LA6-PNS by default
+    pub fn set_profile(&mut self, profile: DeviceProfile) {
+        self.profile = profile;
+    }
+
+    // Send a packet and wait for the unit to acknowledge it
+    fn request(&mut self, packet: &[u8]) -> Result<usize> {
+        self.stream.write_all(packet).map_err(io_error)?;
+        let mut answer: [u8; 1] = [0u8];
+        self.stream.read_exact(&mut answer).map_err(io_error)?;
+        match answer[0] {
+            PNS_ACK => Ok(packet.len()),
+            PNS_NAK => {
+                eprintln!("The unit refused the command");
+                Err(rusb::Error::InvalidParam)
+            }
+            other => {
+                eprintln!("Unexpected answer from the unit: {:02X}", other);
+                Err(rusb::Error::Other)
+            }
+        }
+    }
+
+    // Turn every LED unit and the buzzer off
+    pub fn clear(&mut self) -> Result<usize> {
+        let mut packet: Vec<u8> = PNS_PRODUCT_CATEGORY.to_vec();
+        packet.extend([PNS_CLEAR, BLANK, 0x00, 0x00]);
+        self.request(&packet)
+    }
+}
+
+impl<S: Read + Write> Transport for PnsTransport<S> {
+    fn send(&mut self, data: Data) -> Result<usize> {
+        if let Err(e) = self.profile.check(&data) {
+            eprintln!("Unsupported command: {}", e);
+            return Err(rusb::Error::NotSupported);
+        }
+        self.request(&pns_packet(&data))
+    }
+
+    fn profile(&self) -> Option<&DeviceProfile> {
+        Some(&self.profile)
+    }
+}
+
+// Answers a PNS client such as `PnsTransport` the way a LAN connected unit would, with a
+// `Simulator` carrying out the commands. Clients may also send the 8 byte frames of the simulated
+// model as they are, which are answered the same way. Clones share the simulated unit, so each
+// client can be served on a thread of its own
+#[derive(Clone)]
+pub struct Emulator {
+    simulator: Arc<Mutex<Simulator>>,
+}
+
+impl Emulator {
+    pub fn new(simulator: Simulator) -> Self {
+        Emulator {
+            simulator: Arc::new(Mutex::new(simulator)),
+        }
+    }
+
+    // The simulated unit, locked until the guard is dropped
+    pub fn simulator(&self) -> MutexGuard<'_, Simulator> {
+        self.simulator.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Answer packets from `stream` until the client disconnects, calling `changed` after each
+    // one the simulated unit took, with the unit still locked
+    pub fn serve<S: Read + Write>(&self, stream: &mut S, mut changed: impl FnMut(&Simulator)) -> io::Result<()> {
+        loop {
+            let mut header: [u8; 2] = [0u8; 2];
+            match stream.read_exact(&mut header) {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                result => result?,
+            }
+            let data: Option<Data> = if header == PNS_PRODUCT_CATEGORY {
+                self.read_pns(stream)?
+            } else {
+                let mut frame: [u8; 8] = [0u8; 8];
+                frame[..2].copy_from_slice(&header);
+                stream.read_exact(&mut frame[2..])?;
+                Some(Data::from_array(frame))
+            };
+            let taken: bool = {
+                let mut simulator: MutexGuard<Simulator> = self.simulator();
+                let taken: bool = data.is_some_and(|data| simulator.send(data).is_ok());
+                if taken {
+                    changed(&simulator);
+                }
+                taken
+            };
+            stream.write_all(&[if taken { PNS_ACK } else { PNS_NAK }])?;
+        }
+    }
+
+    // The frame for the rest of a PNS packet, or `None` for commands a unit wouldn't take
+    fn read_pns<S: Read>(&self, stream: &mut S) -> io::Result<Option<Data>> {
+        // Command identifier, a spare byte and the size of the data
+        let mut head: [u8; 4] = [0u8; 4];
+        stream.read_exact(&mut head)?;
+        let mut body: Vec<u8> = vec![0u8; u16::from_be_bytes([head[2], head[3]]) as usize];
+        stream.read_exact(&mut body)?;
+        if self.simulator().tower().is_none() {
+            return Ok(None);
+        }
+        Ok(match (head[0], body.as_slice()) {
+            (PNS_OPERATION_CONTROL, &[u1, u2, u3, u4, u5, buzzer]) => Some(pns_frame(&[u1, u2, u3, u4, u5], buzzer)),
+            (PNS_CLEAR, []) => Some(pns_frame(&[PNS_OFF; 5], PNS_OFF)),
+            _ => None,
+        })
+    }
+}
@@ -0,0 +1,254 @@
+use crate::{Data, DeviceCommand, DeviceProfile, Transport, UsbTransport};
+use rusb::Result;
+use std::thread;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+enum Request {
+    // The result, and how many attempts the transport took
+    Send(Data, oneshot::Sender<(Result<usize>, u32)>),
+    Execute(DeviceCommand, oneshot::Sender<Result<usize>>),
+    Receive(oneshot::Sender<Result<Vec<u8>>>),
+    Profile(oneshot::Sender<Option<DeviceProfile>>),
+}
+
+impl std::fmt::Debug for Request {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Request::Send(data, _) => write!(f, "Send({})", data.to_hex()),
+            Request::Execute(command, _) => write!(f, "Execute({})", command.name()),
+            Request::Receive(_) => write!(f, "Receive"),
+            Request::Profile(_) => write!(f, "Profile"),
+        }
+    }
+}
+
+// One step of a timed sequence: send `command`, then leave it showing for `hold`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub command: DeviceCommand,
+    pub hold: Duration,
+}
+
+// Async handle to a device for tokio. The blocking USB transfers, which can take up to
+// `SEND_TIMEOUT`, run on a thread of their own so they never stall the executor. Clones share
+// the device, and the thread exits once every clone has been dropped
+#[derive(Debug, Clone)]
+pub struct Patlite {
+    requests: mpsc::UnboundedSender<Request>,
+}
+
+impl Patlite {
+    // Open the device with `serial`, or the first one found, on the I/O thread
+    pub async fn open(serial: Option<String>) -> Result<Patlite> {
+        Patlite::open_with(move || UsbTransport::open(serial.as_deref())).await
+    }
+
+    // Open a transport with `open` on the I/O thread, for transports that can't be sent between
+    // threads once open
+    pub async fn open_with<T, F>(open: F) -> Result<Patlite>
+    where
+        T: Transport + 'static,
+        F: FnOnce() -> Result<T> + Send + 'static,
+    {
+        let (opened, result) = oneshot::channel();
+        let (requests, receiver) = mpsc::unbounded_channel();
+        thread::spawn(move || match open() {
+            Ok(transport) => {
+                let _ = opened.send(Ok(()));
+                serve(transport, receiver);
+            }
+            Err(e) => {
+                let _ = opened.send(Err(e));
+            }
+        });
+        result.await.map_err(|_| rusb::Error::Other)??;
+        Ok(Patlite { requests })
+    }
+
+    // Drive any transport from async code, e.g. a `UsbTransport` with retries or the `Simulator`
+    pub fn new<T: Transport + Send + 'static>(transport: T) -> Patlite {
+        let (requests, receiver) = mpsc::unbounded_channel();
+        thread::spawn(move || serve(transport, receiver));
+        Patlite { requests }
+    }
+
+    pub async fn send(&self, data: Data) -> Result<usize> {
+        self.send_with_attempts(data).await.0
+    }
+
+    // Send `data`, also reporting how many attempts the transport needed
+    pub async fn send_with_attempts(&self, data: Data) -> (Result<usize>, u32) {
+        let (reply, result) = oneshot::channel();
+        if self.requests.send(Request::Send(data, reply)).is_err() {
+            return (Err(rusb::Error::NoDevice), 0);
+        }
+        result.await.unwrap_or((Err(rusb::Error::NoDevice), 0))
+    }
+
+    // Send `command` laid out for the model of the device, see `DeviceCommand::execute`
+    pub async fn execute(&self, command: &DeviceCommand) -> Result<usize> {
+        let (reply, result) = oneshot::channel();
+        self.requests
+            .send(Request::Execute(command.clone(), reply))
+            .map_err(|_| rusb::Error::NoDevice)?;
+        result.await.map_err(|_| rusb::Error::NoDevice)?
+    }
+
+    // Wait for a response from the device
+    pub async fn receive(&self) -> Result<Vec<u8>> {
+        let (reply, result) = oneshot::channel();
+        self.requests
+            .send(Request::Receive(reply))
+            .map_err(|_| rusb::Error::NoDevice)?;
+        result.await.map_err(|_| rusb::Error::NoDevice)?
+    }
+
+    // The model of the device, `None` for stand-ins that take the frames of the NE-SN-USB
+    pub async fn profile(&self) -> Result<Option<DeviceProfile>> {
+        let (reply, result) = oneshot::channel();
+        self.requests
+            .send(Request::Profile(reply))
+            .map_err(|_| rusb::Error::NoDevice)?;
+        result.await.map_err(|_| rusb::Error::NoDevice)
+    }
+
+    // Send each step's command and wait out its hold time. Dropping the future cancels the
+    // sequence, e.g. from `tokio::select!` or `tokio::time::timeout`; the device keeps showing
+    // the last step sent
+    pub async fn play(&self, steps: &[Step]) -> Result<()> {
+        for step in steps {
+            self.execute(&step.command).await?;
+            tokio::time::sleep(step.hold).await;
+        }
+        Ok(())
+    }
+}
+
+// Carry out requests on the I/O thread until every handle is gone
+fn serve<T: Transport>(mut transport: T, mut receiver: mpsc::UnboundedReceiver<Request>) {
+    while let Some(request) = receiver.blocking_recv() {
+        match request {
+            Request::Send(data, reply) => {
+                let sent = transport.send(data);
+                let _ = reply.send((sent, transport.attempts()));
+            }
+            Request::Execute(command, reply) => {
+                let _ = reply.send(command.execute(&mut transport));
+            }
+            Request::Receive(reply) => {
+                let mut buf: [u8; 64] = [0u8; 64];
+                let received = transport.receive(&mut buf).map(|read| buf[..read].to_vec());
+                let _ = reply.send(received);
+            }
+            Request::Profile(reply) => {
+                let _ = reply.send(transport.profile().cloned());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{LED_COLOR_BLUE, LED_COLOR_GREEN, LED_COLOR_RED, LED_ON};
+    use crate::{DeviceState, Simulator};
+    use std::sync::{mpsc as std_mpsc, Arc, Mutex};
+
+    // A simulator the test can look into while the I/O thread owns it, telling when it's dropped
+    struct Shared {
+        simulator: Arc<Mutex<Simulator>>,
+        dropped: std_mpsc::Sender<()>,
+    }
+
+    impl Transport for Shared {
+        fn send(&mut self, data: Data) -> Result<usize> {
+            self.simulator.lock().unwrap_or_else(|e| e.into_inner()).send(data)
+        }
+
+        fn receive(&mut self, buf: &mut [u8]) -> Result<usize> {
+            self.simulator.lock().unwrap_or_else(|e| e.into_inner()).receive(buf)
+        }
+    }
+
+    impl Drop for Shared {
+        fn drop(&mut self) {
+            let _ = self.dropped.send(());
+        }
+    }
+
+    async fn open() -> (Patlite, Arc<Mutex<Simulator>>, std_mpsc::Receiver<()>) {
+        let simulator: Arc<Mutex<Simulator>> = Arc::default();
+        let (dropped, on_drop) = std_mpsc::channel();
+        let shared = Shared { simulator: simulator.clone(), dropped };
+        let patlite: Patlite = Patlite::open_with(move || Ok(shared)).await.unwrap();
+        (patlite, simulator, on_drop)
+    }
+
+    fn color(simulator: &Mutex<Simulator>) -> u8 {
+        let state: DeviceState = *simulator.lock().unwrap_or_else(|e| e.into_inner()).state();
+        state.color
+    }
+
+    fn light(color: u8, hold: Duration) -> Step {
+        Step { command: DeviceCommand::Light { color, pattern: LED_ON }, hold }
+    }
+
+    #[tokio::test]
+    async fn open_with_passes_on_failures_to_open() {
+        let opened = Patlite::open_with(|| Err::<Simulator, _>(rusb::Error::Access)).await;
+        assert_eq!(opened.unwrap_err(), rusb::Error::Access);
+    }
+
+    #[tokio::test]
+    async fn requests_reach_the_transport() {
+        let (patlite, simulator, _) = open().await;
+        let data: Data = DeviceCommand::Light { color: LED_COLOR_RED, pattern: LED_ON }.to_data().unwrap();
+        assert_eq!(patlite.send_with_attempts(data).await, (Ok(8), 1));
+        assert_eq!(color(&simulator), LED_COLOR_RED);
+
+        assert_eq!(patlite.execute(&DeviceCommand::Light { color: LED_COLOR_GREEN, pattern: LED_ON }).await, Ok(8));
+        assert_eq!(color(&simulator), LED_COLOR_GREEN);
+
+        // Like the device, the simulator never answers
+        assert_eq!(patlite.receive().await, Err(rusb::Error::Timeout));
+        assert_eq!(patlite.profile().await, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn play_sends_every_step() {
+        let (patlite, simulator, _) = open().await;
+        let steps: [Step; 2] = [light(LED_COLOR_RED, Duration::ZERO), light(LED_COLOR_BLUE, Duration::ZERO)];
+        assert_eq!(patlite.play(&steps).await, Ok(()));
+        assert_eq!(color(&simulator), LED_COLOR_BLUE);
+    }
+
+    #[tokio::test]
+    async fn dropping_play_stops_the_sequence() {
+        let (patlite, simulator, _) = open().await;
+        let steps: [Step; 3] = [
+            light(LED_COLOR_RED, Duration::from_millis(50)),
+            light(LED_COLOR_GREEN, Duration::from_millis(300)),
+            light(LED_COLOR_BLUE, Duration::ZERO),
+        ];
+        let played = tokio::time::timeout(Duration::from_millis(150), patlite.play(&steps)).await;
+        assert!(played.is_err());
+        assert_eq!(color(&simulator), LED_COLOR_GREEN);
+
+        // The blue step would have been sent by now had the sequence gone on
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(color(&simulator), LED_COLOR_GREEN);
+    }
+
+    #[tokio::test]
+    async fn worker_stops_once_every_handle_is_dropped() {
+        let (patlite, _, on_drop) = open().await;
+        let clone: Patlite = patlite.clone();
+        drop(patlite);
+        assert_eq!(clone.execute(&DeviceCommand::Off).await, Ok(8));
+        assert!(on_drop.try_recv().is_err());
+
+        drop(clone);
+        assert_eq!(on_drop.recv_timeout(Duration::from_secs(5)), Ok(()));
+    }
+}
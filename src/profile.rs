@@ -0,0 +1,108 @@
+use crate::constants::*;
+use crate::{get_led_color, Data};
+use serde::{Deserialize, Serialize};
+
+// How commands are laid out in the 8 byte frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FrameFormat {
+    // One multicolor head, the frames of `Data`
+    #[default]
+    NeUsb,
+    // A stacked tower with a single color LED per tier
+    LrUsb,
+}
+
+// What a model of device can do, found by its USB product ID
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceProfile {
+    pub name: String,
+    pub product_id: u16,
+    // LED colors the device can show, for towers the color of each tier from the top
+    pub colors: Vec<u8>,
+    pub tiers: u8,
+    pub buzzer: bool,
+    pub frame_format: FrameFormat,
+}
+
+impl DeviceProfile {
+    // NE-SN-USB, and the NE-ST-USB, NE-WN-USB and NE-WT-USB sharing its product ID
+    pub fn ne_usb() -> Self {
+        DeviceProfile {
+            name: "NE-SN-USB".to_string(),
+            product_id: DEVICE_ID,
+            colors: (LED_COLOR_RED..=LED_COLOR_WHITE).collect(),
+            tiers: 1,
+            buzzer: true,
+            frame_format: FrameFormat::NeUsb,
+        }
+    }
+
+    // Check that the device can carry out `data`, naming what it lacks
+    pub fn check(&self, data: &Data) -> Result<(), String> {
+        if data.command_id != COMMAND_ID_CONTROL {
+            return match self.frame_format {
+                FrameFormat::NeUsb => Ok(()),
+                FrameFormat::LrUsb => Err(format!("{} has no settings", self.name)),
+            };
+        }
+        let buzzer_pattern: u8 = data.alarm_control & 0x0F;
+        if !self.buzzer && buzzer_pattern != BUZZER_OFF && buzzer_pattern <= BUZZER_LONDON_BRIDGE {
+            return Err(format!("{} has no buzzer", self.name));
+        }
+        match self.frame_format {
+            FrameFormat::NeUsb => {
+                let color: u8 = data.led_control >> 4;
+                if color != LED_COLOR_OFF && color <= LED_COLOR_WHITE && !self.colors.contains(&color) {
+                    return Err(format!("{} can't show {}", self.name, get_led_color(color)));
+                }
+            }
+            // A nibble per tier, see `LrUsbFrame`
+            FrameFormat::LrUsb => {
+                let tiers: [u8; 3] = [data.led_control, data.reserved_first, data.reserved_second];
+                let nibbles = tiers.iter().flat_map(|byte| [byte >> 4, byte & 0x0F]);
+                for (index, nibble) in nibbles.enumerate() {
+                    if index >= self.tiers as usize && nibble != 0xF {
+                        return Err(format!("{} has {} tiers, not {}", self.name, self.tiers, index + 1));
+                    }
+                    if nibble > 0x2 && nibble != 0xF {
+                        return Err(format!("{:X} isn't a mode for tier {}", nibble, index + 1));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// The models `open_device` looks for. Models not built in can be registered, e.g. variants
+// without a buzzer. USB towers are only ever registered: their product IDs and frames haven't been
+// checked against a device, so none is built in
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileRegistry {
+    profiles: Vec<DeviceProfile>,
+}
+
+impl Default for ProfileRegistry {
+    fn default() -> Self {
+        ProfileRegistry {
+            profiles: vec![DeviceProfile::ne_usb()],
+        }
+    }
+}
+
+impl ProfileRegistry {
+    // Add `profile`, replacing any registered for the same product ID
+    pub fn register(&mut self, profile: DeviceProfile) {
+        self.profiles.retain(|p| p.product_id != profile.product_id);
+        self.profiles.push(profile);
+    }
+
+    pub fn find(&self, product_id: u16) -> Option<&DeviceProfile> {
+        self.profiles.iter().find(|p| p.product_id == product_id)
+    }
+
+    pub fn profiles(&self) -> &[DeviceProfile] {
+        &self.profiles
+    }
+}
@@ -0,0 +1,261 @@
+use crate::frame::{bytes_to_hex, hex_to_bytes};
+use crate::{Data, DeviceCommand, DeviceProfile, DeviceState, Transport};
+use rusb::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+// One line of a recording, a frame written to or read from the device `elapsed_ms` after the
+// recording started. Failed transfers keep the error by its rusb name, e.g. `Timeout`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    pub elapsed_ms: u64,
+    pub direction: Direction,
+    pub bytes: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl RecordedFrame {
+    pub fn to_bytes(&self) -> std::result::Result<Vec<u8>, String> {
+        hex_to_bytes(&self.bytes)
+    }
+
+    pub fn to_data(&self) -> std::result::Result<Data, String> {
+        Data::from_hex(&self.bytes)
+    }
+
+    // The recorded failure as the error the transport returned
+    pub fn to_error(&self) -> Option<rusb::Error> {
+        self.error.as_deref().map(error_from_name)
+    }
+}
+
+// The error with the `Debug` name `name`, as errors are written in recordings and over the network
+pub fn error_from_name(name: &str) -> rusb::Error {
+    match name {
+        "Io" => rusb::Error::Io,
+        "InvalidParam" => rusb::Error::InvalidParam,
+        "Access" => rusb::Error::Access,
+        "NoDevice" => rusb::Error::NoDevice,
+        "NotFound" => rusb::Error::NotFound,
+        "Busy" => rusb::Error::Busy,
+        "Timeout" => rusb::Error::Timeout,
+        "Overflow" => rusb::Error::Overflow,
+        "Pipe" => rusb::Error::Pipe,
+        "Interrupted" => rusb::Error::Interrupted,
+        "NoMem" => rusb::Error::NoMem,
+        "NotSupported" => rusb::Error::NotSupported,
+        _ => rusb::Error::Other,
+    }
+}
+
+// Read a recording written by `Recorder`, one JSON object per line
+pub fn read_recording<R: BufRead>(reader: R) -> io::Result<Vec<RecordedFrame>> {
+    let mut frames: Vec<RecordedFrame> = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line: String = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let frame: RecordedFrame = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", index + 1, e))
+        })?;
+        frames.push(frame);
+    }
+    Ok(frames)
+}
+
+// Sends and receives with `inner`, writing every frame to `out` with the time since the recorder
+// was created
+pub struct Recorder<T: Transport, W: Write> {
+    inner: T,
+    out: W,
+    started: Instant,
+}
+
+impl<T: Transport, W: Write> Recorder<T, W> {
+    pub fn new(inner: T, out: W) -> Self {
+        Recorder {
+            inner,
+            out,
+            started: Instant::now(),
+        }
+    }
+
+    // The frame has already gone to or come from the device by now, so a recording that can't be
+    // written is only warned about instead of failing the command
+    fn record<U>(&mut self, direction: Direction, bytes: &[u8], result: &Result<U>) {
+        let frame = RecordedFrame {
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+            direction,
+            bytes: bytes_to_hex(bytes),
+            error: result.as_ref().err().map(|e| format!("{:?}", e)),
+        };
+        let written: io::Result<()> = serde_json::to_string(&frame)
+            .map_err(io::Error::from)
+            .and_then(|line| writeln!(self.out, "{}", line))
+            .and_then(|_| self.out.flush());
+        if let Err(e) = written {
+            eprintln!("Failed to record a frame: {}", e);
+        }
+    }
+}
+
+impl<T: Transport, W: Write> Transport for Recorder<T, W> {
+    fn send(&mut self, data: Data) -> Result<usize> {
+        let sent = self.inner.send(data);
+        self.record(Direction::Sent, &data.to_array(), &sent);
+        sent
+    }
+
+    // A command that can't be laid out here is still for `inner` to refuse, a daemon may know
+    // better, but there's no frame of it to record
+    fn execute(&mut self, command: &DeviceCommand) -> Result<usize> {
+        let data = command.encode(self.inner.profile().unwrap_or(&DeviceProfile::ne_usb()));
+        let sent = self.inner.execute(command);
+        match data {
+            Ok(data) => self.record(Direction::Sent, &data.to_array(), &sent),
+            Err(e) => eprintln!("Not recording the {} command, it has no frame here: {}", command.name(), e),
+        }
+        sent
+    }
+
+    fn receive(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let received = self.inner.receive(buf);
+        let read: usize = *received.as_ref().unwrap_or(&0);
+        self.record(Direction::Received, &buf[..read], &received);
+        received
+    }
+
+    fn attempts(&self) -> u32 {
+        self.inner.attempts()
+    }
+
+    fn profile(&self) -> Option<&DeviceProfile> {
+        self.inner.profile()
+    }
+
+    fn state(&self) -> Option<DeviceState> {
+        self.inner.state()
+    }
+}
+
+// Stands in for the device by playing a recording back: each frame sent has to be the next one
+// recorded, and receiving returns the recorded response. Failures recorded are returned again.
+// Clones go on through the same recording, so one kept aside can tell what the others left
+#[derive(Clone)]
+pub struct Replayer {
+    frames: Arc<Mutex<VecDeque<RecordedFrame>>>,
+}
+
+impl Replayer {
+    pub fn new(frames: Vec<RecordedFrame>) -> Self {
+        Replayer {
+            frames: Arc::new(Mutex::new(frames.into())),
+        }
+    }
+
+    fn frames(&self) -> MutexGuard<'_, VecDeque<RecordedFrame>> {
+        self.frames.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Frames of the recording not sent or received yet
+    pub fn remaining(&self) -> usize {
+        self.frames().len()
+    }
+
+    fn next(&mut self, direction: Direction, actual: &str) -> Result<RecordedFrame> {
+        let next: Option<RecordedFrame> = self.frames().pop_front();
+        match next {
+            Some(frame) if frame.direction == direction => Ok(frame),
+            Some(frame) => {
+                eprintln!(
+                    "Replay mismatch: recording has {:?} {} where {:?} {} happened",
+                    frame.direction, frame.bytes, direction, actual
+                );
+                Err(rusb::Error::Other)
+            }
+            None => {
+                eprintln!("Replay mismatch: recording ended before {:?} {}", direction, actual);
+                Err(rusb::Error::Other)
+            }
+        }
+    }
+}
+
+impl Transport for Replayer {
+    fn send(&mut self, data: Data) -> Result<usize> {
+        let frame: RecordedFrame = self.next(Direction::Sent, &data.to_hex())?;
+        if frame.to_bytes().ok() != Some(data.to_array().to_vec()) {
+            eprintln!("Replay mismatch: recording has {} where {} was sent", frame.bytes, data.to_hex());
+            return Err(rusb::Error::Other);
+        }
+        match frame.to_error() {
+            Some(e) => Err(e),
+            None => Ok(data.to_array().len()),
+        }
+    }
+
+    fn receive(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let frame: RecordedFrame = self.next(Direction::Received, "a read")?;
+        if let Some(e) = frame.to_error() {
+            return Err(e);
+        }
+        let bytes: Vec<u8> = frame.to_bytes().map_err(|_| rusb::Error::Other)?;
+        let read: usize = bytes.len().min(buf.len());
+        buf[..read].copy_from_slice(&bytes[..read]);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DryRun;
+
+    // A recording on a full disk
+    struct Full;
+
+    impl Write for Full {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("No space left on device"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn frames_are_recorded_with_their_outcome() {
+        let mut recorder = Recorder::new(DryRun::new(io::sink()), vec![]);
+        let data: Data = Data::from_hex("00 00 11 0F 11 00 00 00").unwrap();
+        assert_eq!(recorder.send(data), Ok(8));
+        assert_eq!(recorder.receive(&mut [0; 8]), Ok(0));
+
+        let frames: Vec<RecordedFrame> = read_recording(recorder.out.as_slice()).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].direction, Direction::Sent);
+        assert_eq!(frames[0].bytes, "00 00 11 0F 11 00 00 00");
+        assert_eq!(frames[1].direction, Direction::Received);
+        assert_eq!(frames[1].bytes, "");
+    }
+
+    #[test]
+    fn failing_recording_doesnt_fail_the_command() {
+        let mut recorder = Recorder::new(DryRun::new(io::sink()), Full);
+        assert_eq!(recorder.send(Data::from_hex("00 00 11 0F 11 00 00 00").unwrap()), Ok(8));
+        assert_eq!(recorder.execute(&DeviceCommand::Off), Ok(8));
+        assert_eq!(recorder.receive(&mut [0; 8]), Ok(0));
+    }
+}
@@ -0,0 +1,34 @@
+use crate::network::io_error;
+use crate::{DeviceProfile, PnsTransport};
+use rusb::Result;
+use serialport::{ErrorKind, SerialPort};
+use std::time::Duration;
+
+// Serial signal units are assumed to take the PNS commands over RS-232C, 8 data bits, no parity
+// and 1 stop bit. PATLITE only documents PNS for its LAN units, so this is behind the experimental
+// `serial` feature until it has been tried on one
+pub type SerialTransport = PnsTransport<Box<dyn SerialPort>>;
+
+fn serial_error(e: serialport::Error) -> rusb::Error {
+    match e.kind() {
+        ErrorKind::NoDevice => rusb::Error::NoDevice,
+        ErrorKind::InvalidInput => rusb::Error::InvalidParam,
+        ErrorKind::Io(kind) => io_error(kind.into()),
+        ErrorKind::Unknown => rusb::Error::Other,
+    }
+}
+
+impl PnsTransport<Box<dyn SerialPort>> {
+    // Open the unit on the serial port at `path`, e.g. /dev/ttyUSB0 or COM3, waiting at most
+    // `timeout` for each answer
+    pub fn open_serial(path: &str, baud_rate: u32, timeout: Duration) -> Result<Self> {
+        let port: Box<dyn SerialPort> = serialport::new(path, baud_rate)
+            .timeout(timeout)
+            .open()
+            .map_err(|e| {
+                eprintln!("Failed to open {}: {}", path, e);
+                serial_error(e)
+            })?;
+        Ok(PnsTransport::new(port, DeviceProfile::pns()))
+    }
+}
@@ -0,0 +1,68 @@
+use crate::{Data, DeviceProfile, DeviceState, FrameFormat, TowerState, Transport};
+use rusb::Result;
+
+// Stands in for the device, keeping track of what its light and buzzer would be doing with the
+// same KEEP semantics. Like the device it never answers reads
+#[derive(Debug, Default)]
+pub struct Simulator {
+    state: DeviceState,
+    profile: Option<DeviceProfile>,
+    // The tiers of a tower model, which take their own frames instead of `state`
+    tower: Option<TowerState>,
+}
+
+impl Simulator {
+    pub fn new() -> Self {
+        Simulator::default()
+    }
+
+    // Stand in for a particular model, rejecting what it can't do. Towers start with every tier off
+    pub fn for_profile(profile: DeviceProfile) -> Self {
+        let tower: Option<TowerState> = match profile.frame_format {
+            FrameFormat::NeUsb => None,
+            FrameFormat::LrUsb => Some(TowerState {
+                buzzer: 0x00,
+                ..TowerState::off(&profile)
+            }),
+        };
+        Simulator {
+            state: DeviceState::default(),
+            profile: Some(profile),
+            tower,
+        }
+    }
+
+    pub fn state(&self) -> &DeviceState {
+        &self.state
+    }
+
+    pub fn tower(&self) -> Option<&TowerState> {
+        self.tower.as_ref()
+    }
+}
+
+impl Transport for Simulator {
+    fn send(&mut self, data: Data) -> Result<usize> {
+        if let Some(Err(e)) = self.profile.as_ref().map(|p| p.check(&data)) {
+            eprintln!("Unsupported command: {}", e);
+            return Err(rusb::Error::NotSupported);
+        }
+        match &mut self.tower {
+            Some(tower) => tower.apply(&data),
+            None => self.state.apply(&data),
+        }
+        Ok(data.to_array().len())
+    }
+
+    fn receive(&mut self, _buf: &mut [u8]) -> Result<usize> {
+        Err(rusb::Error::Timeout)
+    }
+
+    fn profile(&self) -> Option<&DeviceProfile> {
+        self.profile.as_ref()
+    }
+
+    fn state(&self) -> Option<DeviceState> {
+        self.tower.is_none().then_some(self.state)
+    }
+}
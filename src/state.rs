@@ -0,0 +1,161 @@
+use crate::constants::*;
+use crate::{get_buzzer_pattern, get_led_color, get_led_pattern, Data};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+// One cycle of LED patterns 1 to 6 as (lit, milliseconds) steps. The manual only gives the
+// patterns as a timing chart, these approximate it for simulating the light
+const PATTERN_CYCLES: [&[(bool, u64)]; 6] = [
+    &[(true, 500), (false, 500)],
+    &[(true, 250), (false, 250)],
+    &[(true, 100), (false, 100), (true, 100), (false, 700)],
+    &[(true, 100), (false, 100), (true, 100), (false, 100), (true, 100), (false, 500)],
+    &[(true, 50), (false, 50)],
+    &[(true, 200), (false, 200), (true, 200), (false, 1000)],
+];
+
+// What the device is showing, tracked from the commands sent to it since the device can't be
+// read back. Values in the protocol's "keep" range leave the tracked value as it was, the same as
+// on the device. The connection display setting is unknown until one is sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceState {
+    pub color: u8,
+    pub pattern: u8,
+    pub buzzer_pattern: u8,
+    pub repetition: u8,
+    pub volume: u8,
+    #[serde(default)]
+    pub connection_display: Option<bool>,
+}
+
+impl DeviceState {
+    // A state only holds values `apply` can set, the protocol's "keep" values never end up in one
+    pub fn validate(&self) -> Result<(), String> {
+        let fields: [(&str, u8, u8); 5] = [
+            ("color", self.color, LED_COLOR_WHITE),
+            ("pattern", self.pattern, LED_PATTERN6),
+            ("buzzer_pattern", self.buzzer_pattern, BUZZER_LONDON_BRIDGE),
+            ("repetition", self.repetition, BUZZER_COUNT_KEEP - 1),
+            ("volume", self.volume, BUZZER_VOLUME_MAX),
+        ];
+        match fields.iter().find(|(_, value, max)| value > max) {
+            Some((name, value, max)) => Err(format!("{} {} is out of range 0-{}", name, value, max)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn apply(&mut self, data: &Data) {
+        if data.command_id == COMMAND_ID_SETTING {
+            match data.alarm_control {
+                SETTING_OFF => self.connection_display = Some(false),
+                SETTING_ON => self.connection_display = Some(true),
+                _ => (),
+            }
+            return;
+        }
+        if data.command_id != COMMAND_ID_CONTROL {
+            return;
+        }
+        let color = data.led_control >> 4;
+        let pattern = data.led_control & 0x0F;
+        let buzzer_pattern = data.alarm_control & 0x0F;
+        let repetition = data.alarm_control >> 4;
+        let volume = data.volume & 0x0F;
+
+        if color <= LED_COLOR_WHITE {
+            self.color = color;
+        }
+        if pattern <= LED_PATTERN6 {
+            self.pattern = pattern;
+        }
+        if buzzer_pattern <= BUZZER_LONDON_BRIDGE {
+            self.buzzer_pattern = buzzer_pattern;
+        }
+        if repetition != BUZZER_COUNT_KEEP {
+            self.repetition = repetition;
+        }
+        if volume <= BUZZER_VOLUME_MAX {
+            self.volume = volume;
+        }
+    }
+
+    // The state after sending `data`
+    pub fn merged(mut self, data: &Data) -> Self {
+        self.apply(data);
+        self
+    }
+
+    // Whether the light is lit `elapsed` into its pattern
+    pub fn is_lit(&self, elapsed: Duration) -> bool {
+        if self.color == LED_COLOR_OFF {
+            return false;
+        }
+        let cycle = match self.pattern {
+            LED_OFF => return false,
+            LED_ON => return true,
+            // Patterns past the last one mean "keep" in a frame and aren't a state of their own
+            pattern => match PATTERN_CYCLES.get(pattern.wrapping_sub(LED_PATTERN1) as usize) {
+                Some(cycle) => *cycle,
+                None => return false,
+            },
+        };
+        let total: u64 = cycle.iter().map(|(_, ms)| ms).sum();
+        let mut t: u64 = elapsed.as_millis() as u64 % total;
+        for (lit, ms) in cycle {
+            if t < *ms {
+                return *lit;
+            }
+            t -= ms;
+        }
+        false
+    }
+
+    // Whether the buzzer was last told to sound at an audible volume
+    pub fn is_buzzing(&self) -> bool {
+        self.buzzer_pattern != BUZZER_OFF && self.volume != BUZZER_VOLUME_OFF
+    }
+}
+
+impl fmt::Display for DeviceState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let repetition = match self.repetition {
+            BUZZER_COUNT_CONTINUE => "continuous".to_string(),
+            n => format!("x{}", n),
+        };
+        write!(
+            f,
+            "Color: {}  Pattern: {}  Buzzer: {} {}  Volume: {}",
+            get_led_color(self.color),
+            get_led_pattern(self.pattern),
+            get_buzzer_pattern(self.buzzer_pattern),
+            repetition,
+            self.volume
+        )?;
+        match self.connection_display {
+            Some(on) => write!(f, "  Connection display: {}", if on { "on" } else { "off" }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_out_of_range_are_not_lit() {
+        for pattern in [LED_PATTERN6 + 1, LED_PATTERN_KEEP, 0xFF] {
+            let state = DeviceState { color: LED_COLOR_RED, pattern, ..DeviceState::default() };
+            assert!(!state.is_lit(Duration::ZERO));
+        }
+    }
+
+    #[test]
+    fn validate_rejects_values_apply_never_sets() {
+        let state: DeviceState = DeviceState::default().merged(&Data::from_array([0, 0, 0xE7, 0x0A, 0x77, 0, 0, 0]));
+        assert_eq!(state.validate(), Ok(()));
+        let state = DeviceState { volume: BUZZER_VOLUME_KEEP, ..state };
+        assert_eq!(state.validate(), Err("volume 15 is out of range 0-10".to_string()));
+    }
+}
@@ -0,0 +1,354 @@
+use crate::constants::*;
+use crate::names::{canonical_name, lookup, COLOR_NAMES};
+use crate::{get_buzzer_pattern, get_led_color, Data, DeviceProfile, FrameFormat};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TierMode {
+    Off,
+    On,
+    Blink,
+    // Leave the tier as it is
+    Keep,
+}
+
+impl FromStr for TierMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(TierMode::Off),
+            "on" => Ok(TierMode::On),
+            "blink" | "flash" => Ok(TierMode::Blink),
+            "keep" => Ok(TierMode::Keep),
+            _ => Err(format!("Unknown tier mode: {} (expected on, blink, off or keep)", s)),
+        }
+    }
+}
+
+// A tier by its position from the top, starting at 1, or by its color
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "TierName")]
+pub enum TierSelector {
+    Index(u8),
+    Color(u8),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TierName {
+    Index(u8),
+    Name(String),
+}
+
+impl TryFrom<TierName> for TierSelector {
+    type Error = String;
+
+    fn try_from(name: TierName) -> Result<Self, Self::Error> {
+        match name {
+            TierName::Index(index) => Ok(TierSelector::Index(index)),
+            TierName::Name(name) => name.parse(),
+        }
+    }
+}
+
+impl FromStr for TierSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(index) = s.parse::<u8>() {
+            return Ok(TierSelector::Index(index));
+        }
+        match lookup(COLOR_NAMES, s) {
+            Some(color) if (LED_COLOR_RED..=LED_COLOR_WHITE).contains(&color) => Ok(TierSelector::Color(color)),
+            _ => Err(format!("Unknown tier: {} (expected a number from the top or a color)", s)),
+        }
+    }
+}
+
+// Numbers stay numbers and colors are written by name, so commands read back the same
+impl Serialize for TierSelector {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            TierSelector::Index(index) => serializer.serialize_u8(*index),
+            TierSelector::Color(color) => serializer.serialize_str(canonical_name(COLOR_NAMES, *color).unwrap_or("?")),
+        }
+    }
+}
+
+impl fmt::Display for TierSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TierSelector::Index(index) => write!(f, "tier {}", index),
+            TierSelector::Color(color) => write!(f, "the {} tier", get_led_color(*color)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TierSetting {
+    pub tier: TierSelector,
+    pub mode: TierMode,
+}
+
+impl TierSetting {
+    // Parse `[tier] TIER MODE [[tier] TIER MODE]...`, e.g. `tier 1 on tier 3 blink` or `red on green off`
+    pub fn parse_list(words: &[&str]) -> Result<Vec<TierSetting>, String> {
+        let mut words = words.iter().filter(|w| !w.eq_ignore_ascii_case("tier"));
+        let mut settings: Vec<TierSetting> = vec![];
+        while let Some(tier) = words.next() {
+            let mode = words.next().ok_or_else(|| format!("Missing the mode for tier {}", tier))?;
+            settings.push(TierSetting {
+                tier: tier.parse()?,
+                mode: mode.parse()?,
+            });
+        }
+        if settings.is_empty() {
+            return Err("Give at least one tier and mode, e.g. `tier 1 on`".to_string());
+        }
+        Ok(settings)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Tier {
+    pub color: u8,
+    pub mode: TierMode,
+}
+
+// The light of each tier of a tower, top first, with the buzzer left as it is unless
+// `buzzer` is set. Single head models have one tier that can show any of their colors
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TowerState {
+    pub tiers: Vec<Tier>,
+    // Alarm control byte, repetitions in the upper 4 bits and the pattern in the lower
+    pub buzzer: u8,
+}
+
+impl TowerState {
+    // Every tier of `profile` left as it is
+    pub fn new(profile: &DeviceProfile) -> Self {
+        let tiers: Vec<Tier> = match profile.frame_format {
+            FrameFormat::NeUsb => vec![Tier { color: LED_COLOR_KEEP, mode: TierMode::Keep }],
+            FrameFormat::LrUsb => profile
+                .colors
+                .iter()
+                .take(profile.tiers as usize)
+                .map(|color| Tier { color: *color, mode: TierMode::Keep })
+                .collect(),
+        };
+        TowerState {
+            tiers,
+            buzzer: BUZZER_COUNT_KEEP << 4 | BUZZER_KEEP,
+        }
+    }
+
+    // Every tier of `profile` turned off
+    pub fn off(profile: &DeviceProfile) -> Self {
+        let mut tower = TowerState::new(profile);
+        for tier in &mut tower.tiers {
+            tier.mode = TierMode::Off;
+        }
+        tower
+    }
+
+    pub fn set(&mut self, profile: &DeviceProfile, selector: TierSelector, mode: TierMode) -> Result<(), String> {
+        let single_head: bool = profile.frame_format == FrameFormat::NeUsb;
+        let index: usize = match selector {
+            TierSelector::Index(index) if index >= 1 && (index as usize) <= self.tiers.len() => index as usize - 1,
+            TierSelector::Color(color) if single_head && profile.colors.contains(&color) => {
+                self.tiers[0].color = color;
+                0
+            }
+            TierSelector::Color(color) => self
+                .tiers
+                .iter()
+                .position(|tier| tier.color == color)
+                .ok_or_else(|| format!("{} has no {} tier", profile.name, get_led_color(color)))?,
+            TierSelector::Index(index) => {
+                let plural: &str = if self.tiers.len() == 1 { "" } else { "s" };
+                return Err(format!("{} has {} tier{}, not {}", profile.name, self.tiers.len(), plural, index));
+            }
+        };
+        self.tiers[index].mode = mode;
+        Ok(())
+    }
+
+    // Merge an LR-USB frame, leaving the tiers and buzzer it keeps as they are
+    pub fn apply(&mut self, data: &Data) {
+        let bytes: [u8; 3] = [data.led_control, data.reserved_first, data.reserved_second];
+        let nibbles = bytes.iter().flat_map(|byte| [byte >> 4, byte & 0x0F]);
+        for (tier, nibble) in self.tiers.iter_mut().zip(nibbles) {
+            tier.mode = match nibble {
+                0x0 => TierMode::Off,
+                0x1 => TierMode::On,
+                0x2 => TierMode::Blink,
+                _ => continue,
+            };
+        }
+        if data.alarm_control & 0x0F != BUZZER_KEEP {
+            self.buzzer = data.alarm_control;
+        }
+    }
+}
+
+impl fmt::Display for TowerState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, tier) in self.tiers.iter().enumerate() {
+            let mode: &str = match tier.mode {
+                TierMode::Off => "off",
+                TierMode::On => "on",
+                TierMode::Blink => "blink",
+                TierMode::Keep => "keep",
+            };
+            write!(f, "Tier {} {}: {}  ", index + 1, get_led_color(tier.color), mode)?;
+        }
+        write!(f, "Buzzer: {}", get_buzzer_pattern(self.buzzer & 0x0F))
+    }
+}
+
+// Lays a tower state out in a model's frame
+pub(crate) trait TowerEncoder {
+    fn encode(tower: &TowerState) -> Result<Data, String>;
+}
+
+// The single head of the NE-SN-USB, lit in the color of its tier. Blinking uses LED pattern 1
+impl TowerEncoder for Data {
+    fn encode(tower: &TowerState) -> Result<Data, String> {
+        let [tier] = tower.tiers.as_slice() else {
+            return Err("A single head device has exactly one tier".to_string());
+        };
+        let led_control: u8 = match (tier.mode, tier.color) {
+            (TierMode::Keep, _) => LED_COLOR_KEEP << 4 | LED_PATTERN_KEEP,
+            (TierMode::Off, _) => LED_COLOR_OFF << 4 | LED_OFF,
+            (TierMode::On, color) => color << 4 | LED_ON,
+            (TierMode::Blink, color) => color << 4 | LED_PATTERN1,
+        };
+        let mut data = Data::default();
+        data.set_alarm_control(tower.buzzer);
+        data.set_led_control(led_control);
+        Ok(data)
+    }
+}
+
+// LR-USB towers: byte 3 controls the buzzer like the NE-USB, byte 4 is left at keep, and bytes 5
+// to 7 hold a nibble per tier from the top, 0 off, 1 on, 2 blinking and 0xF keep. This layout is
+// the crate's own and unverified, PATLITE doesn't publish one, so it's only used for the PNS units,
+// which translate it, and for USB models a config registers with `frame_format = "lr-usb"`
+pub(crate) struct LrUsbFrame;
+
+impl TowerEncoder for LrUsbFrame {
+    fn encode(tower: &TowerState) -> Result<Data, String> {
+        if tower.tiers.len() > 6 {
+            return Err(format!("An LR-USB frame has room for 6 tiers, not {}", tower.tiers.len()));
+        }
+        let mut nibbles: [u8; 6] = [0xF; 6];
+        for (nibble, tier) in nibbles.iter_mut().zip(&tower.tiers) {
+            *nibble = match tier.mode {
+                TierMode::Off => 0x0,
+                TierMode::On => 0x1,
+                TierMode::Blink => 0x2,
+                TierMode::Keep => 0xF,
+            };
+        }
+        Ok(Data::from_array([
+            COMMAND_VERSION,
+            COMMAND_ID_CONTROL,
+            tower.buzzer,
+            0xFF,
+            nibbles[0] << 4 | nibbles[1],
+            nibbles[2] << 4 | nibbles[3],
+            nibbles[4] << 4 | nibbles[5],
+            BLANK,
+        ]))
+    }
+}
+
+impl DeviceProfile {
+    // The frame that shows `tower` on this model
+    pub fn encode(&self, tower: &TowerState) -> Result<Data, String> {
+        match self.frame_format {
+            FrameFormat::NeUsb => Data::encode(tower),
+            FrameFormat::LrUsb => LrUsbFrame::encode(tower),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tower() -> DeviceProfile {
+        DeviceProfile {
+            name: "tower".to_string(),
+            colors: vec![LED_COLOR_RED, LED_COLOR_YELLOW, LED_COLOR_GREEN],
+            tiers: 3,
+            frame_format: FrameFormat::LrUsb,
+            ..DeviceProfile::ne_usb()
+        }
+    }
+
+    fn encode(profile: &DeviceProfile, words: &[&str]) -> Result<[u8; 8], String> {
+        let mut state: TowerState = TowerState::new(profile);
+        for setting in TierSetting::parse_list(words)? {
+            state.set(profile, setting.tier, setting.mode)?;
+        }
+        profile.encode(&state).map(|data| data.to_array())
+    }
+
+    #[test]
+    fn parse_list_skips_the_tier_keyword() {
+        let settings: Vec<TierSetting> = TierSetting::parse_list(&["tier", "1", "on", "tier", "3", "blink"]).unwrap();
+        assert_eq!(
+            settings,
+            vec![
+                TierSetting { tier: TierSelector::Index(1), mode: TierMode::On },
+                TierSetting { tier: TierSelector::Index(3), mode: TierMode::Blink },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_list_takes_colors() {
+        let settings: Vec<TierSetting> = TierSetting::parse_list(&["red", "on"]).unwrap();
+        assert_eq!(settings, vec![TierSetting { tier: TierSelector::Color(LED_COLOR_RED), mode: TierMode::On }]);
+    }
+
+    #[test]
+    fn parse_list_needs_a_mode_per_tier() {
+        assert_eq!(
+            TierSetting::parse_list(&["tier", "1", "on", "tier", "2"]),
+            Err("Missing the mode for tier 2".to_string())
+        );
+        assert!(TierSetting::parse_list(&["tier"]).is_err());
+    }
+
+    #[test]
+    fn single_head_lights_the_color_of_its_tier() {
+        let ne_usb: DeviceProfile = DeviceProfile::ne_usb();
+        assert_eq!(encode(&ne_usb, &["red", "blink"]), Ok([0x00, 0x00, 0xFF, 0x0F, 0x12, 0x00, 0x00, 0x00]));
+        assert_eq!(encode(&ne_usb, &["1", "off"]), Ok([0x00, 0x00, 0xFF, 0x0F, 0x00, 0x00, 0x00, 0x00]));
+        assert_eq!(encode(&ne_usb, &["2", "on"]), Err("NE-SN-USB has 1 tier, not 2".to_string()));
+    }
+
+    #[test]
+    fn lr_usb_frame_has_a_nibble_per_tier() {
+        let tower: DeviceProfile = tower();
+        assert_eq!(
+            encode(&tower, &["tier", "1", "on", "tier", "3", "blink"]),
+            Ok([0x00, 0x00, 0xFF, 0xFF, 0x1F, 0x2F, 0xFF, 0x00])
+        );
+        assert_eq!(encode(&tower, &["yellow", "off"]), Ok([0x00, 0x00, 0xFF, 0xFF, 0xF0, 0xFF, 0xFF, 0x00]));
+        assert_eq!(encode(&tower, &["blue", "on"]), Err("tower has no Blue tier".to_string()));
+    }
+
+    #[test]
+    fn lr_usb_frame_has_room_for_six_tiers() {
+        let tiers: Vec<Tier> = vec![Tier { color: LED_COLOR_RED, mode: TierMode::On }; 7];
+        let state = TowerState { tiers, buzzer: BUZZER_OFF };
+        assert_eq!(LrUsbFrame::encode(&state), Err("An LR-USB frame has room for 6 tiers, not 7".to_string()));
+    }
+}
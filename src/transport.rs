@@ -0,0 +1,215 @@
+use crate::constants::{ENDPOINT_ADDRESS_GET, SEND_TIMEOUT};
+use crate::frame::bytes_to_hex;
+use crate::{send_command, Data, DeviceCommand, DeviceProfile, DeviceState};
+use rusb::{DeviceHandle, Result, UsbContext};
+use std::io::Write;
+use std::time::Duration;
+
+// Something frames can be sent to: an open device, or a stand-in that only shows them
+pub trait Transport {
+    fn send(&mut self, data: Data) -> Result<usize>;
+
+    // Carry out `command`, sending its frame laid out for the model of the device. Transports
+    // that take commands as they are, such as a daemon checking them, send them instead
+    fn execute(&mut self, command: &DeviceCommand) -> Result<usize> {
+        let data: Data = command.frame(self.profile())?;
+        self.send(data)
+    }
+
+    // Wait for bytes from the device, returning how many were read into `buf`
+    fn receive(&mut self, _buf: &mut [u8]) -> Result<usize> {
+        Err(rusb::Error::NotSupported)
+    }
+
+    // How many attempts the last send or receive took, more than 1 when it was retried
+    fn attempts(&self) -> u32 {
+        1
+    }
+
+    // The model of the device, used to lay commands out in its frame format. Stand-ins
+    // without one get the frames of the NE-SN-USB
+    fn profile(&self) -> Option<&DeviceProfile> {
+        None
+    }
+
+    // What the device was last set to, for transports that keep track of it. None of the
+    // devices can be read back, so it's only ever what the frames sent through it said
+    fn state(&self) -> Option<DeviceState> {
+        None
+    }
+}
+
+impl<T: UsbContext> Transport for DeviceHandle<T> {
+    fn send(&mut self, data: Data) -> Result<usize> {
+        send_command(self, data)
+    }
+
+    fn receive(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.read_interrupt(ENDPOINT_ADDRESS_GET, buf, Duration::from_millis(SEND_TIMEOUT))
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn send(&mut self, data: Data) -> Result<usize> {
+        (**self).send(data)
+    }
+
+    fn execute(&mut self, command: &DeviceCommand) -> Result<usize> {
+        (**self).execute(command)
+    }
+
+    fn receive(&mut self, buf: &mut [u8]) -> Result<usize> {
+        (**self).receive(buf)
+    }
+
+    fn attempts(&self) -> u32 {
+        (**self).attempts()
+    }
+
+    fn profile(&self) -> Option<&DeviceProfile> {
+        (**self).profile()
+    }
+
+    fn state(&self) -> Option<DeviceState> {
+        (**self).state()
+    }
+}
+
+// Writes each frame to `out` instead of sending it, for trying commands without the device
+pub struct DryRun<W: Write> {
+    out: W,
+}
+
+impl<W: Write> DryRun<W> {
+    pub fn new(out: W) -> Self {
+        DryRun { out }
+    }
+}
+
+impl<W: Write> Transport for DryRun<W> {
+    fn send(&mut self, data: Data) -> Result<usize> {
+        writeln!(self.out, "Would send: {}  ({})", data.to_hex(), data.describe())
+            .map_err(|_| rusb::Error::Io)?;
+        Ok(data.to_array().len())
+    }
+
+    fn receive(&mut self, _buf: &mut [u8]) -> Result<usize> {
+        writeln!(self.out, "Would read from the device").map_err(|_| rusb::Error::Io)?;
+        Ok(0)
+    }
+}
+
+fn frame(data: Data) -> String {
+    format!("{}  ({})", data.to_hex(), data.describe())
+}
+
+// Sends frames with `inner` and writes each one to `out` along with how sending it went
+pub struct Trace<T: Transport, W: Write> {
+    inner: T,
+    out: W,
+}
+
+impl<T: Transport, W: Write> Trace<T, W> {
+    pub fn new(inner: T, out: W) -> Self {
+        Trace { inner, out }
+    }
+
+    // `what` was sent, a frame or a command `inner` took as it is
+    fn log(&mut self, what: String, sent: &Result<usize>) {
+        let mut outcome = match sent {
+            Ok(written) => format!("{} bytes", written),
+            Err(e) => format!("failed: {}", e),
+        };
+        if self.inner.attempts() > 1 {
+            outcome.push_str(&format!(" after {} attempts", self.inner.attempts()));
+        }
+        // A trace that can't be written shouldn't stop the frames themselves
+        let _ = writeln!(self.out, "Sent: {} {}", what, outcome);
+    }
+}
+
+impl<T: Transport, W: Write> Transport for Trace<T, W> {
+    fn send(&mut self, data: Data) -> Result<usize> {
+        let sent = self.inner.send(data);
+        self.log(frame(data), &sent);
+        sent
+    }
+
+    // The frame is the one `inner` sends, or has the daemon behind it send. A command that can't
+    // be laid out here is still for `inner` to refuse, a daemon may know better, and is shown as is
+    fn execute(&mut self, command: &DeviceCommand) -> Result<usize> {
+        let what: String = match command.encode(self.inner.profile().unwrap_or(&DeviceProfile::ne_usb())) {
+            Ok(data) => frame(data),
+            Err(_) => serde_json::to_string(command).unwrap_or_else(|_| command.name().to_string()),
+        };
+        let sent = self.inner.execute(command);
+        self.log(what, &sent);
+        sent
+    }
+
+    fn receive(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let received = self.inner.receive(buf);
+        let _ = match &received {
+            Ok(read) => writeln!(self.out, "Received: {}", bytes_to_hex(&buf[..*read])),
+            Err(e) => writeln!(self.out, "Receive failed: {}", e),
+        };
+        received
+    }
+
+    fn attempts(&self) -> u32 {
+        self.inner.attempts()
+    }
+
+    fn profile(&self) -> Option<&DeviceProfile> {
+        self.inner.profile()
+    }
+
+    fn state(&self) -> Option<DeviceState> {
+        self.inner.state()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Takes every command as it is, the way the daemon behind --remote does
+    struct Accepting {
+        profile: DeviceProfile,
+        executed: Vec<DeviceCommand>,
+    }
+
+    impl Transport for Accepting {
+        fn send(&mut self, data: Data) -> Result<usize> {
+            Ok(data.to_array().len())
+        }
+
+        fn execute(&mut self, command: &DeviceCommand) -> Result<usize> {
+            self.executed.push(command.clone());
+            Ok(8)
+        }
+
+        fn profile(&self) -> Option<&DeviceProfile> {
+            Some(&self.profile)
+        }
+    }
+
+    #[test]
+    fn trace_shows_the_frame_of_a_command() {
+        let mut out: Vec<u8> = vec![];
+        let mut trace = Trace::new(Accepting { profile: DeviceProfile::ne_usb(), executed: vec![] }, &mut out);
+        trace.execute(&DeviceCommand::Light { color: 1, pattern: 1 }).unwrap();
+        drop(trace);
+        assert!(String::from_utf8(out).unwrap().starts_with("Sent: 00 00 FF 0F 11 00 00 00  (control: color Red"));
+    }
+
+    #[test]
+    fn trace_leaves_commands_it_cant_lay_out_to_inner() {
+        let mut out: Vec<u8> = vec![];
+        let mut trace = Trace::new(Accepting { profile: DeviceProfile::pns(), executed: vec![] }, &mut out);
+        assert_eq!(trace.execute(&DeviceCommand::Volume { level: 3 }), Ok(8));
+        assert_eq!(trace.inner.executed, [DeviceCommand::Volume { level: 3 }]);
+        drop(trace);
+        assert_eq!(String::from_utf8(out).unwrap(), "Sent: {\"command\":\"volume\",\"level\":3} 8 bytes\n");
+    }
+}
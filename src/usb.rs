@@ -0,0 +1,273 @@
+use crate::constants::{ENDPOINT_ADDRESS, ENDPOINT_ADDRESS_GET, SEND_TIMEOUT};
+use crate::{setup_registered_device, Data, DeviceProfile, Endpoint, ProfileRegistry, Transport};
+use rusb::{Context, DeviceHandle, Result, UsbContext};
+use std::thread;
+use std::time::Duration;
+
+// Errors worth another attempt by default: the device was slow, stalled or briefly claimed
+pub const RETRYABLE_ERRORS: [rusb::Error; 3] = [rusb::Error::Timeout, rusb::Error::Pipe, rusb::Error::Busy];
+
+// How often to try a transfer and how long to wait in between, doubling the wait each time
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub backoff: Duration,
+    pub retry_on: Vec<rusb::Error>,
+}
+
+impl Default for RetryPolicy {
+    // A single attempt, the same as `send_command`
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 1,
+            backoff: Duration::from_millis(100),
+            retry_on: RETRYABLE_ERRORS.to_vec(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn is_retryable(&self, error: &rusb::Error) -> bool {
+        self.retry_on.contains(error)
+    }
+
+    // Wait before attempt number `attempt + 1`
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.backoff.saturating_mul(1 << attempt.saturating_sub(1).min(16))
+    }
+
+    // Attempt `transfer` until it succeeds, fails with an error that isn't retryable or runs out
+    // of attempts, returning its result and how many attempts it took
+    pub(crate) fn run<R>(&self, transfer: &mut impl Transfer<R>) -> (Result<R>, u32) {
+        let mut attempt: u32 = 1;
+        loop {
+            match transfer.attempt() {
+                Err(e) if attempt < self.attempts && self.is_retryable(&e) => {
+                    if e == rusb::Error::Pipe {
+                        transfer.reclaim();
+                    }
+                    transfer.wait(self.delay(attempt));
+                    attempt += 1;
+                }
+                result => return (result, attempt),
+            }
+        }
+    }
+}
+
+// A transfer `RetryPolicy::run` can attempt again
+pub(crate) trait Transfer<R> {
+    fn attempt(&mut self) -> Result<R>;
+
+    // Make the endpoint usable again after it stalled
+    fn reclaim(&mut self);
+
+    fn wait(&mut self, delay: Duration);
+}
+
+// A read or write on `address` of a claimed interface
+struct UsbTransfer<'a, T: UsbContext, F> {
+    handle: &'a mut DeviceHandle<T>,
+    iface: u8,
+    address: u8,
+    timeout: Duration,
+    transfer: F,
+}
+
+impl<T: UsbContext, R, F: FnMut(&mut DeviceHandle<T>, Duration) -> Result<R>> Transfer<R> for UsbTransfer<'_, T, F> {
+    fn attempt(&mut self) -> Result<R> {
+        (self.transfer)(self.handle, self.timeout)
+    }
+
+    fn reclaim(&mut self) {
+        let _ = self.handle.clear_halt(self.address);
+        let _ = self.handle.release_interface(self.iface);
+        if let Err(e) = self.handle.claim_interface(self.iface) {
+            eprintln!("Failed to claim the interface again: {:?}", e);
+        }
+    }
+
+    fn wait(&mut self, delay: Duration) {
+        thread::sleep(delay);
+    }
+}
+
+// A claimed device with its own timeout and retry policy. A `Pipe` error, the endpoint stalling,
+// clears the halt and claims the interface again before the next attempt. Frames the device's
+// model can't carry out are rejected with `NotSupported` before anything is sent
+pub struct UsbTransport<T: UsbContext> {
+    handle: DeviceHandle<T>,
+    endpoint: Endpoint,
+    profile: DeviceProfile,
+    timeout: Duration,
+    retry: RetryPolicy,
+    attempts: u32,
+}
+
+impl UsbTransport<Context> {
+    // Open the device with `serial`, or the first one found, with the default timeout and no retries
+    pub fn open(serial: Option<&str>) -> Result<Self> {
+        UsbTransport::open_registered(&ProfileRegistry::default(), serial)
+    }
+
+    // Open a device of any model in `registry`
+    pub fn open_registered(registry: &ProfileRegistry, serial: Option<&str>) -> Result<Self> {
+        let (handle, endpoint, profile) = setup_registered_device(registry, serial)?;
+        Ok(UsbTransport::new(handle, endpoint, profile))
+    }
+}
+
+impl<T: UsbContext> UsbTransport<T> {
+    pub fn new(handle: DeviceHandle<T>, endpoint: Endpoint, profile: DeviceProfile) -> Self {
+        UsbTransport {
+            handle,
+            endpoint,
+            profile,
+            timeout: Duration::from_millis(SEND_TIMEOUT),
+            retry: RetryPolicy::default(),
+            attempts: 0,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    pub fn profile(&self) -> &DeviceProfile {
+        &self.profile
+    }
+
+    pub fn handle(&mut self) -> &mut DeviceHandle<T> {
+        &mut self.handle
+    }
+
+    // Run `transfer` on `address` with the retry policy
+    fn with_retries<R>(
+        &mut self,
+        address: u8,
+        transfer: impl FnMut(&mut DeviceHandle<T>, Duration) -> Result<R>,
+    ) -> Result<R> {
+        let mut usb = UsbTransfer {
+            handle: &mut self.handle,
+            iface: self.endpoint.iface,
+            address,
+            timeout: self.timeout,
+            transfer,
+        };
+        let (result, attempts) = self.retry.run(&mut usb);
+        self.attempts = attempts;
+        result
+    }
+}
+
+impl<T: UsbContext> Transport for UsbTransport<T> {
+    fn send(&mut self, data: Data) -> Result<usize> {
+        if let Err(e) = self.profile.check(&data) {
+            eprintln!("Unsupported command: {}", e);
+            return Err(rusb::Error::NotSupported);
+        }
+        let frame: [u8; 8] = data.to_array();
+        self.with_retries(ENDPOINT_ADDRESS, |handle, timeout| {
+            handle.write_interrupt(ENDPOINT_ADDRESS, &frame, timeout)
+        })
+    }
+
+    fn receive(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.with_retries(ENDPOINT_ADDRESS_GET, |handle, timeout| {
+            handle.read_interrupt(ENDPOINT_ADDRESS_GET, buf, timeout)
+        })
+    }
+
+    fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    fn profile(&self) -> Option<&DeviceProfile> {
+        Some(&self.profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    // Fails with `failures` in turn, then succeeds, noting what the policy asked of it
+    #[derive(Default)]
+    struct Flaky {
+        failures: VecDeque<rusb::Error>,
+        attempts: u32,
+        reclaims: u32,
+        waits: Vec<Duration>,
+    }
+
+    impl Flaky {
+        fn new(failures: &[rusb::Error]) -> Self {
+            Flaky { failures: failures.iter().copied().collect(), ..Flaky::default() }
+        }
+    }
+
+    impl Transfer<usize> for Flaky {
+        fn attempt(&mut self) -> Result<usize> {
+            self.attempts += 1;
+            self.failures.pop_front().map_or(Ok(8), Err)
+        }
+
+        fn reclaim(&mut self) {
+            self.reclaims += 1;
+        }
+
+        fn wait(&mut self, delay: Duration) {
+            self.waits.push(delay);
+        }
+    }
+
+    fn policy(attempts: u32) -> RetryPolicy {
+        RetryPolicy { attempts, ..RetryPolicy::default() }
+    }
+
+    #[test]
+    fn default_policy_attempts_once() {
+        let mut flaky = Flaky::new(&[rusb::Error::Timeout]);
+        assert_eq!(RetryPolicy::default().run(&mut flaky), (Err(rusb::Error::Timeout), 1));
+        assert!(flaky.waits.is_empty());
+    }
+
+    #[test]
+    fn timeouts_are_retried_with_doubling_backoff() {
+        let mut flaky = Flaky::new(&[rusb::Error::Timeout; 3]);
+        assert_eq!(policy(5).run(&mut flaky), (Ok(8), 4));
+        assert_eq!(flaky.waits, [Duration::from_millis(100), Duration::from_millis(200), Duration::from_millis(400)]);
+        assert_eq!(flaky.reclaims, 0);
+    }
+
+    #[test]
+    fn pipe_errors_reclaim_the_interface_before_retrying() {
+        let mut flaky = Flaky::new(&[rusb::Error::Pipe, rusb::Error::Timeout]);
+        assert_eq!(policy(3).run(&mut flaky), (Ok(8), 3));
+        assert_eq!(flaky.reclaims, 1);
+    }
+
+    #[test]
+    fn last_error_is_returned_when_attempts_run_out() {
+        let mut flaky = Flaky::new(&[rusb::Error::Busy; 3]);
+        assert_eq!(policy(3).run(&mut flaky), (Err(rusb::Error::Busy), 3));
+        assert_eq!(flaky.waits.len(), 2);
+    }
+
+    #[test]
+    fn errors_that_arent_retryable_end_at_once() {
+        let mut flaky = Flaky::new(&[rusb::Error::InvalidParam]);
+        assert_eq!(policy(5).run(&mut flaky), (Err(rusb::Error::InvalidParam), 1));
+        assert!(flaky.waits.is_empty());
+
+        let mut flaky = Flaky::new(&[rusb::Error::Pipe]);
+        let timeouts_only = RetryPolicy { retry_on: vec![rusb::Error::Timeout], ..policy(5) };
+        assert_eq!(timeouts_only.run(&mut flaky), (Err(rusb::Error::Pipe), 1));
+        assert_eq!(flaky.reclaims, 0);
+    }
+}
@@ -0,0 +1,103 @@
+use patlite_rs::{pns_frame, pns_packet, Data, DeviceCommand, DeviceProfile, Emulator, PnsTransport, Simulator, TierMode, TierSetting};
+use std::io::{self, Cursor, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+// A client whose packets are all sent up front, collecting the answers
+struct Pipe {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl Pipe {
+    fn new(packets: &[Vec<u8>]) -> Self {
+        Pipe { input: Cursor::new(packets.concat()), output: vec![] }
+    }
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn packet(words: &[&str]) -> Vec<u8> {
+    let command = DeviceCommand::Tiers { tiers: TierSetting::parse_list(words).unwrap() };
+    pns_packet(&command.encode(&DeviceProfile::pns()).unwrap())
+}
+
+fn modes(emulator: &Emulator) -> Vec<TierMode> {
+    emulator.simulator().tower().expect("A PNS unit is a tower").tiers.iter().map(|tier| tier.mode).collect()
+}
+
+#[test]
+fn pns_frame_undoes_pns_packet() {
+    let data: Data = DeviceCommand::Tiers { tiers: TierSetting::parse_list(&["1", "on", "3", "blink", "5", "off"]).unwrap() }
+        .encode(&DeviceProfile::pns())
+        .unwrap();
+    let packet: Vec<u8> = pns_packet(&data);
+    let units: [u8; 5] = packet[6..11].try_into().unwrap();
+    assert_eq!(pns_frame(&units, packet[11]).to_array(), data.to_array());
+}
+
+#[test]
+fn packets_change_the_simulated_tower() {
+    let emulator = Emulator::new(Simulator::for_profile(DeviceProfile::pns()));
+    let mut pipe = Pipe::new(&[packet(&["tier", "1", "on", "tier", "3", "blink"]), packet(&["red", "off", "white", "on"])]);
+    let mut changes: usize = 0;
+    emulator.serve(&mut pipe, |_| changes += 1).unwrap();
+
+    assert_eq!(pipe.output, [0x06, 0x06]);
+    assert_eq!(changes, 2);
+    assert_eq!(modes(&emulator), [TierMode::Off, TierMode::Off, TierMode::Blink, TierMode::Off, TierMode::On]);
+
+    let mut pipe = Pipe::new(&[b"ABC\x00\x00\x00".to_vec()]);
+    emulator.serve(&mut pipe, |_| {}).unwrap();
+    assert_eq!(pipe.output, [0x06]);
+    assert_eq!(modes(&emulator), [TierMode::Off; 5]);
+}
+
+#[test]
+fn single_head_takes_frames_and_refuses_packets() {
+    let emulator = Emulator::new(Simulator::for_profile(DeviceProfile::ne_usb()));
+    let frame: Data = DeviceCommand::Light { color: 1, pattern: 1 }.to_data().unwrap();
+    let mut pipe = Pipe::new(&[frame.to_array().to_vec(), packet(&["1", "on"])]);
+    emulator.serve(&mut pipe, |_| {}).unwrap();
+
+    assert_eq!(pipe.output, [0x06, 0x15]);
+    assert_eq!(emulator.simulator().state().color, 1);
+}
+
+#[test]
+fn clients_share_the_unit_over_loopback() {
+    let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address: String = listener.local_addr().unwrap().to_string();
+    let emulator = Emulator::new(Simulator::for_profile(DeviceProfile::pns()));
+    let served: Emulator = emulator.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let (emulator, mut stream) = (served.clone(), stream.unwrap());
+            thread::spawn(move || emulator.serve(&mut stream, |_| {}));
+        }
+    });
+
+    // The first client stays connected while the second sends
+    let timeout: Duration = Duration::from_secs(2);
+    let mut first: PnsTransport = PnsTransport::connect(&address, timeout).unwrap();
+    let mut second: PnsTransport = PnsTransport::connect(&address, timeout).unwrap();
+    let setting = |words: &[&str]| DeviceCommand::Tiers { tiers: TierSetting::parse_list(words).unwrap() };
+    assert_eq!(setting(&["1", "on"]).execute(&mut first), Ok(12));
+    assert_eq!(setting(&["2", "blink"]).execute(&mut second), Ok(12));
+    assert_eq!(modes(&emulator), [TierMode::On, TierMode::Blink, TierMode::Off, TierMode::Off, TierMode::Off]);
+}
@@ -0,0 +1,44 @@
+// exec runs Unix programs, `sleep` and `sh`
+#![cfg(unix)]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+const EXIT_CODE_TIMEOUT: i32 = 124;
+const EXIT_CODE_NOT_STARTED: i32 = 127;
+const SIGKILL: i32 = 9;
+
+// Run `exec` under --dry-run, with an empty config so the host's doesn't count
+fn exec(args: &[&str]) -> Output {
+    let config: PathBuf = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("exec-config.toml");
+    fs::write(&config, "").expect("Failed to write the config");
+    Command::new(env!("CARGO_BIN_EXE_patlite-rs"))
+        .arg("--config")
+        .arg(&config)
+        .arg("--dry-run")
+        .args(args)
+        .output()
+        .expect("Failed to run patlite-rs")
+}
+
+#[test]
+fn timed_out_child_exits_124() {
+    let output: Output = exec(&["exec", "--timeout", "1", "--", "sleep", "30"]);
+    assert_eq!(output.status.code(), Some(EXIT_CODE_TIMEOUT));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("sleep timed out"));
+}
+
+#[test]
+fn missing_program_exits_127_without_touching_stdout() {
+    let output: Output = exec(&["--output", "json", "exec", "--", "/nonexistent/patlite-rs-test"]);
+    assert_eq!(output.status.code(), Some(EXIT_CODE_NOT_STARTED));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Failed to start /nonexistent/patlite-rs-test"));
+}
+
+#[test]
+fn killed_child_exits_128_plus_the_signal() {
+    let output: Output = exec(&["exec", "--", "sh", "-c", "kill -9 $$"]);
+    assert_eq!(output.status.code(), Some(128 + SIGKILL));
+}
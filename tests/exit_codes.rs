@@ -0,0 +1,71 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+const EXIT_USAGE: i32 = 2;
+const EXIT_DEVICE_NOT_FOUND: i32 = 3;
+const EXIT_CONFIG: i32 = 7;
+
+// Run the CLI with `config` written to a config file named after the test, so the host's doesn't count
+fn run(name: &str, config: &str, args: &[&str]) -> Output {
+    let path: PathBuf = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("exit-codes-{}.toml", name));
+    fs::write(&path, config).expect("Failed to write the config");
+    Command::new(env!("CARGO_BIN_EXE_patlite-rs"))
+        .arg("--config")
+        .arg(&path)
+        .args(args)
+        .output()
+        .expect("Failed to run patlite-rs")
+}
+
+// A serial number no device has, so a unit plugged into the host isn't touched
+#[test]
+fn missing_device_exits_3() {
+    let output: Output = run("missing", "", &["--device", "NO-SUCH-SERIAL", "light", "red", "on"]);
+    assert_eq!(output.status.code(), Some(EXIT_DEVICE_NOT_FOUND));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error: "));
+}
+
+#[test]
+fn invalid_argument_exits_2() {
+    let output: Output = run("invalid", "", &["--dry-run", "light", "chartreuse", "on"]);
+    assert_eq!(output.status.code(), Some(EXIT_USAGE));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown color: chartreuse"));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn json_failure_is_an_object_with_the_exit_code() {
+    let output: Output = run("json", "", &["--device", "NO-SUCH-SERIAL", "--output", "json", "light", "red", "on"]);
+    assert_eq!(output.status.code(), Some(EXIT_DEVICE_NOT_FOUND));
+    let failure: serde_json::Value = serde_json::from_slice(&output.stdout).expect("The failure is JSON");
+    let fields: Vec<&String> = failure.as_object().expect("The failure is an object").keys().collect();
+    assert_eq!(fields, ["error", "exit_code", "ok"]);
+    assert_eq!(failure["ok"], false);
+    assert_eq!(failure["exit_code"], EXIT_DEVICE_NOT_FOUND);
+    assert!(failure["error"].is_string());
+}
+
+#[test]
+fn unknown_preset_fails_as_a_config_error() {
+    let output: Output = run("preset", "[presets]\nbuild = \"red\"\n", &["--dry-run", "--output", "json", "preset", "deploy"]);
+    assert_eq!(output.status.code(), Some(EXIT_CONFIG));
+    let failure: serde_json::Value = serde_json::from_slice(&output.stdout).expect("The failure is JSON");
+    assert_eq!(failure["error"], "Unknown preset: deploy");
+    assert_eq!(failure["exit_code"], EXIT_CONFIG);
+}
+
+#[test]
+fn broken_config_stops_commands_needing_it() {
+    let output: Output = run("broken-light", "volume = \"loud\"", &["--dry-run", "light", "red", "on"]);
+    assert_eq!(output.status.code(), Some(EXIT_CONFIG));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error: Unknown volume: loud"));
+}
+
+#[test]
+fn broken_config_is_ignored_by_commands_not_needing_it() {
+    let output: Output = run("broken-models", "volume = \"loud\"", &["models"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Ignoring the config: Unknown volume: loud"));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("NE-SN-USB"));
+}
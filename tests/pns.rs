@@ -0,0 +1,129 @@
+use patlite_rs::{DeviceCommand, DeviceProfile, PnsTransport, TierSetting};
+use std::io::{self, Cursor, Read, Write};
+use std::net::TcpListener;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+// A PNS unit on a loopback port answering every packet with `answer`, or not at all without one.
+// Hands back the packets it was sent once the client disconnects
+fn stub(answer: Option<u8>) -> (String, JoinHandle<Vec<Vec<u8>>>) {
+    let listener: TcpListener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind the stub");
+    let address: String = listener.local_addr().unwrap().to_string();
+    let handle = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("Failed to accept the client");
+        let mut packets: Vec<Vec<u8>> = vec![];
+        let mut header: [u8; 6] = [0u8; 6];
+        while stream.read_exact(&mut header).is_ok() {
+            let mut packet: Vec<u8> = vec![0u8; 6 + u16::from_be_bytes([header[4], header[5]]) as usize];
+            packet[..6].copy_from_slice(&header);
+            stream.read_exact(&mut packet[6..]).expect("Failed to read the packet");
+            packets.push(packet);
+            if let Some(answer) = answer {
+                stream.write_all(&[answer]).expect("Failed to answer");
+            }
+        }
+        packets
+    });
+    (address, handle)
+}
+
+// A unit on any other stream, such as a serial port, answering from `answers`
+struct Memory {
+    answers: Cursor<Vec<u8>>,
+    sent: Vec<u8>,
+}
+
+impl Read for Memory {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.answers.read(buf)
+    }
+}
+
+impl Write for Memory {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sent.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn tiers(words: &[&str]) -> DeviceCommand {
+    DeviceCommand::Tiers { tiers: TierSetting::parse_list(words).unwrap() }
+}
+
+#[test]
+fn commands_are_sent_as_operation_control_packets() {
+    let (address, stub) = stub(Some(0x06));
+    let mut transport: PnsTransport = PnsTransport::connect(&address, TIMEOUT).expect("Failed to connect");
+    let commands: [DeviceCommand; 5] = [
+        DeviceCommand::Light { color: 1, pattern: 1 },
+        tiers(&["tier", "1", "on", "tier", "3", "blink"]),
+        DeviceCommand::Buzz { pattern: 5, repetition: 3, volume: 10 },
+        DeviceCommand::Buzz { pattern: 8, repetition: 3, volume: 10 },
+        DeviceCommand::Off,
+    ];
+    for command in &commands {
+        assert_eq!(command.execute(&mut transport), Ok(12), "{:?}", command);
+    }
+    assert_eq!(transport.clear(), Ok(6));
+    drop(transport);
+
+    let packets: Vec<Vec<u8>> = stub.join().unwrap();
+    assert_eq!(
+        packets,
+        vec![
+            b"ABS\x00\x00\x06\x01\x09\x09\x09\x09\x09".to_vec(),
+            b"ABS\x00\x00\x06\x01\x09\x02\x09\x09\x09".to_vec(),
+            // Strong attention sounds as pattern 4
+            b"ABS\x00\x00\x06\x09\x09\x09\x09\x09\x04".to_vec(),
+            // Past London Bridge the buzzer is left as it is
+            b"ABS\x00\x00\x06\x09\x09\x09\x09\x09\x09".to_vec(),
+            b"ABS\x00\x00\x06\x00\x00\x00\x00\x00\x00".to_vec(),
+            b"ABC\x00\x00\x00".to_vec(),
+        ]
+    );
+}
+
+#[test]
+fn nak_is_an_invalid_parameter() {
+    let (address, stub) = stub(Some(0x15));
+    let mut transport: PnsTransport = PnsTransport::connect(&address, TIMEOUT).expect("Failed to connect");
+    assert_eq!(DeviceCommand::Off.execute(&mut transport), Err(rusb::Error::InvalidParam));
+    assert_eq!(transport.clear(), Err(rusb::Error::InvalidParam));
+    drop(transport);
+    assert_eq!(stub.join().unwrap().len(), 2);
+}
+
+#[test]
+fn missing_answer_times_out() {
+    let (address, stub) = stub(None);
+    let mut transport: PnsTransport = PnsTransport::connect(&address, TIMEOUT).expect("Failed to connect");
+    assert_eq!(DeviceCommand::Off.execute(&mut transport), Err(rusb::Error::Timeout));
+    drop(transport);
+    assert_eq!(stub.join().unwrap().len(), 1);
+}
+
+#[test]
+fn any_stream_carries_packets() {
+    let memory = Memory { answers: Cursor::new(vec![0x06, 0x15]), sent: vec![] };
+    let mut transport: PnsTransport<Memory> = PnsTransport::new(memory, DeviceProfile::pns());
+    assert_eq!(tiers(&["green", "on"]).execute(&mut transport), Ok(12));
+    assert_eq!(DeviceCommand::Off.execute(&mut transport), Err(rusb::Error::InvalidParam));
+    // Without an answer left the stream ends
+    assert_eq!(transport.clear(), Err(rusb::Error::Io));
+}
+
+#[test]
+fn model_is_checked_before_sending() {
+    let memory = Memory { answers: Cursor::new(vec![]), sent: vec![] };
+    let quiet = DeviceProfile { buzzer: false, ..DeviceProfile::pns() };
+    let mut transport: PnsTransport<Memory> = PnsTransport::new(memory, quiet);
+    assert_eq!(
+        DeviceCommand::Buzz { pattern: 1, repetition: 1, volume: 5 }.execute(&mut transport),
+        Err(rusb::Error::NotSupported)
+    );
+}
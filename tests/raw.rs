@@ -0,0 +1,55 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+const EXIT_USAGE: i32 = 2;
+
+// Run `raw` with --dry-run and an empty config, so neither a device nor the host's config counts
+fn raw(args: &[&str]) -> Output {
+    let config: PathBuf = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("raw-config.toml");
+    fs::write(&config, "").expect("Failed to write the config");
+    Command::new(env!("CARGO_BIN_EXE_patlite-rs"))
+        .arg("--config")
+        .arg(&config)
+        .arg("--dry-run")
+        .arg("raw")
+        .args(args)
+        .output()
+        .expect("Failed to run patlite-rs")
+}
+
+#[test]
+fn dry_run_shows_the_frame_once_as_would_send() {
+    let output: Output = raw(&["00", "00", "0x11", "0F", "11", "00", "00", "00"]);
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Would send: 00 00 11 0F 11 00 00 00  (control: color Red, pattern On, buzzer Continuous, repeat x1, volume keep)\n"
+    );
+}
+
+#[test]
+fn malformed_hex_is_a_usage_error() {
+    let output: Output = raw(&["00", "00", "0g", "0F", "11", "00", "00", "00"]);
+    assert_eq!(output.status.code(), Some(EXIT_USAGE));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn wrong_number_of_bytes_is_a_usage_error() {
+    assert_eq!(raw(&["00", "00", "11", "0F", "11", "00", "00"]).status.code(), Some(EXIT_USAGE));
+    assert_eq!(raw(&["00", "00", "11", "0F", "11", "00", "00", "00", "00"]).status.code(), Some(EXIT_USAGE));
+    assert_eq!(raw(&["00", "00", "11", "0F", "11", "00", "00", "100"]).status.code(), Some(EXIT_USAGE));
+}
+
+#[test]
+fn invalid_frame_needs_force() {
+    let refused: Output = raw(&["00", "00", "11", "0F", "11", "00", "00", "01"]);
+    assert_eq!(refused.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("Invalid frame, bytes 6 to 8 must be 0. Use --force to send it anyway"));
+    assert!(refused.stdout.is_empty());
+
+    let forced: Output = raw(&["--force", "00", "00", "11", "0F", "11", "00", "00", "01"]);
+    assert_eq!(forced.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&forced.stdout).starts_with("Would send: 00 00 11 0F 11 00 00 01"));
+}
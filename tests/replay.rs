@@ -0,0 +1,47 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+#[cfg(unix)]
+const EXIT_REPLAY_INCOMPLETE: i32 = 8;
+
+fn golden(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(name)
+}
+
+// Run the CLI against a golden recording, with an empty config so the host's doesn't count
+fn replay(recording: &str, args: &[&str]) -> Output {
+    let config: PathBuf = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("replay-config.toml");
+    fs::write(&config, "").expect("Failed to write the config");
+    Command::new(env!("CARGO_BIN_EXE_patlite-rs"))
+        .arg("--config")
+        .arg(&config)
+        .arg("--replay")
+        .arg(golden(recording))
+        .args(args)
+        .output()
+        .expect("Failed to run patlite-rs")
+}
+
+// exec runs `true`, which only Unix has
+#[cfg(unix)]
+#[test]
+fn matching_run_passes() {
+    let output: Output = replay("exec-success.ndjson", &["exec", "--", "true"]);
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn different_frame_fails() {
+    let output: Output = replay("exec-success.ndjson", &["light", "red", "on"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Replay mismatch"));
+}
+
+#[cfg(unix)]
+#[test]
+fn dropped_frames_fail_with_their_count() {
+    let output: Output = replay("exec-then-off.ndjson", &["exec", "--", "true"]);
+    assert_eq!(output.status.code(), Some(EXIT_REPLAY_INCOMPLETE));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 recorded frames weren't sent or received"));
+}
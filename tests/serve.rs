@@ -0,0 +1,219 @@
+#![cfg(feature = "remote")]
+
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Child, ChildStderr, Command, Output, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+const TOKEN: &str = "serve-test-token";
+
+// A `serve --simulate` on a free loopback port, stopped when dropped
+struct Daemon {
+    child: Child,
+    address: String,
+    config: PathBuf,
+}
+
+impl Daemon {
+    // Start the daemon with `config`, named after the test so each has its own
+    fn start(name: &str, config: &str) -> Daemon {
+        let dir: PathBuf = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("serve-{}", name));
+        fs::create_dir_all(&dir).expect("Failed to create the test directory");
+        let config_path: PathBuf = dir.join("config.toml");
+        fs::write(&config_path, config).expect("Failed to write the config");
+        let mut child: Child = Command::new(env!("CARGO_BIN_EXE_patlite-rs"))
+            .arg("--config")
+            .arg(&config_path)
+            .args(["--token", TOKEN, "serve", "--simulate", "--listen", "127.0.0.1:0"])
+            .env("XDG_CONFIG_HOME", &dir)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to run patlite-rs");
+        let address: String = serving_address(child.stderr.take().expect("Stderr is piped"));
+        Daemon { child, address, config: config_path }
+    }
+
+    // Run the CLI against the daemon with --remote
+    fn remote(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_patlite-rs"))
+            .arg("--config")
+            .arg(&self.config)
+            .args(["--remote", &self.address, "--token", TOKEN])
+            .args(args)
+            .output()
+            .expect("Failed to run patlite-rs")
+    }
+
+    // Send a bare HTTP/1.0 request, so the body isn't chunked and ends with the connection
+    fn request(&self, method: &str, target: &str, token: Option<&str>, body: Option<&str>) -> TcpStream {
+        let mut stream: TcpStream = TcpStream::connect(&self.address).expect("Failed to connect to the daemon");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).expect("Failed to set the timeout");
+        let mut head: String = format!("{} {} HTTP/1.0\r\nHost: {}\r\n", method, target, self.address);
+        if let Some(token) = token {
+            head.push_str(&format!("Authorization: Bearer {}\r\n", token));
+        }
+        if let Some(body) = body {
+            head.push_str(&format!("Content-Type: application/json\r\nContent-Length: {}\r\n", body.len()));
+        }
+        head.push_str("\r\n");
+        head.push_str(body.unwrap_or_default());
+        stream.write_all(head.as_bytes()).expect("Failed to send the request");
+        stream
+    }
+
+    // The status and body of a GET
+    fn get(&self, target: &str, token: Option<&str>) -> (u16, String) {
+        let mut response: String = String::new();
+        self.request("GET", target, token, None)
+            .read_to_string(&mut response)
+            .expect("Failed to read the response");
+        let status: u16 = response[9..12].parse().expect("The response starts with a status line");
+        let body: &str = response.split_once("\r\n\r\n").map_or("", |(_, body)| body);
+        (status, body.to_string())
+    }
+
+    fn state(&self) -> serde_json::Value {
+        let (status, body) = self.get("/v1/state", Some(TOKEN));
+        assert_eq!(status, 200, "{}", body);
+        serde_json::from_str(&body).expect("The state is JSON")
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// The address from the daemon's "Serving on" line. The rest of stderr, the audit log among it,
+// is read on a thread of its own so the daemon never blocks writing it
+fn serving_address(stderr: ChildStderr) -> String {
+    let (found, address) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            if let Some(address) = line.strip_prefix("Serving on http://") {
+                let _ = found.send(address.to_string());
+            }
+        }
+    });
+    address.recv_timeout(Duration::from_secs(30)).expect("The daemon didn't start serving")
+}
+
+// The next `data:` of a stream of server-sent events, skipping the event names and keep-alives
+fn next_event(events: &mut impl BufRead) -> serde_json::Value {
+    let mut line: String = String::new();
+    loop {
+        line.clear();
+        assert!(events.read_line(&mut line).expect("Failed to read an event") > 0, "The events ended");
+        if let Some(data) = line.strip_prefix("data:") {
+            return serde_json::from_str(data.trim()).expect("Events are JSON");
+        }
+    }
+}
+
+// The payload of the next WebSocket frame, which the server sends unmasked
+fn next_message(socket: &mut impl Read) -> serde_json::Value {
+    let mut head: [u8; 2] = [0; 2];
+    socket.read_exact(&mut head).expect("Failed to read a frame");
+    assert_eq!(head[0], 0x81, "Expected a final text frame");
+    let len: usize = match head[1] {
+        126 => {
+            let mut len: [u8; 2] = [0; 2];
+            socket.read_exact(&mut len).expect("Failed to read the length");
+            u16::from_be_bytes(len).into()
+        }
+        len => len.into(),
+    };
+    let mut payload: Vec<u8> = vec![0; len];
+    socket.read_exact(&mut payload).expect("Failed to read the payload");
+    serde_json::from_slice(&payload).expect("Messages are JSON")
+}
+
+#[test]
+fn requests_without_a_valid_token_are_refused() {
+    let daemon: Daemon = Daemon::start("tokens", "");
+    assert_eq!(daemon.get("/v1/state", None).0, 401);
+    assert_eq!(daemon.get("/v1/state", Some("wrong")).0, 401);
+    assert_eq!(daemon.get("/v1/state?token=wrong", None).0, 401);
+    assert_eq!(daemon.get("/v1/profile", None).0, 401);
+
+    let mut response: String = String::new();
+    daemon
+        .request("POST", "/v1/commands", Some("wrong"), Some("{\"command\": \"off\"}"))
+        .read_to_string(&mut response)
+        .expect("Failed to read the response");
+    assert!(response.starts_with("HTTP/1.0 401"), "{}", response);
+}
+
+#[test]
+fn query_tokens_are_percent_decoded() {
+    let daemon: Daemon = Daemon::start("query-token", "[tokens.web]\ntoken = \"a+b/c=d%e\"\nscopes = [\"read-state\"]\n");
+    assert_eq!(daemon.get("/v1/state?token=a%2Bb%2Fc%3Dd%25e", None).0, 200);
+    assert_eq!(daemon.get("/v1/state", Some("a+b/c=d%e")).0, 200);
+    // A raw `+` is a space in a query
+    assert_eq!(daemon.get("/v1/state?token=a+b/c%3Dd%25e", None).0, 401);
+}
+
+#[test]
+fn remote_commands_change_the_state_served() {
+    let daemon: Daemon = Daemon::start("remote", "");
+    assert_eq!(daemon.state()["color"], 0);
+
+    let lit: Output = daemon.remote(&["light", "red", "blink"]);
+    assert_eq!(lit.status.code(), Some(0), "{}", String::from_utf8_lossy(&lit.stderr));
+    let state: serde_json::Value = daemon.state();
+    assert_eq!((&state["color"], &state["pattern"]), (&serde_json::json!(1), &serde_json::json!(2)));
+
+    // The state subcommand asks the daemon for it over /v1/state
+    let shown: Output = daemon.remote(&["--output", "json", "state"]);
+    assert_eq!(shown.status.code(), Some(0), "{}", String::from_utf8_lossy(&shown.stderr));
+    let shown: serde_json::Value = serde_json::from_slice(&shown.stdout).expect("The state is JSON");
+    assert_eq!(shown, state);
+}
+
+#[test]
+fn events_follow_commands() {
+    let daemon: Daemon = Daemon::start("events", "");
+    let mut events = BufReader::new(daemon.request("GET", &format!("/v1/events?token={}", TOKEN), None, None));
+    let mut status: String = String::new();
+    events.read_line(&mut status).expect("Failed to read the status");
+    assert!(status.starts_with("HTTP/1.0 200"), "{}", status);
+    assert_eq!(next_event(&mut events)["color"], 0);
+
+    let lit: Output = daemon.remote(&["light", "green", "on"]);
+    assert_eq!(lit.status.code(), Some(0), "{}", String::from_utf8_lossy(&lit.stderr));
+    let event: serde_json::Value = next_event(&mut events);
+    assert_eq!((&event["color"], &event["pattern"]), (&serde_json::json!(2), &serde_json::json!(1)));
+}
+
+#[test]
+fn websocket_follows_commands() {
+    let daemon: Daemon = Daemon::start("websocket", "");
+    let mut socket: TcpStream = TcpStream::connect(&daemon.address).expect("Failed to connect to the daemon");
+    socket.set_read_timeout(Some(Duration::from_secs(10))).expect("Failed to set the timeout");
+    let upgrade: String = format!(
+        "GET /v1/ws?token={} HTTP/1.1\r\nHost: {}\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\
+         Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+        TOKEN, daemon.address
+    );
+    socket.write_all(upgrade.as_bytes()).expect("Failed to send the upgrade");
+    let mut head: Vec<u8> = vec![];
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte: [u8; 1] = [0];
+        socket.read_exact(&mut byte).expect("Failed to read the upgrade");
+        head.push(byte[0]);
+    }
+    assert!(head.starts_with(b"HTTP/1.1 101"), "{}", String::from_utf8_lossy(&head));
+    assert_eq!(next_message(&mut socket)["color"], 0);
+
+    let lit: Output = daemon.remote(&["buzz", "sweep", "3"]);
+    assert_eq!(lit.status.code(), Some(0), "{}", String::from_utf8_lossy(&lit.stderr));
+    let message: serde_json::Value = next_message(&mut socket);
+    assert_eq!((&message["buzzer_pattern"], &message["repetition"]), (&serde_json::json!(2), &serde_json::json!(3)));
+}
@@ -0,0 +1,81 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn golden(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(name)
+}
+
+// Pipe `input` through `stream` against a golden recording, returning the result lines
+fn stream(recording: &str, input: &str) -> (Output, Vec<serde_json::Value>) {
+    let config: PathBuf = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("stream-config.toml");
+    fs::write(&config, "").expect("Failed to write the config");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_patlite-rs"))
+        .arg("--config")
+        .arg(&config)
+        .arg("--replay")
+        .arg(golden(recording))
+        .arg("stream")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run patlite-rs");
+    child.stdin.take().unwrap().write_all(input.as_bytes()).expect("Failed to write to stream");
+    let output: Output = child.wait_with_output().expect("Failed to wait for patlite-rs");
+    let results: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("Result lines are JSON"))
+        .collect();
+    (output, results)
+}
+
+#[test]
+fn commands_answer_with_their_id() {
+    let (output, results) = stream("stream-light.ndjson", "{\"id\": 1, \"command\": \"light\", \"color\": 1, \"pattern\": 1}\n\n");
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(results, [serde_json::json!({"id": 1, "ok": true, "command": "light", "attempts": 1})]);
+}
+
+#[test]
+fn id_is_echoed_when_the_command_fails_to_parse() {
+    let (_, results) = stream("stream-light.ndjson", "{\"id\": \"a\", \"command\": \"nope\"}\n{\"id\": [2], \"command\": \"light\", \"color\": \"red\"}\n");
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0]["id"], "a");
+    assert_eq!(results[1]["id"], serde_json::json!([2]));
+    for result in &results {
+        assert_eq!(result["ok"], false);
+        assert!(result["error"].as_str().unwrap().starts_with("Invalid command: "));
+    }
+}
+
+#[test]
+fn line_that_is_not_json_answers_without_an_id() {
+    let (_, results) = stream("stream-light.ndjson", "not json\n");
+    assert_eq!(results.len(), 1);
+    assert!(results[0].get("id").is_none());
+    assert_eq!(results[0]["ok"], false);
+}
+
+#[test]
+fn invalid_command_answers_with_the_error_and_sends_nothing() {
+    let (output, results) = stream(
+        "stream-light.ndjson",
+        "{\"id\": 7, \"command\": \"light\", \"color\": 99, \"pattern\": 1}\n{\"command\": \"light\", \"color\": 1, \"pattern\": 1}\n",
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(results[0], serde_json::json!({"id": 7, "ok": false, "command": "light", "error": "color 99 is out of range 0-15"}));
+    assert_eq!(results[1]["ok"], true);
+}
+
+#[test]
+fn state_request_answers_without_sending() {
+    let (output, results) = stream("stream-light.ndjson", "{\"id\": 1, \"command\": \"light\", \"color\": 1, \"pattern\": 1}\n{\"id\": \"s\", \"command\": \"state\"}\n");
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[1]["id"], "s");
+    assert_eq!(results[1]["command"], "state");
+    assert_eq!(results[1]["ok"], false);
+    assert_eq!(results[1]["error"], "The state of this device isn't tracked");
+}